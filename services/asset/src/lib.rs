@@ -9,11 +9,16 @@ use derive_more::Display;
 
 use binding_macro::{cycles, genesis, service, write};
 use protocol::traits::{ExecutorParams, ServiceResponse, ServiceSDK, StoreMap};
-use protocol::types::{Hash, ServiceContext};
+use protocol::types::{Address, Hash, ServiceContext};
 
 use crate::types::{
-    Asset, Balance, CreateAssetPayload, GetAssetPayload, GetBalancePayload, GetBalanceResponse,
-    InitGenesisPayload, ModifyBalancePayload, TransferEvent, TransferPayload,
+    subaccount_key, Asset, Balance, BalanceChangeEvent, CreateAssetPayload, FaucetPayload,
+    FreezePayload, GetAssetPayload, GetBalancePayload, GetBalanceResponse, GetHolderCountPayload,
+    GetHolderCountResponse, GetSubAccountBalancePayload, GetSubAccountBalanceResponse,
+    InitGenesisPayload, IsAuthorizedPayload, IsAuthorizedResponse, MintPayload,
+    ModifyBalancePayload, SetDexTradablePayload, SetWrapPairPayload, SettleLockedPayload,
+    SubAccountEvent, SubAccountPayload, TransferEvent, TransferIssuerPayload, TransferPayload,
+    WrapPayload, BPS_DENOMINATOR,
 };
 
 /*
@@ -49,15 +54,47 @@ macro_rules! serde_json_string {
     };
 }
 
-const ADMISSION_TOKEN: Bytes = Bytes::from_static(b"dex_token");
+// admission tokens are scoped to a capability, LOCK_TOKEN admits lock and
+// unlock, BALANCE_TOKEN admits add_value and sub_value
+const LOCK_TOKEN: Bytes = Bytes::from_static(b"dex_lock_token");
+const BALANCE_TOKEN: Bytes = Bytes::from_static(b"dex_balance_token");
 const ASSETS_KEY: &str = "assets";
+const LOCKED_TOTALS_KEY: &str = "locked_totals";
+const FAUCET_ENABLED_KEY: &str = "faucet_enabled";
+const FAUCET_COOLDOWN_KEY: &str = "faucet_cooldown";
+const FAUCET_ASSET_KEY: &str = "faucet_asset";
+const FAUCET_CLAIMS_KEY: &str = "faucet_claims";
+const HOLDER_COUNTS_KEY: &str = "holder_counts";
+const WRAP_PAIRS_KEY: &str = "wrap_pairs";
 
 pub struct AssetService<SDK> {
     sdk: SDK,
     assets: Box<dyn StoreMap<Hash, Asset>>,
+    // sum of every account's locked balance, per asset
+    locked_totals: Box<dyn StoreMap<Hash, u64>>,
+    // the height of each address's last faucet claim
+    faucet_claims: Box<dyn StoreMap<Address, u64>>,
+    // number of accounts holding each asset, see Balance::is_empty
+    holder_counts: Box<dyn StoreMap<Hash, u64>>,
+    // the wrapped asset pegged to each asset
+    wrap_pairs: Box<dyn StoreMap<Hash, Hash>>,
 }
 
 pub trait AssetFacade {
+    fn query_asset(&self, ctx: ServiceContext, payload: GetAssetPayload) -> ServiceResponse<Asset>;
+
+    fn query_balance(
+        &self,
+        ctx: ServiceContext,
+        payload: GetBalancePayload,
+    ) -> ServiceResponse<Balance>;
+
+    fn query_locked_total(
+        &self,
+        ctx: ServiceContext,
+        payload: GetAssetPayload,
+    ) -> ServiceResponse<u64>;
+
     fn lock(&mut self, ctx: ServiceContext, payload: ModifyBalancePayload) -> ServiceResponse<()>;
 
     fn unlock(&mut self, ctx: ServiceContext, payload: ModifyBalancePayload)
@@ -74,21 +111,59 @@ pub trait AssetFacade {
         ctx: ServiceContext,
         payload: ModifyBalancePayload,
     ) -> ServiceResponse<()>;
+
+    fn settle_locked(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SettleLockedPayload,
+    ) -> ServiceResponse<()>;
 }
 
 // this is for other service
 impl<SDK: ServiceSDK> AssetFacade for AssetService<SDK> {
+    fn query_asset(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetAssetPayload,
+    ) -> ServiceResponse<Asset> {
+        match self.assets.get(&payload.id) {
+            Some(asset) => ServiceResponse::from_succeed(asset),
+            None => AssetError::AssetNotExist { id: payload.id }.into(),
+        }
+    }
+
+    fn query_balance(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetBalancePayload,
+    ) -> ServiceResponse<Balance> {
+        let balance = self
+            .sdk
+            .get_account_value(&payload.user, &payload.asset_id)
+            .unwrap_or(Balance::default());
+        ServiceResponse::from_succeed(balance)
+    }
+
+    fn query_locked_total(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetAssetPayload,
+    ) -> ServiceResponse<u64> {
+        ServiceResponse::from_succeed(self.locked_totals.get(&payload.id).unwrap_or(0))
+    }
+
     fn add_value(
         &mut self,
         ctx: ServiceContext,
         payload: ModifyBalancePayload,
     ) -> ServiceResponse<()> {
         let extra = ctx.get_extra().expect("Caller should have admission token");
-        if extra != ADMISSION_TOKEN {
+        if extra != BALANCE_TOKEN {
             return AssetError::PermissionDenial.into();
         }
 
-        self._add_value(&payload)
+        call_and_parse_service_response!(self, _add_value, &payload);
+        self.emit_balance_change(&ctx, payload, true)
     }
 
     fn sub_value(
@@ -97,16 +172,89 @@ impl<SDK: ServiceSDK> AssetFacade for AssetService<SDK> {
         payload: ModifyBalancePayload,
     ) -> ServiceResponse<()> {
         let extra = ctx.get_extra().expect("Caller should have admission token");
-        if extra != ADMISSION_TOKEN {
+        if extra != BALANCE_TOKEN {
+            return AssetError::PermissionDenial.into();
+        }
+
+        call_and_parse_service_response!(self, _sub_value, &payload);
+        self.emit_balance_change(&ctx, payload, false)
+    }
+
+    // admitted like add_value and sub_value, since it hands value to another
+    // account. emits the BalanceChange events of the sub and the add
+    fn settle_locked(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SettleLockedPayload,
+    ) -> ServiceResponse<()> {
+        let extra = ctx.get_extra().expect("Caller should have admission token");
+        if extra != BALANCE_TOKEN {
             return AssetError::PermissionDenial.into();
         }
 
-        self._sub_value(&payload)
+        if !self.assets.contains(&payload.asset_id) {
+            return AssetError::AssetNotExist {
+                id: payload.asset_id.clone(),
+            }
+            .into();
+        }
+
+        let from_before = self.balance_of(&payload.from, &payload.asset_id);
+        if from_before.locked < payload.value {
+            return AssetError::InsufficientBalance {
+                wanted: payload.value,
+                had: from_before.locked,
+            }
+            .into();
+        }
+        let mut from_after = from_before.clone();
+        from_after.locked -= payload.value;
+
+        // paying oneself is an unlock
+        let to_before = if payload.to == payload.from {
+            from_after.clone()
+        } else {
+            self.balance_of(&payload.to, &payload.asset_id)
+        };
+        let mut to_after = to_before.clone();
+        to_after.current = match to_after.current.checked_add(payload.value) {
+            Some(current) => current,
+            None => return AssetError::U64Overflow.into(),
+        };
+
+        let locked_total = self.locked_totals.get(&payload.asset_id).unwrap_or(0);
+        self.locked_totals.insert(
+            payload.asset_id.clone(),
+            locked_total.saturating_sub(payload.value),
+        );
+        if payload.to == payload.from {
+            self.sdk
+                .set_account_value(&payload.to, payload.asset_id.clone(), to_after);
+        } else {
+            self.update_holders(&payload.asset_id, &from_before, &from_after);
+            self.update_holders(&payload.asset_id, &to_before, &to_after);
+            self.sdk
+                .set_account_value(&payload.from, payload.asset_id.clone(), from_after);
+            self.sdk
+                .set_account_value(&payload.to, payload.asset_id.clone(), to_after);
+        }
+
+        let change = |user: Address| ModifyBalancePayload {
+            asset_id: payload.asset_id.clone(),
+            user,
+            value: payload.value,
+            correlation_id: payload.correlation_id.clone(),
+        };
+        let sub_res = self.emit_balance_change(&ctx, change(payload.from.clone()), false);
+        if sub_res.is_error() {
+            return sub_res;
+        }
+        self.emit_balance_change(&ctx, change(payload.to.clone()), true)
     }
 
     fn lock(&mut self, ctx: ServiceContext, payload: ModifyBalancePayload) -> ServiceResponse<()> {
         let extra = ctx.get_extra().expect("Caller should have admission token");
-        if extra != ADMISSION_TOKEN {
+        if extra != LOCK_TOKEN {
             return AssetError::PermissionDenial.into();
         }
 
@@ -137,6 +285,16 @@ impl<SDK: ServiceSDK> AssetFacade for AssetService<SDK> {
         }
 
         balance.locked = result;
+        let locked_total = match self
+            .locked_totals
+            .get(&payload.asset_id)
+            .unwrap_or(0)
+            .checked_add(payload.value)
+        {
+            Some(locked_total) => locked_total,
+            None => return AssetError::U64Overflow.into(),
+        };
+        self.locked_totals.insert(payload.asset_id.clone(), locked_total);
         self.sdk
             .set_account_value(&payload.user, payload.asset_id, balance);
         ServiceResponse::from_succeed(())
@@ -148,7 +306,7 @@ impl<SDK: ServiceSDK> AssetFacade for AssetService<SDK> {
         payload: ModifyBalancePayload,
     ) -> ServiceResponse<()> {
         let extra = ctx.get_extra().expect("Caller should have admission token");
-        if extra != ADMISSION_TOKEN {
+        if extra != LOCK_TOKEN {
             return AssetError::PermissionDenial.into();
         }
 
@@ -178,6 +336,12 @@ impl<SDK: ServiceSDK> AssetFacade for AssetService<SDK> {
         }
 
         balance.current = result;
+        // locks taken before totals were tracked aren't in the total
+        let locked_total = self.locked_totals.get(&payload.asset_id).unwrap_or(0);
+        self.locked_totals.insert(
+            payload.asset_id.clone(),
+            locked_total.saturating_sub(payload.value),
+        );
         self.sdk
             .set_account_value(&payload.user, payload.asset_id, balance);
         ServiceResponse::from_succeed(())
@@ -190,8 +354,22 @@ impl<SDK: ServiceSDK> AssetFacade for AssetService<SDK> {
 impl<SDK: ServiceSDK> AssetService<SDK> {
     pub fn new(mut sdk: SDK) -> Self {
         let assets: Box<dyn StoreMap<Hash, Asset>> = sdk.alloc_or_recover_map(ASSETS_KEY);
+        let locked_totals: Box<dyn StoreMap<Hash, u64>> =
+            sdk.alloc_or_recover_map(LOCKED_TOTALS_KEY);
+        let faucet_claims: Box<dyn StoreMap<Address, u64>> =
+            sdk.alloc_or_recover_map(FAUCET_CLAIMS_KEY);
+        let holder_counts: Box<dyn StoreMap<Hash, u64>> =
+            sdk.alloc_or_recover_map(HOLDER_COUNTS_KEY);
+        let wrap_pairs: Box<dyn StoreMap<Hash, Hash>> = sdk.alloc_or_recover_map(WRAP_PAIRS_KEY);
 
-        Self { sdk, assets }
+        Self {
+            sdk,
+            assets,
+            locked_totals,
+            faucet_claims,
+            holder_counts,
+            wrap_pairs,
+        }
     }
 
     #[genesis]
@@ -202,14 +380,27 @@ impl<SDK: ServiceSDK> AssetService<SDK> {
             symbol: payload.symbol,
             supply: payload.supply,
             issuer: payload.issuer.clone(),
+            decimals: payload.decimals,
+            frozen: false,
+            max_supply: payload.max_supply,
+            transfer_fee_bps: 0,
+            dex_tradable: true,
         };
+        assert!(!asset.exceeds_max_supply(asset.supply));
 
         self.assets.insert(asset.id.clone(), asset.clone());
+        self.sdk
+            .set_value(FAUCET_ENABLED_KEY.to_owned(), payload.faucet_enabled);
+        self.sdk
+            .set_value(FAUCET_COOLDOWN_KEY.to_owned(), payload.faucet_cooldown);
+        self.sdk
+            .set_value(FAUCET_ASSET_KEY.to_owned(), asset.id.clone());
 
         let balance = Balance {
             current: payload.supply,
             locked: 0,
         };
+        self.update_holders(&asset.id, &Balance::default(), &balance);
 
         self.sdk.set_account_value(&asset.issuer, asset.id, balance)
     }
@@ -235,13 +426,28 @@ impl<SDK: ServiceSDK> AssetService<SDK> {
             symbol: payload.symbol,
             supply: payload.supply,
             issuer: caller.clone(),
+            decimals: payload.decimals,
+            frozen: false,
+            max_supply: payload.max_supply,
+            transfer_fee_bps: payload.transfer_fee_bps,
+            dex_tradable: true,
         };
+        if asset.transfer_fee_bps > BPS_DENOMINATOR {
+            return AssetError::IllegalTransferFee.into();
+        }
+        if asset.exceeds_max_supply(asset.supply) {
+            return AssetError::MaxSupplyExceeded {
+                max_supply: asset.max_supply,
+            }
+            .into();
+        }
         self.assets.insert(id.clone(), asset.clone());
 
         let balance = Balance {
             current: payload.supply,
             locked: 0,
         };
+        self.update_holders(&id, &Balance::default(), &balance);
         self.sdk.set_account_value(&caller, id, balance);
 
         let event_string = serde_json_string!(asset);
@@ -276,34 +482,659 @@ impl<SDK: ServiceSDK> AssetService<SDK> {
         })
     }
 
+    #[cycles(100_00)]
+    #[read]
+    fn get_holder_count(
+        &self,
+        ctx: ServiceContext,
+        payload: GetHolderCountPayload,
+    ) -> ServiceResponse<GetHolderCountResponse> {
+        if !self.assets.contains(&payload.asset_id) {
+            return AssetError::AssetNotExist {
+                id: payload.asset_id,
+            }
+            .into();
+        }
+
+        ServiceResponse::from_succeed(GetHolderCountResponse {
+            holders: self.holder_counts.get(&payload.asset_id).unwrap_or(0),
+            asset_id: payload.asset_id,
+        })
+    }
+
+    #[cycles(100_00)]
+    #[read]
+    fn is_authorized(
+        &self,
+        ctx: ServiceContext,
+        payload: IsAuthorizedPayload,
+    ) -> ServiceResponse<IsAuthorizedResponse> {
+        let token = Bytes::from(payload.token_or_address);
+        let lock = token == LOCK_TOKEN;
+        let balance = token == BALANCE_TOKEN;
+        ServiceResponse::from_succeed(IsAuthorizedResponse {
+            authorized: lock || balance,
+            lock,
+            balance,
+        })
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn mint(&mut self, ctx: ServiceContext, payload: MintPayload) -> ServiceResponse<()> {
+        let res = self.get_issued_asset(&ctx, &payload.asset_id);
+        if res.is_error() {
+            return ServiceResponse::from_error(res.code, res.error_message);
+        }
+        let asset = res.succeed_data;
+
+        let res = self.issue(asset, &payload.to, payload.value);
+        if res.is_error() {
+            return res;
+        }
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("Mint".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    // testnet top-up of the genesis asset, minted to the caller at most once
+    // per cooldown. assets created later are never minted by it
+    #[cycles(210_00)]
+    #[write]
+    fn faucet(&mut self, ctx: ServiceContext, payload: FaucetPayload) -> ServiceResponse<()> {
+        let enabled: bool = self
+            .sdk
+            .get_value(&FAUCET_ENABLED_KEY.to_owned())
+            .unwrap_or(false);
+        let faucet_asset: Option<Hash> = self.sdk.get_value(&FAUCET_ASSET_KEY.to_owned());
+        if !enabled || faucet_asset.as_ref() != Some(&payload.asset_id) {
+            return AssetError::FaucetDisabled.into();
+        }
+
+        let asset = match self.assets.get(&payload.asset_id) {
+            Some(asset) => asset,
+            None => {
+                return AssetError::AssetNotExist {
+                    id: payload.asset_id,
+                }
+                .into()
+            }
+        };
+        if asset.frozen {
+            return AssetError::AssetFrozen {
+                id: payload.asset_id,
+            }
+            .into();
+        }
+
+        let caller = ctx.get_caller();
+        let height = ctx.get_current_height();
+        if let Some(last_claim) = self.faucet_claims.get(&caller) {
+            let cooldown: u64 = self
+                .sdk
+                .get_value(&FAUCET_COOLDOWN_KEY.to_owned())
+                .unwrap_or(0);
+            if height < last_claim.saturating_add(cooldown) {
+                return AssetError::FaucetCooldown {
+                    next_height: last_claim.saturating_add(cooldown),
+                }
+                .into();
+            }
+        }
+
+        let res = self.issue(asset, &caller, payload.value);
+        if res.is_error() {
+            return res;
+        }
+        self.faucet_claims.insert(caller, height);
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("Faucet".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn transfer_issuer(
+        &mut self,
+        ctx: ServiceContext,
+        payload: TransferIssuerPayload,
+    ) -> ServiceResponse<()> {
+        let res = self.get_issued_asset(&ctx, &payload.asset_id);
+        if res.is_error() {
+            return ServiceResponse::from_error(res.code, res.error_message);
+        }
+        let mut asset = res.succeed_data;
+
+        asset.issuer = payload.new_issuer.clone();
+        self.assets.insert(payload.asset_id.clone(), asset);
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("TransferIssuer".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn freeze(&mut self, ctx: ServiceContext, payload: FreezePayload) -> ServiceResponse<()> {
+        let res = self.get_issued_asset(&ctx, &payload.asset_id);
+        if res.is_error() {
+            return ServiceResponse::from_error(res.code, res.error_message);
+        }
+        let mut asset = res.succeed_data;
+
+        asset.frozen = payload.frozen;
+        self.assets.insert(payload.asset_id, asset);
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("Freeze".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn set_dex_tradable(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SetDexTradablePayload,
+    ) -> ServiceResponse<()> {
+        let res = self.get_issued_asset(&ctx, &payload.asset_id);
+        if res.is_error() {
+            return ServiceResponse::from_error(res.code, res.error_message);
+        }
+        let mut asset = res.succeed_data;
+
+        asset.dex_tradable = payload.tradable;
+        self.assets.insert(payload.asset_id.clone(), asset);
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("SetDexTradable".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn set_wrap_pair(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SetWrapPairPayload,
+    ) -> ServiceResponse<()> {
+        if payload.asset_id == payload.wrapped_id {
+            return AssetError::IllegalWrapPair.into();
+        }
+        let res = self.get_issued_asset(&ctx, &payload.asset_id);
+        if res.is_error() {
+            return ServiceResponse::from_error(res.code, res.error_message);
+        }
+        let res = self.get_issued_asset(&ctx, &payload.wrapped_id);
+        if res.is_error() {
+            return ServiceResponse::from_error(res.code, res.error_message);
+        }
+
+        self.wrap_pairs
+            .insert(payload.asset_id.clone(), payload.wrapped_id.clone());
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("SetWrapPair".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn wrap(&mut self, ctx: ServiceContext, payload: WrapPayload) -> ServiceResponse<()> {
+        let res = self.convert(
+            &ctx.get_caller(),
+            &payload.asset_id,
+            &payload.wrapped_id,
+            &payload,
+        );
+        if res.is_error() {
+            return res;
+        }
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("Wrap".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn unwrap(&mut self, ctx: ServiceContext, payload: WrapPayload) -> ServiceResponse<()> {
+        let res = self.convert(
+            &ctx.get_caller(),
+            &payload.wrapped_id,
+            &payload.asset_id,
+            &payload,
+        );
+        if res.is_error() {
+            return res;
+        }
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("Unwrap".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
     #[cycles(210_00)]
     #[write]
     fn transfer(&mut self, ctx: ServiceContext, payload: TransferPayload) -> ServiceResponse<()> {
+        let asset = self.assets.get(&payload.asset_id).unwrap_or_default();
+        if asset.frozen {
+            return AssetError::AssetFrozen {
+                id: payload.asset_id,
+            }
+            .into();
+        }
+
+        if payload.from_subaccount.is_empty() {
+            let sub_payload = ModifyBalancePayload {
+                asset_id: payload.asset_id.clone(),
+                user: ctx.get_caller(),
+                value: payload.value,
+                correlation_id: String::new(),
+            };
+            call_and_parse_service_response!(self, _sub_value, &sub_payload);
+        } else {
+            let sub_res = self.sub_subaccount(
+                &ctx.get_caller(),
+                &payload.asset_id,
+                &payload.from_subaccount,
+                payload.value,
+            );
+            if sub_res.is_error() {
+                return sub_res;
+            }
+        }
+
+        let fee = asset.transfer_fee(payload.value);
+        let add_payload = ModifyBalancePayload {
+            asset_id: payload.asset_id.clone(),
+            user: payload.to.clone(),
+            value: payload.value - fee,
+            correlation_id: String::new(),
+        };
+        call_and_parse_service_response!(self, _add_value, &add_payload);
+        if fee != 0 {
+            let fee_payload = ModifyBalancePayload {
+                asset_id: payload.asset_id.clone(),
+                user: asset.issuer.clone(),
+                value: fee,
+                correlation_id: String::new(),
+            };
+            call_and_parse_service_response!(self, _add_value, &fee_payload);
+        }
+
+        let event = TransferEvent {
+            asset_id: payload.asset_id,
+            from: ctx.get_caller(),
+            to: payload.to,
+            value: payload.value,
+            decimals: asset.decimals,
+            fee,
+        };
+        let event_json = serde_json_string!(event);
+        ctx.emit_event("TransferAsset".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    // like transfer, but checks the caller can cover the whole value up front
+    // and that the balances moved by exactly the value, the issuer's transfer
+    // fee included, before committing
+    #[cycles(210_00)]
+    #[write]
+    fn transfer_exact(
+        &mut self,
+        ctx: ServiceContext,
+        payload: TransferPayload,
+    ) -> ServiceResponse<()> {
+        let asset = self.assets.get(&payload.asset_id).unwrap_or_default();
+        if asset.frozen {
+            return AssetError::AssetFrozen {
+                id: payload.asset_id,
+            }
+            .into();
+        }
+
+        if !payload.from_subaccount.is_empty() {
+            return AssetError::IllegalSubAccount.into();
+        }
+
+        let caller = ctx.get_caller();
+        let sender_before = self.balance_of(&caller, &payload.asset_id);
+        let recipient_before = self.balance_of(&payload.to, &payload.asset_id);
+        let issuer_before = self.balance_of(&asset.issuer, &payload.asset_id);
+        if sender_before.current < payload.value {
+            return AssetError::InsufficientBalance {
+                wanted: payload.value,
+                had: sender_before.current,
+            }
+            .into();
+        }
+
         let sub_payload = ModifyBalancePayload {
             asset_id: payload.asset_id.clone(),
-            user: ctx.get_caller(),
+            user: caller.clone(),
             value: payload.value,
+            correlation_id: String::new(),
         };
         call_and_parse_service_response!(self, _sub_value, &sub_payload);
 
+        let fee = asset.transfer_fee(payload.value);
         let add_payload = ModifyBalancePayload {
             asset_id: payload.asset_id.clone(),
             user: payload.to.clone(),
-            value: payload.value,
+            value: payload.value - fee,
+            correlation_id: String::new(),
         };
         call_and_parse_service_response!(self, _add_value, &add_payload);
+        if fee != 0 {
+            let fee_payload = ModifyBalancePayload {
+                asset_id: payload.asset_id.clone(),
+                user: asset.issuer.clone(),
+                value: fee,
+                correlation_id: String::new(),
+            };
+            call_and_parse_service_response!(self, _add_value, &fee_payload);
+        }
+
+        // any two of the three parties may be the same account
+        let expected = |user: &Address, before: u64| {
+            let mut expected = before as i128;
+            if user == &caller {
+                expected -= payload.value as i128;
+            }
+            if user == &payload.to {
+                expected += (payload.value - fee) as i128;
+            }
+            if user == &asset.issuer {
+                expected += fee as i128;
+            }
+            expected
+        };
+        let sender_after = self.balance_of(&caller, &payload.asset_id).current;
+        let recipient_after = self.balance_of(&payload.to, &payload.asset_id).current;
+        let issuer_after = self.balance_of(&asset.issuer, &payload.asset_id).current;
+        let holds = sender_after as i128 == expected(&caller, sender_before.current)
+            && recipient_after as i128 == expected(&payload.to, recipient_before.current)
+            && issuer_after as i128 == expected(&asset.issuer, issuer_before.current);
+        if !holds {
+            return AssetError::TransferInvariant {
+                sender: sender_after,
+                recipient: recipient_after,
+            }
+            .into();
+        }
 
         let event = TransferEvent {
             asset_id: payload.asset_id,
-            from: ctx.get_caller(),
+            from: caller,
             to: payload.to,
             value: payload.value,
+            decimals: asset.decimals,
+            fee,
         };
         let event_json = serde_json_string!(event);
         ctx.emit_event("TransferAsset".to_owned(), event_json);
         ServiceResponse::from_succeed(())
     }
 
+    #[cycles(210_00)]
+    #[write]
+    fn deposit_subaccount(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SubAccountPayload,
+    ) -> ServiceResponse<()> {
+        if payload.label.is_empty() {
+            return AssetError::IllegalSubAccount.into();
+        }
+
+        let caller = ctx.get_caller();
+        let key = subaccount_key(&payload.asset_id, &payload.label);
+        let mut balance = self.balance_of(&caller, &key);
+        balance.current = match balance.current.checked_add(payload.value) {
+            Some(current) => current,
+            None => return AssetError::U64Overflow.into(),
+        };
+
+        let sub_payload = ModifyBalancePayload {
+            asset_id: payload.asset_id.clone(),
+            user: caller.clone(),
+            value: payload.value,
+            correlation_id: String::new(),
+        };
+        call_and_parse_service_response!(self, _sub_value, &sub_payload);
+        self.sdk.set_account_value(&caller, key, balance);
+
+        let event = SubAccountEvent {
+            asset_id: payload.asset_id,
+            user: caller,
+            label: payload.label,
+            value: payload.value,
+        };
+        let event_json = serde_json_string!(event);
+        ctx.emit_event("DepositSubAccount".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn withdraw_subaccount(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SubAccountPayload,
+    ) -> ServiceResponse<()> {
+        let caller = ctx.get_caller();
+        let sub_res =
+            self.sub_subaccount(&caller, &payload.asset_id, &payload.label, payload.value);
+        if sub_res.is_error() {
+            return sub_res;
+        }
+
+        let add_payload = ModifyBalancePayload {
+            asset_id: payload.asset_id.clone(),
+            user: caller.clone(),
+            value: payload.value,
+            correlation_id: String::new(),
+        };
+        call_and_parse_service_response!(self, _add_value, &add_payload);
+
+        let event = SubAccountEvent {
+            asset_id: payload.asset_id,
+            user: caller,
+            label: payload.label,
+            value: payload.value,
+        };
+        let event_json = serde_json_string!(event);
+        ctx.emit_event("WithdrawSubAccount".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(100_00)]
+    #[read]
+    fn get_subaccount_balance(
+        &self,
+        ctx: ServiceContext,
+        payload: GetSubAccountBalancePayload,
+    ) -> ServiceResponse<GetSubAccountBalanceResponse> {
+        let key = subaccount_key(&payload.asset_id, &payload.label);
+        ServiceResponse::from_succeed(GetSubAccountBalanceResponse {
+            asset_id: payload.asset_id,
+            label: payload.label,
+            balance: self.balance_of(&payload.user, &key).current,
+        })
+    }
+
+    fn sub_subaccount(
+        &mut self,
+        user: &Address,
+        asset_id: &Hash,
+        label: &str,
+        value: u64,
+    ) -> ServiceResponse<()> {
+        if label.is_empty() {
+            return AssetError::IllegalSubAccount.into();
+        }
+
+        let key = subaccount_key(asset_id, label);
+        let mut balance = self.balance_of(user, &key);
+        if balance.current < value {
+            return AssetError::InsufficientBalance {
+                wanted: value,
+                had: balance.current,
+            }
+            .into();
+        }
+
+        balance.current -= value;
+        self.sdk.set_account_value(user, key, balance);
+        ServiceResponse::from_succeed(())
+    }
+
+    // count an account in or out of an asset's holders when its balance
+    // becomes non-empty or empty. lock and unlock only move value between
+    // current and locked, so they never change whether an account holds
+    fn update_holders(&mut self, asset_id: &Hash, before: &Balance, after: &Balance) {
+        let holders = self.holder_counts.get(asset_id).unwrap_or(0);
+        if before.is_empty() && !after.is_empty() {
+            self.holder_counts.insert(asset_id.clone(), holders + 1);
+        } else if !before.is_empty() && after.is_empty() {
+            self.holder_counts
+                .insert(asset_id.clone(), holders.saturating_sub(1));
+        }
+    }
+
+    fn balance_of(&self, user: &Address, asset_id: &Hash) -> Balance {
+        self.sdk
+            .get_account_value(user, asset_id)
+            .unwrap_or(Balance::default())
+    }
+
+    // the asset, provided the caller is its current issuer
+    // mints value of the asset to `to`, growing its supply
+    fn issue(&mut self, mut asset: Asset, to: &Address, value: u64) -> ServiceResponse<()> {
+        asset.supply = match asset.supply.checked_add(value) {
+            Some(supply) => supply,
+            None => return AssetError::U64Overflow.into(),
+        };
+        if asset.exceeds_max_supply(asset.supply) {
+            return AssetError::MaxSupplyExceeded {
+                max_supply: asset.max_supply,
+            }
+            .into();
+        }
+
+        let add_payload = ModifyBalancePayload {
+            asset_id: asset.id.clone(),
+            user: to.clone(),
+            value,
+            correlation_id: String::new(),
+        };
+        call_and_parse_service_response!(self, _add_value, &add_payload);
+        self.assets.insert(asset.id.clone(), asset);
+        ServiceResponse::from_succeed(())
+    }
+
+    // burns value of `from` held by user and mints as much of `to`, which
+    // must be the two sides of the payload's configured wrap pair. every
+    // check runs before either supply changes
+    fn convert(
+        &mut self,
+        user: &Address,
+        from: &Hash,
+        to: &Hash,
+        payload: &WrapPayload,
+    ) -> ServiceResponse<()> {
+        if self.wrap_pairs.get(&payload.asset_id) != Some(payload.wrapped_id.clone()) {
+            return AssetError::IllegalWrapPair.into();
+        }
+        let mut burnt = match self.assets.get(from) {
+            Some(asset) => asset,
+            None => return AssetError::AssetNotExist { id: from.clone() }.into(),
+        };
+        let minted = match self.assets.get(to) {
+            Some(asset) => asset,
+            None => return AssetError::AssetNotExist { id: to.clone() }.into(),
+        };
+        for asset in [&burnt, &minted].iter() {
+            if asset.frozen {
+                return AssetError::AssetFrozen {
+                    id: asset.id.clone(),
+                }
+                .into();
+            }
+        }
+
+        let balance = self.balance_of(user, from);
+        if balance.current < payload.value {
+            return AssetError::InsufficientBalance {
+                wanted: payload.value,
+                had: balance.current,
+            }
+            .into();
+        }
+        burnt.supply = match burnt.supply.checked_sub(payload.value) {
+            Some(supply) => supply,
+            None => return AssetError::U64Overflow.into(),
+        };
+        let minted_supply = match minted.supply.checked_add(payload.value) {
+            Some(supply) => supply,
+            None => return AssetError::U64Overflow.into(),
+        };
+        if minted.exceeds_max_supply(minted_supply) {
+            return AssetError::MaxSupplyExceeded {
+                max_supply: minted.max_supply,
+            }
+            .into();
+        }
+
+        let sub_payload = ModifyBalancePayload {
+            asset_id: from.clone(),
+            user: user.clone(),
+            value: payload.value,
+            correlation_id: String::new(),
+        };
+        call_and_parse_service_response!(self, _sub_value, &sub_payload);
+        self.assets.insert(from.clone(), burnt);
+        self.issue(minted, user, payload.value)
+    }
+
+    fn get_issued_asset(&self, ctx: &ServiceContext, asset_id: &Hash) -> ServiceResponse<Asset> {
+        let asset = match self.assets.get(asset_id) {
+            Some(asset) => asset,
+            None => {
+                return AssetError::AssetNotExist {
+                    id: asset_id.clone(),
+                }
+                .into()
+            }
+        };
+        if asset.issuer != ctx.get_caller() {
+            return AssetError::PermissionDenial.into();
+        }
+
+        ServiceResponse::from_succeed(asset)
+    }
+
+    fn emit_balance_change(
+        &self,
+        ctx: &ServiceContext,
+        payload: ModifyBalancePayload,
+        added: bool,
+    ) -> ServiceResponse<()> {
+        let event = BalanceChangeEvent {
+            asset_id: payload.asset_id,
+            user: payload.user,
+            value: payload.value,
+            added,
+            correlation_id: payload.correlation_id,
+        };
+        let event_json = serde_json_string!(event);
+        ctx.emit_event("BalanceChange".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
     fn _add_value(&mut self, payload: &ModifyBalancePayload) -> ServiceResponse<()> {
         if !self.assets.contains(&payload.asset_id) {
             return AssetError::AssetNotExist {
@@ -322,7 +1153,9 @@ impl<SDK: ServiceSDK> AssetService<SDK> {
             return AssetError::U64Overflow.into();
         }
 
+        let before = balance.clone();
         balance.current = result;
+        self.update_holders(&payload.asset_id, &before, &balance);
         self.sdk
             .set_account_value(&payload.user, payload.asset_id.clone(), balance);
         ServiceResponse::from_succeed(())
@@ -349,7 +1182,9 @@ impl<SDK: ServiceSDK> AssetService<SDK> {
             .into();
         }
 
+        let before = balance.clone();
         balance.current = balance.current - payload.value;
+        self.update_holders(&payload.asset_id, &before, &balance);
         self.sdk
             .set_account_value(&payload.user, payload.asset_id.clone(), balance);
         ServiceResponse::from_succeed(())
@@ -380,6 +1215,39 @@ pub enum AssetError {
     U64Overflow,
 
     PermissionDenial,
+
+    #[display(fmt = "Asset {:?} is frozen", id)]
+    AssetFrozen {
+        id: Hash,
+    },
+
+    #[display(
+        fmt = "Transfer left sender with {} and recipient with {}",
+        sender,
+        recipient
+    )]
+    TransferInvariant {
+        sender: u64,
+        recipient: u64,
+    },
+
+    #[display(fmt = "Supply exceeds max supply {}", max_supply)]
+    MaxSupplyExceeded {
+        max_supply: u64,
+    },
+
+    FaucetDisabled,
+
+    IllegalTransferFee,
+
+    #[display(fmt = "Faucet cooling down until height {}", next_height)]
+    FaucetCooldown {
+        next_height: u64,
+    },
+
+    IllegalSubAccount,
+
+    IllegalWrapPair,
 }
 
 impl AssetError {
@@ -391,6 +1259,14 @@ impl AssetError {
             AssetError::InsufficientBalance { .. } => 104,
             AssetError::U64Overflow => 105,
             AssetError::PermissionDenial => 106,
+            AssetError::AssetFrozen { .. } => 107,
+            AssetError::MaxSupplyExceeded { .. } => 108,
+            AssetError::TransferInvariant { .. } => 109,
+            AssetError::FaucetDisabled => 110,
+            AssetError::FaucetCooldown { .. } => 111,
+            AssetError::IllegalTransferFee => 112,
+            AssetError::IllegalSubAccount => 113,
+            AssetError::IllegalWrapPair => 114,
         }
     }
 }
@@ -14,14 +14,30 @@ use protocol::types::{
 };
 use protocol::ProtocolResult;
 
-use crate::types::{CreateAssetPayload, GetAssetPayload, GetBalancePayload, TransferPayload, ModifyBalancePayload};
-use crate::AssetService;
+use crate::types::{
+    scale_amount, Balance, CreateAssetPayload, FaucetPayload, FreezePayload, GetAssetPayload,
+    GetBalancePayload, GetHolderCountPayload, GetSubAccountBalancePayload, InitGenesisPayload,
+    IsAuthorizedPayload, IsAuthorizedResponse, MintPayload, ModifyBalancePayload,
+    SetWrapPairPayload, SettleLockedPayload, SubAccountPayload, TransferIssuerPayload,
+    TransferPayload, WrapPayload,
+};
+use crate::{AssetFacade, AssetService};
+
+type TestAssetService = AssetService<
+    DefalutServiceSDK<
+        GeneralServiceState<MemoryDB>,
+        DefaultChainQuerier<MockStorage>,
+        NoopDispatcher,
+    >,
+>;
+
+const CYCLES_LIMIT: u64 = 1024 * 1024 * 1024; // 1073741824
 
 #[test]
 fn test_transfer() {
     let cycles_limit = 1024 * 1024 * 1024; // 1073741824
     let caller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
-    let context = mock_context(cycles_limit, caller);
+    let context = mock_context(cycles_limit, caller.clone());
 
     let mut service = new_asset_service();
 
@@ -32,66 +48,697 @@ fn test_transfer() {
             name: "test".to_owned(),
             symbol: "test".to_owned(),
             supply,
+            decimals: 0,
+            max_supply: 0,
+            transfer_fee_bps: 0,
         })
-        .unwrap();
+        .succeed_data;
 
     let to_address = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
-    service
-        .transfer(context.clone(), TransferPayload {
-            asset_id: asset.id.clone(),
-            to:       to_address.clone(),
-            value:    1024,
-        })
-        .unwrap();
+    let res = service.transfer(context.clone(), TransferPayload {
+        asset_id:        asset.id.clone(),
+        to:              to_address.clone(),
+        value:           1024,
+        from_subaccount: String::new(),
+    });
+    assert!(!res.is_error());
 
     let balance_res = service
         .get_balance(context, GetBalancePayload {
             asset_id: asset.id.clone(),
+            user:     caller,
         })
-        .unwrap();
+        .succeed_data;
     println!("sender:{:?}", &balance_res);
 
     let rev_context = mock_context(cycles_limit, to_address.clone());
+    let balance_payload = GetBalancePayload {
+        asset_id: asset.id.clone(),
+        user:     to_address.clone(),
+    };
     let balance_res = service
-        .get_balance(rev_context.clone(), GetBalancePayload { asset_id: asset.id.clone() })
-        .unwrap();
+        .get_balance(rev_context.clone(), balance_payload.clone())
+        .succeed_data;
     println!("reciever:{:?}", &balance_res);
 
-
-    service
-        .lock(rev_context.clone(), ModifyBalancePayload {
-            asset_id: asset.id.clone(),
-            user:       to_address.clone(),
-            value:    24,
-        })
-        .unwrap();
+    let lock_context = mock_admitted_context(b"dex_lock_token");
+    let res = service.lock(lock_context.clone(), ModifyBalancePayload {
+        asset_id:       asset.id.clone(),
+        user:           to_address.clone(),
+        value:          24,
+        correlation_id: String::new(),
+    });
+    assert!(!res.is_error());
 
     let balance_res = service
-        .get_balance(rev_context.clone(), GetBalancePayload { asset_id: asset.id.clone() })
-        .unwrap();
+        .get_balance(rev_context.clone(), balance_payload.clone())
+        .succeed_data;
     println!("reciever:{:?}", &balance_res);
 
-    service
-        .unlock(rev_context.clone(), ModifyBalancePayload {
-            asset_id: asset.id.clone(),
-            user:       to_address.clone(),
-            value:    24,
-        })
-        .unwrap();
+    let res = service.unlock(lock_context, ModifyBalancePayload {
+        asset_id:       asset.id.clone(),
+        user:           to_address.clone(),
+        value:          24,
+        correlation_id: String::new(),
+    });
+    assert!(!res.is_error());
 
-    let balance_res = service
-        .get_balance(rev_context.clone(), GetBalancePayload { asset_id: asset.id.clone() })
-        .unwrap();
+    let balance_res = service.get_balance(rev_context, balance_payload).succeed_data;
     println!("reciever:{:?}", &balance_res);
 }
 
-fn new_asset_service() -> AssetService<
-    DefalutServiceSDK<
-        GeneralServiceState<MemoryDB>,
-        DefaultChainQuerier<MockStorage>,
-        NoopDispatcher,
-    >,
-> {
+#[test]
+fn test_scale_amount() {
+    assert_eq!(scale_amount(12, 2, 2), Some(12));
+    assert_eq!(scale_amount(12, 2, 8), Some(12_000_000));
+    assert_eq!(scale_amount(12_345_678, 8, 2), Some(12));
+    assert_eq!(scale_amount(1, 8, 0), Some(0));
+
+    assert_eq!(scale_amount(std::u64::MAX, 0, 1), None);
+    assert_eq!(scale_amount(1, 0, 20), None);
+    assert_eq!(scale_amount(1, 0, 255), None);
+    assert_eq!(scale_amount(std::u64::MAX, 255, 0), Some(0));
+}
+
+#[test]
+fn test_admission_tokens() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let asset_id = create_asset(&mut service, &user, "BASE");
+    let payload = ModifyBalancePayload {
+        asset_id:       asset_id.clone(),
+        user:           user.clone(),
+        value:          100,
+        correlation_id: String::new(),
+    };
+
+    // a caller admitted for locking can't mint balance out of thin air
+    let lock_context = mock_admitted_context(b"dex_lock_token");
+    assert!(!service.lock(lock_context.clone(), payload.clone()).is_error());
+    let res = service.add_value(lock_context, payload.clone());
+    assert_eq!(res.code, 106);
+
+    let balance_context = mock_admitted_context(b"dex_balance_token");
+    let res = service.unlock(balance_context.clone(), payload.clone());
+    assert_eq!(res.code, 106);
+    assert!(!service.add_value(balance_context, payload).is_error());
+
+    let balance = get_balance(&service, &user, &asset_id);
+    assert_eq!(balance.current, 1_000_000 - 100 + 100);
+    assert_eq!(balance.locked, 100);
+}
+
+#[test]
+fn test_settle_locked() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let alice = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let bob = Address::from_hex("0x777cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let asset_id = create_asset(&mut service, &user, "BASE");
+    let modify = |user: &Address, value: u64| ModifyBalancePayload {
+        asset_id: asset_id.clone(),
+        user: user.clone(),
+        value,
+        correlation_id: String::new(),
+    };
+    let settle = |to: &Address, value: u64| SettleLockedPayload {
+        asset_id: asset_id.clone(),
+        from: user.clone(),
+        to: to.clone(),
+        value,
+        correlation_id: String::new(),
+    };
+
+    let lock_context = mock_admitted_context(b"dex_lock_token");
+    let balance_context = mock_admitted_context(b"dex_balance_token");
+    assert!(!service.lock(lock_context.clone(), modify(&user, 300)).is_error());
+
+    // paying alice by unlocking, subtracting and adding leaves the same
+    // balances as paying bob straight out of the lock
+    assert!(!service.unlock(lock_context.clone(), modify(&user, 100)).is_error());
+    assert!(!service.sub_value(balance_context.clone(), modify(&user, 100)).is_error());
+    assert!(!service.add_value(balance_context.clone(), modify(&alice, 100)).is_error());
+    let res = service.settle_locked(balance_context.clone(), settle(&bob, 100));
+    assert!(!res.is_error());
+
+    assert_eq!(get_balance(&service, &alice, &asset_id), get_balance(&service, &bob, &asset_id));
+    let balance = get_balance(&service, &user, &asset_id);
+    assert_eq!(balance.current, 1_000_000 - 300);
+    assert_eq!(balance.locked, 100);
+
+    let res = service.settle_locked(lock_context, settle(&bob, 10));
+    assert_eq!(res.code, 106);
+    let res = service.settle_locked(balance_context.clone(), settle(&bob, 101));
+    assert_eq!(res.code, 104);
+
+    // settling to oneself unlocks
+    let res = service.settle_locked(balance_context, settle(&user, 40));
+    assert!(!res.is_error());
+    let balance = get_balance(&service, &user, &asset_id);
+    assert_eq!(balance.current, 1_000_000 - 300 + 40);
+    assert_eq!(balance.locked, 60);
+    let locked_total = service
+        .query_locked_total(mock_context(CYCLES_LIMIT, user.clone()), GetAssetPayload {
+            id: asset_id.clone(),
+        })
+        .succeed_data;
+    assert_eq!(locked_total, 60);
+}
+
+#[test]
+fn test_transfer_issuer() {
+    let issuer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let new_issuer = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let asset_id = create_asset(&mut service, &issuer, "BASE");
+
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let res = service.transfer_issuer(context, TransferIssuerPayload {
+        asset_id:   asset_id.clone(),
+        new_issuer: new_issuer.clone(),
+    });
+    assert!(!res.is_error());
+
+    let mint = MintPayload {
+        asset_id: asset_id.clone(),
+        to:       issuer.clone(),
+        value:    100,
+    };
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let res = service.mint(context, mint.clone());
+    assert_eq!(res.code, 106);
+
+    let context = mock_context(CYCLES_LIMIT, new_issuer);
+    assert!(!service.mint(context, mint).is_error());
+    assert_eq!(get_balance(&service, &issuer, &asset_id).current, 1_000_100);
+}
+
+#[test]
+fn test_transfer_event_decimals() {
+    let sender = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let receiver = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let context = mock_context(CYCLES_LIMIT, sender.clone());
+    let asset = service
+        .create_asset(context, CreateAssetPayload {
+            name:             "USDT".to_owned(),
+            symbol:           "USDT".to_owned(),
+            supply:           1_000_000,
+            decimals:         6,
+            max_supply:       0,
+            transfer_fee_bps: 0,
+        })
+        .succeed_data;
+
+    let context = mock_context(CYCLES_LIMIT, sender);
+    let res = service.transfer(context.clone(), TransferPayload {
+        asset_id:        asset.id,
+        to:              receiver,
+        value:           100,
+        from_subaccount: String::new(),
+    });
+    assert!(!res.is_error());
+
+    let events = context.get_events();
+    let transfer = events.last().expect("transfer should emit an event");
+    assert!(transfer.data.contains("\"decimals\":6"));
+}
+
+#[test]
+fn test_transfer_fee() {
+    let issuer = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let sender = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let receiver = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let asset = service
+        .create_asset(context, CreateAssetPayload {
+            name:             "TAX".to_owned(),
+            symbol:           "TAX".to_owned(),
+            supply:           1_000_000,
+            decimals:         0,
+            max_supply:       0,
+            transfer_fee_bps: 250,
+        })
+        .succeed_data;
+    let balance_context = mock_admitted_context(b"dex_balance_token");
+    let res = service.add_value(balance_context, ModifyBalancePayload {
+        asset_id:       asset.id.clone(),
+        user:           sender.clone(),
+        value:          10_000,
+        correlation_id: String::new(),
+    });
+    assert!(!res.is_error());
+
+    // 2.5% of 1001 is 25.025, rounded down in the receiver's favour
+    let context = mock_context(CYCLES_LIMIT, sender.clone());
+    let res = service.transfer(context.clone(), TransferPayload {
+        asset_id:        asset.id.clone(),
+        to:              receiver.clone(),
+        value:           1001,
+        from_subaccount: String::new(),
+    });
+    assert!(!res.is_error());
+    assert_eq!(get_balance(&service, &sender, &asset.id).current, 10_000 - 1001);
+    assert_eq!(get_balance(&service, &receiver, &asset.id).current, 976);
+    assert_eq!(get_balance(&service, &issuer, &asset.id).current, 1_000_000 + 25);
+
+    let events = context.get_events();
+    let transfer = events.last().expect("transfer should emit an event");
+    assert!(transfer.data.contains("\"fee\":25"));
+
+    // transfer_exact charges it just the same
+    let res = service.transfer_exact(context, TransferPayload {
+        asset_id:        asset.id.clone(),
+        to:              receiver.clone(),
+        value:           1000,
+        from_subaccount: String::new(),
+    });
+    assert!(!res.is_error());
+    assert_eq!(get_balance(&service, &sender, &asset.id).current, 10_000 - 2001);
+    assert_eq!(get_balance(&service, &receiver, &asset.id).current, 976 + 975);
+    assert_eq!(get_balance(&service, &issuer, &asset.id).current, 1_000_000 + 50);
+
+    let context = mock_context(CYCLES_LIMIT, issuer);
+    let res = service.create_asset(context, CreateAssetPayload {
+        name:             "BAD".to_owned(),
+        symbol:           "BAD".to_owned(),
+        supply:           1,
+        decimals:         0,
+        max_supply:       0,
+        transfer_fee_bps: 10_001,
+    });
+    assert_eq!(res.code, 112);
+}
+
+#[test]
+fn test_supply_overflow() {
+    let issuer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let asset = service
+        .create_asset(context, CreateAssetPayload {
+            name:             "MAX".to_owned(),
+            symbol:           "MAX".to_owned(),
+            supply:           std::u64::MAX,
+            decimals:         0,
+            max_supply:       0,
+            transfer_fee_bps: 0,
+        })
+        .succeed_data;
+
+    let balance_context = mock_admitted_context(b"dex_balance_token");
+    let res = service.add_value(balance_context, ModifyBalancePayload {
+        asset_id:       asset.id.clone(),
+        user:           issuer.clone(),
+        value:          1,
+        correlation_id: String::new(),
+    });
+    assert_eq!(res.code, 105);
+    assert_eq!(get_balance(&service, &issuer, &asset.id).current, std::u64::MAX);
+
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let res = service.mint(context, MintPayload {
+        asset_id: asset.id,
+        to:       issuer.clone(),
+        value:    1,
+    });
+    assert_eq!(res.code, 105);
+
+    let context = mock_context(CYCLES_LIMIT, issuer);
+    let res = service.create_asset(context, CreateAssetPayload {
+        name:             "CAP".to_owned(),
+        symbol:           "CAP".to_owned(),
+        supply:           101,
+        decimals:         0,
+        max_supply:       100,
+        transfer_fee_bps: 0,
+    });
+    assert_eq!(res.code, 108);
+}
+
+#[test]
+fn test_transfer_exact() {
+    let sender = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let receiver = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let asset_id = create_asset(&mut service, &sender, "BASE");
+
+    let context = mock_context(CYCLES_LIMIT, sender.clone());
+    let res = service.transfer_exact(context, TransferPayload {
+        asset_id:        asset_id.clone(),
+        to:              receiver.clone(),
+        value:           1_000_001,
+        from_subaccount: String::new(),
+    });
+    assert_eq!(res.code, 104);
+
+    let context = mock_context(CYCLES_LIMIT, sender.clone());
+    let res = service.transfer_exact(context, TransferPayload {
+        asset_id:        asset_id.clone(),
+        to:              receiver.clone(),
+        value:           1_000_000,
+        from_subaccount: String::new(),
+    });
+    assert!(!res.is_error());
+    assert_eq!(get_balance(&service, &sender, &asset_id).current, 0);
+    assert_eq!(get_balance(&service, &receiver, &asset_id).current, 1_000_000);
+}
+
+#[test]
+fn test_subaccounts() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let receiver = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let asset_id = create_asset(&mut service, &user, "BASE");
+    let subaccount = |service: &TestAssetService, label: &str| {
+        let context = mock_context(CYCLES_LIMIT, user.clone());
+        service
+            .get_subaccount_balance(context, GetSubAccountBalancePayload {
+                asset_id: asset_id.clone(),
+                user:     user.clone(),
+                label:    label.to_owned(),
+            })
+            .succeed_data
+            .balance
+    };
+    let move_payload = |label: &str, value: u64| SubAccountPayload {
+        asset_id: asset_id.clone(),
+        label:    label.to_owned(),
+        value,
+    };
+
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let res = service.deposit_subaccount(context.clone(), move_payload("trading", 300_000));
+    assert!(!res.is_error());
+    assert_eq!(get_balance(&service, &user, &asset_id).current, 700_000);
+    assert_eq!(subaccount(&service, "trading"), 300_000);
+    assert_eq!(subaccount(&service, "savings"), 0);
+
+    // the main balance can't pay with what sits in a sub-account
+    let res = service.transfer(context.clone(), TransferPayload {
+        asset_id:        asset_id.clone(),
+        to:              receiver.clone(),
+        value:           700_001,
+        from_subaccount: String::new(),
+    });
+    assert_eq!(res.code, 104);
+
+    // nor can one sub-account pay with another's funds
+    let res = service.withdraw_subaccount(context.clone(), move_payload("savings", 1));
+    assert_eq!(res.code, 104);
+    let res = service.deposit_subaccount(context.clone(), move_payload("", 1));
+    assert_eq!(res.code, 113);
+
+    let res = service.transfer(context.clone(), TransferPayload {
+        asset_id:        asset_id.clone(),
+        to:              receiver.clone(),
+        value:           50_000,
+        from_subaccount: "trading".to_owned(),
+    });
+    assert!(!res.is_error());
+    assert_eq!(subaccount(&service, "trading"), 250_000);
+    assert_eq!(get_balance(&service, &user, &asset_id).current, 700_000);
+    assert_eq!(get_balance(&service, &receiver, &asset_id).current, 50_000);
+
+    let res = service.withdraw_subaccount(context, move_payload("trading", 250_000));
+    assert!(!res.is_error());
+    assert_eq!(subaccount(&service, "trading"), 0);
+    assert_eq!(get_balance(&service, &user, &asset_id).current, 950_000);
+}
+
+#[test]
+fn test_wrap() {
+    let issuer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let user = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let native = create_asset(&mut service, &issuer, "NAT");
+    let wrapped = create_asset(&mut service, &issuer, "WNAT");
+    let other = create_asset(&mut service, &user, "OTHER");
+    let supply = |service: &TestAssetService, id: &Hash| {
+        let context = mock_context(CYCLES_LIMIT, issuer.clone());
+        let res = service.get_asset(context, GetAssetPayload { id: id.clone() });
+        res.succeed_data.supply
+    };
+    let wrap_payload = |value: u64| WrapPayload {
+        asset_id:   native.clone(),
+        wrapped_id: wrapped.clone(),
+        value,
+    };
+
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let res = service.transfer(context.clone(), TransferPayload {
+        asset_id:        native.clone(),
+        to:              user.clone(),
+        value:           300_000,
+        from_subaccount: String::new(),
+    });
+    assert!(!res.is_error());
+
+    // nothing is pegged until the issuer of both sides configures it
+    let user_context = mock_context(CYCLES_LIMIT, user.clone());
+    let res = service.wrap(user_context.clone(), wrap_payload(1));
+    assert_eq!(res.code, 114);
+    let res = service.set_wrap_pair(context.clone(), SetWrapPairPayload {
+        asset_id:   native.clone(),
+        wrapped_id: other.clone(),
+    });
+    assert_eq!(res.code, 106);
+    let res = service.set_wrap_pair(context.clone(), SetWrapPairPayload {
+        asset_id:   native.clone(),
+        wrapped_id: native.clone(),
+    });
+    assert_eq!(res.code, 114);
+    let res = service.set_wrap_pair(context, SetWrapPairPayload {
+        asset_id:   native.clone(),
+        wrapped_id: wrapped.clone(),
+    });
+    assert!(!res.is_error());
+
+    let res = service.wrap(user_context.clone(), wrap_payload(200_000));
+    assert!(!res.is_error());
+    assert_eq!(get_balance(&service, &user, &native).current, 100_000);
+    assert_eq!(get_balance(&service, &user, &wrapped).current, 200_000);
+    assert_eq!(supply(&service, &native), 800_000);
+    assert_eq!(supply(&service, &wrapped), 1_200_000);
+
+    let res = service.unwrap(user_context.clone(), wrap_payload(200_001));
+    assert_eq!(res.code, 104);
+    let res = service.unwrap(user_context.clone(), wrap_payload(50_000));
+    assert!(!res.is_error());
+    assert_eq!(get_balance(&service, &user, &native).current, 150_000);
+    assert_eq!(get_balance(&service, &user, &wrapped).current, 150_000);
+    assert_eq!(supply(&service, &native), 850_000);
+    assert_eq!(supply(&service, &wrapped), 1_150_000);
+
+    // the peg only runs along the configured pair
+    let res = service.wrap(user_context, WrapPayload {
+        asset_id:   wrapped.clone(),
+        wrapped_id: native.clone(),
+        value:      1,
+    });
+    assert_eq!(res.code, 114);
+}
+
+#[test]
+fn test_is_authorized() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let asset_id = create_asset(&mut service, &user, "BASE");
+    let authorized = |service: &TestAssetService, token_or_address: &str| {
+        let context = mock_context(CYCLES_LIMIT, user.clone());
+        service
+            .is_authorized(context, IsAuthorizedPayload {
+                token_or_address: token_or_address.to_owned(),
+            })
+            .succeed_data
+    };
+
+    assert_eq!(authorized(&service, "dex_lock_token"), IsAuthorizedResponse {
+        authorized: true,
+        lock:       true,
+        balance:    false,
+    });
+    assert_eq!(authorized(&service, "dex_balance_token"), IsAuthorizedResponse {
+        authorized: true,
+        lock:       false,
+        balance:    true,
+    });
+    let address = user.as_hex();
+    assert_eq!(authorized(&service, &address), IsAuthorizedResponse::default());
+
+    // and the admission checks agree
+    let lock_payload = ModifyBalancePayload {
+        asset_id:       asset_id.clone(),
+        user:           user.clone(),
+        value:          1,
+        correlation_id: String::new(),
+    };
+    let res = service.lock(mock_admitted_context(b"dex_lock_token"), lock_payload.clone());
+    assert!(!res.is_error());
+    let res = service.lock(mock_admitted_context(b"dex_balance_token"), lock_payload);
+    assert_eq!(res.code, 106);
+}
+
+#[test]
+fn test_holder_count() {
+    let issuer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let alice = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let bob = Address::from_hex("0x777cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_asset_service();
+    let asset_id = create_asset(&mut service, &issuer, "BASE");
+    let holders = |service: &TestAssetService| {
+        let context = mock_context(CYCLES_LIMIT, issuer.clone());
+        service
+            .get_holder_count(context, GetHolderCountPayload {
+                asset_id: asset_id.clone(),
+            })
+            .succeed_data
+            .holders
+    };
+    let transfer = |service: &mut TestAssetService, from: &Address, to: &Address, value: u64| {
+        let context = mock_context(CYCLES_LIMIT, from.clone());
+        let res = service.transfer(context, TransferPayload {
+            asset_id:        asset_id.clone(),
+            to:              to.clone(),
+            value,
+            from_subaccount: String::new(),
+        });
+        assert!(!res.is_error());
+    };
+    assert_eq!(holders(&service), 1);
+
+    transfer(&mut service, &issuer, &alice, 100);
+    transfer(&mut service, &issuer, &bob, 100);
+    transfer(&mut service, &issuer, &bob, 100);
+    assert_eq!(holders(&service), 3);
+
+    // an account whose whole balance is locked still holds the asset
+    let lock_payload = ModifyBalancePayload {
+        asset_id:       asset_id.clone(),
+        user:           bob.clone(),
+        value:          200,
+        correlation_id: String::new(),
+    };
+    let lock_context = mock_admitted_context(b"dex_lock_token");
+    assert!(!service.lock(lock_context.clone(), lock_payload.clone()).is_error());
+    assert_eq!(holders(&service), 3);
+    assert!(!service.unlock(lock_context, lock_payload).is_error());
+
+    transfer(&mut service, &alice, &issuer, 100);
+    transfer(&mut service, &bob, &alice, 200);
+    assert_eq!(holders(&service), 2);
+    transfer(&mut service, &alice, &issuer, 200);
+    assert_eq!(holders(&service), 1);
+}
+
+#[test]
+fn test_faucet() {
+    let issuer = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let asset_id = Hash::digest(Bytes::from_static(b"native"));
+
+    let mut service = new_asset_service();
+    service.init_genesis(InitGenesisPayload {
+        id:              asset_id.clone(),
+        name:            "NATIVE".to_owned(),
+        symbol:          "NAT".to_owned(),
+        supply:          1000,
+        issuer:          issuer.clone(),
+        decimals:        0,
+        max_supply:      0,
+        faucet_enabled:  true,
+        faucet_cooldown: 10,
+    });
+
+    let claim = FaucetPayload {
+        asset_id: asset_id.clone(),
+        value:    100,
+    };
+    let context = mock_tx_context(user.clone(), Hash::digest(Bytes::from_static(b"claim_1")), 1);
+    assert!(!service.faucet(context, claim.clone()).is_error());
+    assert_eq!(get_balance(&service, &user, &asset_id).current, 100);
+
+    let context = mock_tx_context(user.clone(), Hash::digest(Bytes::from_static(b"claim_2")), 10);
+    let res = service.faucet(context, claim.clone());
+    assert_eq!(res.code, 111);
+
+    let context = mock_tx_context(user.clone(), Hash::digest(Bytes::from_static(b"claim_3")), 11);
+    assert!(!service.faucet(context, claim).is_error());
+    assert_eq!(get_balance(&service, &user, &asset_id).current, 200);
+
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let asset = service
+        .get_asset(context, GetAssetPayload {
+            id: asset_id.clone(),
+        })
+        .succeed_data;
+    assert_eq!(asset.supply, 1200);
+
+    // only the genesis asset is handed out
+    let other = create_asset(&mut service, &issuer, "OTHER");
+    let context = mock_tx_context(user.clone(), Hash::digest(Bytes::from_static(b"claim_4")), 30);
+    let res = service.faucet(context, FaucetPayload {
+        asset_id: other,
+        value:    100,
+    });
+    assert_eq!(res.code, 110);
+
+    // and not while it's frozen
+    let context = mock_context(CYCLES_LIMIT, issuer);
+    let res = service.freeze(context, FreezePayload {
+        asset_id: asset_id.clone(),
+        frozen:   true,
+    });
+    assert!(!res.is_error());
+    let context = mock_tx_context(user, Hash::digest(Bytes::from_static(b"claim_5")), 30);
+    let res = service.faucet(context, FaucetPayload {
+        asset_id,
+        value: 100,
+    });
+    assert_eq!(res.code, 107);
+}
+
+#[test]
+fn test_faucet_disabled() {
+    let issuer = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let asset_id = Hash::digest(Bytes::from_static(b"native"));
+
+    let mut service = new_asset_service();
+    service.init_genesis(InitGenesisPayload {
+        id:              asset_id.clone(),
+        name:            "NATIVE".to_owned(),
+        symbol:          "NAT".to_owned(),
+        supply:          1000,
+        issuer,
+        decimals:        0,
+        max_supply:      0,
+        faucet_enabled:  false,
+        faucet_cooldown: 0,
+    });
+
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let res = service.faucet(context, FaucetPayload {
+        asset_id: asset_id.clone(),
+        value:    100,
+    });
+    assert_eq!(res.code, 110);
+    assert_eq!(get_balance(&service, &user, &asset_id).current, 0);
+}
+
+fn new_asset_service() -> TestAssetService {
     let chain_db = DefaultChainQuerier::new(Arc::new(MockStorage {}));
     let trie = MPTTrie::new(Arc::new(MemoryDB::new(false)));
     let state = GeneralServiceState::new(trie);
@@ -102,7 +749,51 @@ fn new_asset_service() -> AssetService<
         NoopDispatcher {},
     );
 
-    AssetService::new(sdk).unwrap()
+    AssetService::new(sdk)
+}
+
+fn create_asset(service: &mut TestAssetService, issuer: &Address, symbol: &str) -> Hash {
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let asset = service.create_asset(context, CreateAssetPayload {
+        name:             symbol.to_owned(),
+        symbol:           symbol.to_owned(),
+        supply:           1_000_000,
+        decimals:         0,
+        max_supply:       0,
+        transfer_fee_bps: 0,
+    });
+
+    asset.succeed_data.id
+}
+
+fn get_balance(service: &TestAssetService, user: &Address, asset_id: &Hash) -> Balance {
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let res = service.get_balance(context, GetBalancePayload {
+        asset_id: asset_id.clone(),
+        user:     user.clone(),
+    });
+
+    res.succeed_data.balance
+}
+
+fn mock_tx_context(caller: Address, tx_hash: Hash, height: u64) -> ServiceContext {
+    let params = ServiceContextParams {
+        tx_hash: Some(tx_hash),
+        nonce: None,
+        cycles_limit: CYCLES_LIMIT,
+        cycles_price: 1,
+        cycles_used: Rc::new(RefCell::new(0)),
+        caller,
+        height,
+        timestamp: 0,
+        service_name: "service_name".to_owned(),
+        service_method: "service_method".to_owned(),
+        service_payload: "service_payload".to_owned(),
+        extra: None,
+        events: Rc::new(RefCell::new(vec![])),
+    };
+
+    ServiceContext::new(params)
 }
 
 fn mock_context(cycles_limit: u64, caller: Address) -> ServiceContext {
@@ -113,7 +804,7 @@ fn mock_context(cycles_limit: u64, caller: Address) -> ServiceContext {
         cycles_price: 1,
         cycles_used: Rc::new(RefCell::new(0)),
         caller,
-        epoch_id: 1,
+        height: 1,
         timestamp: 0,
         service_name: "service_name".to_owned(),
         service_method: "service_method".to_owned(),
@@ -125,6 +816,26 @@ fn mock_context(cycles_limit: u64, caller: Address) -> ServiceContext {
     ServiceContext::new(params)
 }
 
+fn mock_admitted_context(token: &'static [u8]) -> ServiceContext {
+    let params = ServiceContextParams {
+        tx_hash: None,
+        nonce: None,
+        cycles_limit: CYCLES_LIMIT,
+        cycles_price: 1,
+        cycles_used: Rc::new(RefCell::new(0)),
+        caller: Address::from_hash(Hash::from_empty()).unwrap(),
+        height: 1,
+        timestamp: 0,
+        service_name: "service_name".to_owned(),
+        service_method: "service_method".to_owned(),
+        service_payload: "service_payload".to_owned(),
+        extra: Some(Bytes::from_static(token)),
+        events: Rc::new(RefCell::new(vec![])),
+    };
+
+    ServiceContext::new(params)
+}
+
 struct MockStorage;
 
 #[async_trait]
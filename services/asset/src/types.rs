@@ -12,6 +12,16 @@ pub struct InitGenesisPayload {
     pub symbol: String,
     pub supply: u64,
     pub issuer: Address,
+    #[serde(default)]
+    pub decimals: u8,
+    #[serde(default)]
+    pub max_supply: u64,
+    // testnet faucet, letting anyone mint the genesis asset to themselves
+    // once per cooldown blocks
+    #[serde(default)]
+    pub faucet_enabled: bool,
+    #[serde(default)]
+    pub faucet_cooldown: u64,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
@@ -21,6 +31,48 @@ pub struct Asset {
     pub symbol: String,
     pub supply: u64,
     pub issuer: Address,
+    // amounts are counted in units of 10^-decimals of the asset
+    pub decimals: u8,
+    // a frozen asset can't be transferred, and trades on it are halted
+    pub frozen: bool,
+    // cap on supply, including minted amounts. 0 means no cap
+    pub max_supply: u64,
+    // share of every transfer, in basis points, paid to the issuer
+    pub transfer_fee_bps: u64,
+    // whether the dex accepts orders on the asset, transfers are unaffected
+    pub dex_tradable: bool,
+}
+
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+impl Asset {
+    pub fn exceeds_max_supply(&self, supply: u64) -> bool {
+        self.max_supply != 0 && supply > self.max_supply
+    }
+
+    // rounded down, so the recipient never gets more than was sent
+    pub fn transfer_fee(&self, value: u64) -> u64 {
+        (value as u128 * self.transfer_fee_bps as u128 / BPS_DENOMINATOR as u128) as u64
+    }
+}
+
+// convert an amount between two decimal precisions, rounding down when
+// precision is lost. returns None if the result doesn't fit in u64
+pub fn scale_amount(value: u64, from_decimals: u8, to_decimals: u8) -> Option<u64> {
+    let scaled = if to_decimals >= from_decimals {
+        let factor = 10u128.checked_pow((to_decimals - from_decimals) as u32)?;
+        (value as u128).checked_mul(factor)?
+    } else {
+        match 10u128.checked_pow((from_decimals - to_decimals) as u32) {
+            Some(factor) => value as u128 / factor,
+            None => 0,
+        }
+    };
+
+    if scaled > std::u64::MAX as u128 {
+        return None;
+    }
+    Some(scaled as u64)
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
@@ -29,11 +81,24 @@ pub struct Balance {
     pub locked: u64,
 }
 
+impl Balance {
+    // an account holds an asset while any of it is current or locked
+    pub fn is_empty(&self) -> bool {
+        self.current == 0 && self.locked == 0
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct CreateAssetPayload {
     pub name: String,
     pub symbol: String,
     pub supply: u64,
+    #[serde(default)]
+    pub decimals: u8,
+    #[serde(default)]
+    pub max_supply: u64,
+    #[serde(default)]
+    pub transfer_fee_bps: u64,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -58,6 +123,69 @@ pub struct ModifyBalancePayload {
     pub asset_id: Hash,
     pub user: Address,
     pub value: u64,
+    // lets the caller tie the change to its own records, it is only echoed
+    // in the event
+    #[serde(default)]
+    pub correlation_id: String,
+}
+
+// moves value out of `from`'s locked balance straight into `to`'s current
+// balance, as settling a locked order would with an unlock, a sub and an add
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SettleLockedPayload {
+    pub asset_id: Hash,
+    pub from: Address,
+    pub to: Address,
+    pub value: u64,
+    #[serde(default)]
+    pub correlation_id: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MintPayload {
+    pub asset_id: Hash,
+    pub to: Address,
+    pub value: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FaucetPayload {
+    pub asset_id: Hash,
+    pub value: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TransferIssuerPayload {
+    pub asset_id: Hash,
+    pub new_issuer: Address,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FreezePayload {
+    pub asset_id: Hash,
+    pub frozen: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SetDexTradablePayload {
+    pub asset_id: Hash,
+    pub tradable: bool,
+}
+
+// pegs wrapped_id 1:1 to asset_id, the caller must issue both
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SetWrapPairPayload {
+    pub asset_id: Hash,
+    pub wrapped_id: Hash,
+}
+
+// wrap burns value of asset_id and mints as much of wrapped_id, unwrap the
+// other way round
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct WrapPayload {
+    pub asset_id: Hash,
+    pub wrapped_id: Hash,
+    pub value: u64,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -65,6 +193,87 @@ pub struct TransferPayload {
     pub asset_id: Hash,
     pub to: Address,
     pub value: u64,
+    // pay out of this sub-account of the caller rather than its main
+    // balance. transfer_exact only moves main balances
+    #[serde(default)]
+    pub from_subaccount: String,
+}
+
+// moves value between the caller's main balance of an asset and one of its
+// labelled sub-accounts
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SubAccountPayload {
+    pub asset_id: Hash,
+    pub label: String,
+    pub value: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SubAccountEvent {
+    pub asset_id: Hash,
+    pub user: Address,
+    pub label: String,
+    pub value: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetSubAccountBalancePayload {
+    pub asset_id: Hash,
+    pub user: Address,
+    pub label: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetSubAccountBalanceResponse {
+    pub asset_id: Hash,
+    pub label: String,
+    pub balance: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetHolderCountPayload {
+    pub asset_id: Hash,
+}
+
+// addresses with a non-empty main balance of the asset. funds moved into
+// sub-accounts don't count
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetHolderCountResponse {
+    pub asset_id: Hash,
+    pub holders: u64,
+}
+
+// an admission token, or an address, as a caller would present it
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct IsAuthorizedPayload {
+    pub token_or_address: String,
+}
+
+// what the protected operations would admit it to: lock and unlock, or
+// add_value, sub_value and settle_locked. admission goes by token alone, so
+// an address is never authorized
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct IsAuthorizedResponse {
+    pub authorized: bool,
+    pub lock: bool,
+    pub balance: bool,
+}
+
+// a sub-account balance is an account value of its user, kept under a key
+// derived from the asset and the label so it never mixes with the main one
+pub fn subaccount_key(asset_id: &Hash, label: &str) -> Hash {
+    Hash::digest(Bytes::from(asset_id.as_hex() + "/" + label))
+}
+
+// emitted by add_value and sub_value
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BalanceChangeEvent {
+    pub asset_id: Hash,
+    pub user: Address,
+    pub value: u64,
+    // false when the value was subtracted
+    pub added: bool,
+    pub correlation_id: String,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -73,6 +282,10 @@ pub struct TransferEvent {
     pub from: Address,
     pub to: Address,
     pub value: u64,
+    // decimals of the asset, so value can be displayed without a lookup
+    pub decimals: u8,
+    // taken out of value and paid to the issuer, `to` receives the rest
+    pub fee: u64,
 }
 
 impl rlp::Decodable for Asset {
@@ -83,18 +296,28 @@ impl rlp::Decodable for Asset {
             symbol: rlp.at(2)?.as_val()?,
             supply: rlp.at(3)?.as_val()?,
             issuer: rlp.at(4)?.as_val()?,
+            decimals: rlp.at(5)?.as_val()?,
+            frozen: rlp.at(6)?.as_val()?,
+            max_supply: rlp.at(7)?.as_val()?,
+            transfer_fee_bps: rlp.at(8)?.as_val()?,
+            dex_tradable: rlp.at(9)?.as_val()?,
         })
     }
 }
 
 impl rlp::Encodable for Asset {
     fn rlp_append(&self, s: &mut rlp::RlpStream) {
-        s.begin_list(5)
+        s.begin_list(10)
             .append(&self.id)
             .append(&self.name)
             .append(&self.symbol)
             .append(&self.supply)
-            .append(&self.issuer);
+            .append(&self.issuer)
+            .append(&self.decimals)
+            .append(&self.frozen)
+            .append(&self.max_supply)
+            .append(&self.transfer_fee_bps)
+            .append(&self.dex_tradable);
     }
 }
 
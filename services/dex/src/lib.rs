@@ -8,24 +8,92 @@ use std::rc::Rc;
 
 use bytes::Bytes;
 use derive_more::Display;
+use serde::Serialize;
 
 use binding_macro::{cycles, genesis, hook_after, read, service, write};
-use protocol::traits::{ExecutorParams, ServiceResponse, ServiceSDK, StoreMap, StoreUint64};
+use protocol::traits::{
+    ExecutorParams, ServiceResponse, ServiceSDK, StoreArray, StoreBool, StoreMap, StoreUint64,
+};
 use protocol::types::{Address, Hash, ServiceContext, ServiceContextParams};
 
 use crate::types::{
-    AddTradePayload, Deal, DealStatus, GenesisPayload, GetOrderPayload, GetOrderResponse,
-    GetTradesResponse, ModifyAssetPayload, Order, OrderKind, OrderPayload, OrderStatus, Trade,
+    apply_bps, canonical_trade_id, client_order_key, derive_order_hash, exceeds_deviation,
+    fill_within_average, pro_rata_allocate, taker_kind, AddTradePayload, AmendOrderPayload,
+    AssetTvl, AuditLocksPayload, AuditLocksResponse, BatchOrderPayload, BatchOrderResponse,
+    CancelByClientIdPayload, CancelOrderPayload, CircuitBreakerEvent, ComputeDealPricePayload,
+    ComputeDealPriceResponse, ComputeTradeIdPayload, ComputeTradeIdResponse, ConvertToMarketPayload,
+    CreateSwapPayload, CrossedBook, Deal, DealRecord, DealStatus, DetectCrossedBooksResponse,
+    EstimateMatchCostPayload, EstimateMatchCostResponse, EstimateSlippageResponse,
+    ExportStatePayload, ExportStateResponse, FindOffgridOrdersResponse, GenesisPayload,
+    Get24hStatsPayload, Get24hStatsResponse, GetBookVersionPayload, GetBookVersionResponse,
+    GetConfigResponse, GetDealsByRangePayload, GetDealsResponse, GetFillPricePayload,
+    GetFillPriceResponse, GetHistoryPayload, GetHistoryResponse, GetHookEventsPayload,
+    GetHookEventsResponse, GetLevelCountsPayload, GetLevelCountsResponse, GetLockedBreakdownPayload,
+    GetLockedBreakdownResponse, GetNativeAssetResponse, GetOpenInterestPayload,
+    GetOpenInterestResponse, GetOrderBookPayload, GetOrderDealsPayload, GetOrderDealsResponse,
+    GetOrderPayload, GetOrderReceiptPayload, GetOrderReceiptResponse, GetOrderResponse,
+    GetPriorityQueuePayload, GetPriorityQueueResponse, GetReferencePricePayload,
+    GetReferencePriceResponse, GetStatsResponse, GetTradePayload, GetTradesResponse, GetTvlResponse,
+    GetUserFillsPayload, GetUserTradesPayload, GetUserTradesResponse, HookEvent,
+    IncreaseOrderPayload, InvalidateOrdersEvent, IsCrossedPayload, IsCrossedResponse,
+    IsPriceImprovingResponse, MarketOrderPayload, MarketOrderResponse, MatchingRoundEvent,
+    ModifyAssetPayload, MoveOrderPayload, NetDelta, NetDeltaFailedEvent, OffgridOrdersPayload,
+    Order, OrderBook, OrderCounter, OrderIndex, OrderKind, OrderLock, OrderPayload, OrderStatus,
+    PriceLevel, PriceSource, PruneHistoryPayload, PruneHistoryResponse, QueuedOrder,
+    ReconcileLocksPayload, ReconcileLocksResponse, Rounding, SetAssetGroupPayload,
+    SetFeeRatePayload, SetMaxExpiryPayload, SetTradeEnabledPayload, SettleAssetPayload,
+    SplitOrderPayload, SplitOrderResponse, Swap, SwapPayload, TimeInForce, Trade, TradeDealCount,
+    UpdateTradeParamsPayload, BPS_DENOMINATOR, GOOD_TILL_CANCEL,
+};
+use asset::types::{
+    Balance, GetAssetPayload, GetBalancePayload, ModifyBalancePayload, SettleLockedPayload,
 };
-use asset::types::ModifyBalancePayload;
 use asset::AssetFacade;
 
-const ADMISSION_TOKEN: Bytes = Bytes::from_static(b"dex_token");
+// the asset service admits locking and balance changes under separate tokens
+const LOCK_TOKEN: Bytes = Bytes::from_static(b"dex_lock_token");
+const BALANCE_TOKEN: Bytes = Bytes::from_static(b"dex_balance_token");
+// charged per open order by force_match, on top of its base cycles
+const FORCE_MATCH_ORDER_CYCLES: u64 = 210_00;
+// rough cost of one settle, priced like a write method
+const SETTLE_CYCLES: u64 = 210_00;
+// upper bound on the taker fee set_fee_rate accepts, in basis points
+const MAX_FEE_RATE: u64 = 1000;
+// failed settlements after which a resting order is dropped from the book
+const MAX_SETTLE_FAILURES: u64 = 3;
 const TRADES_KEY: &str = "trades";
 const BUY_ORDERS_KEY: &str = "buy_orders";
 const SELL_ORDERS_KEY: &str = "sell_orders";
 const HISTORY_ORDERS_KEY: &str = "history_orders";
 const VALIDITY_KEY: &str = "validity";
+const PRO_RATA_KEY: &str = "pro_rata";
+const FEE_RATE_KEY: &str = "fee_rate";
+const REFERRAL_RATE_KEY: &str = "referral_rate";
+const MAKER_REBATE_KEY: &str = "maker_rebate";
+const FEE_ACCOUNT_KEY: &str = "fee_account";
+const ADMIN_KEY: &str = "admin";
+const NATIVE_ASSET_KEY: &str = "native_asset";
+const MAX_ORDERS_PER_BLOCK_KEY: &str = "max_orders_per_block";
+const ORDER_COUNTERS_KEY: &str = "order_counters";
+const ORDER_BOOK_SNAPSHOT_KEY: &str = "order_book_snapshot";
+const SNAPSHOT_DEPTH_KEY: &str = "snapshot_depth";
+const MAX_NOTIONAL_KEY: &str = "max_notional";
+const CIRCUIT_BREAKER_KEY: &str = "circuit_breaker";
+const LAST_PRICES_KEY: &str = "last_prices";
+const HOOK_EVENTS_KEY: &str = "hook_events";
+const DEAL_LOG_KEY: &str = "deal_log";
+const ORDER_INDEX_KEY: &str = "order_index";
+const TOTAL_DEALS_KEY: &str = "total_deals";
+const TRADE_DEALS_KEY: &str = "trade_deals";
+const SWAPS_KEY: &str = "swaps";
+const NET_SETTLEMENT_KEY: &str = "net_settlement";
+const ASSET_GROUPS_KEY: &str = "asset_groups";
+const ROUNDING_KEY: &str = "rounding";
+const SETTLE_FAILURES_KEY: &str = "settle_failures";
+const EXPIRY_GRACE_KEY: &str = "expiry_grace";
+const MAX_EXPIRY_KEY: &str = "max_expiry";
+const CLIENT_ORDERS_KEY: &str = "client_orders";
+const BOOK_VERSIONS_KEY: &str = "book_versions";
 
 /*
 call a method which returns ServiceResponse.
@@ -75,12 +143,43 @@ macro_rules! check_get_or_return {
 }
 
 pub struct DexService<SDK: ServiceSDK, A> {
-    _sdk: SDK,
+    sdk: SDK,
     trades: Box<dyn StoreMap<Hash, Trade>>,
     buy_orders: Box<dyn StoreMap<Hash, Order>>,
     sell_orders: Box<dyn StoreMap<Hash, Order>>,
     history_orders: Box<dyn StoreMap<Hash, Order>>,
+    order_index: Box<dyn StoreMap<Hash, OrderIndex>>,
     validity: Box<dyn StoreUint64>,
+    pro_rata: Box<dyn StoreBool>,
+    fee_rate: Box<dyn StoreUint64>,
+    referral_rate: Box<dyn StoreUint64>,
+    maker_rebate: Box<dyn StoreUint64>,
+    circuit_breaker: Box<dyn StoreUint64>,
+    max_orders_per_block: Box<dyn StoreUint64>,
+    order_counters: Box<dyn StoreMap<Address, OrderCounter>>,
+    order_book_snapshot: Box<dyn StoreBool>,
+    snapshot_depth: Box<dyn StoreUint64>,
+    max_notional: Box<dyn StoreUint64>,
+    last_prices: Box<dyn StoreMap<Hash, u64>>,
+    hook_events: Box<dyn StoreArray<HookEvent>>,
+    deal_log: Box<dyn StoreArray<DealRecord>>,
+    total_deals: Box<dyn StoreUint64>,
+    trade_deals: Box<dyn StoreMap<Hash, u64>>,
+    swaps: Box<dyn StoreMap<Hash, Swap>>,
+    net_settlement: Box<dyn StoreBool>,
+    asset_groups: Box<dyn StoreMap<Hash, String>>,
+    rounding: Box<dyn StoreUint64>,
+    settle_failures: Box<dyn StoreMap<Hash, u64>>,
+    expiry_grace: Box<dyn StoreUint64>,
+    max_expiry: Box<dyn StoreUint64>,
+    client_orders: Box<dyn StoreMap<Hash, Hash>>,
+    // the height each trade's book last changed at
+    book_versions: Box<dyn StoreMap<Hash, u64>>,
+    // correlation id passed along with balance changes, see settle
+    correlation_id: String,
+    // while a netted matching round runs, unlocks and balance changes are
+    // collected here rather than sent to the asset service
+    net_deltas: Option<Vec<NetDelta>>,
     asset: A,
 }
 
@@ -97,22 +196,113 @@ impl<SDK: 'static + ServiceSDK, A: AssetFacade> DexService<SDK, A> {
         let sell_orders: Box<dyn StoreMap<Hash, Order>> = sdk.alloc_or_recover_map(SELL_ORDERS_KEY);
         let history_orders: Box<dyn StoreMap<Hash, Order>> =
             sdk.alloc_or_recover_map(HISTORY_ORDERS_KEY);
+        let order_index: Box<dyn StoreMap<Hash, OrderIndex>> =
+            sdk.alloc_or_recover_map(ORDER_INDEX_KEY);
         let validity: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(VALIDITY_KEY);
+        let pro_rata: Box<dyn StoreBool> = sdk.alloc_or_recover_bool(PRO_RATA_KEY);
+        let fee_rate: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(FEE_RATE_KEY);
+        let referral_rate: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(REFERRAL_RATE_KEY);
+        let maker_rebate: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(MAKER_REBATE_KEY);
+        let circuit_breaker: Box<dyn StoreUint64> =
+            sdk.alloc_or_recover_uint64(CIRCUIT_BREAKER_KEY);
+        let max_orders_per_block: Box<dyn StoreUint64> =
+            sdk.alloc_or_recover_uint64(MAX_ORDERS_PER_BLOCK_KEY);
+        let order_counters: Box<dyn StoreMap<Address, OrderCounter>> =
+            sdk.alloc_or_recover_map(ORDER_COUNTERS_KEY);
+        let order_book_snapshot: Box<dyn StoreBool> =
+            sdk.alloc_or_recover_bool(ORDER_BOOK_SNAPSHOT_KEY);
+        let snapshot_depth: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(SNAPSHOT_DEPTH_KEY);
+        let max_notional: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(MAX_NOTIONAL_KEY);
+        let last_prices: Box<dyn StoreMap<Hash, u64>> = sdk.alloc_or_recover_map(LAST_PRICES_KEY);
+        let hook_events: Box<dyn StoreArray<HookEvent>> =
+            sdk.alloc_or_recover_array(HOOK_EVENTS_KEY);
+        let deal_log: Box<dyn StoreArray<DealRecord>> = sdk.alloc_or_recover_array(DEAL_LOG_KEY);
+        let total_deals: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(TOTAL_DEALS_KEY);
+        let trade_deals: Box<dyn StoreMap<Hash, u64>> = sdk.alloc_or_recover_map(TRADE_DEALS_KEY);
+        let swaps: Box<dyn StoreMap<Hash, Swap>> = sdk.alloc_or_recover_map(SWAPS_KEY);
+        let net_settlement: Box<dyn StoreBool> = sdk.alloc_or_recover_bool(NET_SETTLEMENT_KEY);
+        let asset_groups: Box<dyn StoreMap<Hash, String>> =
+            sdk.alloc_or_recover_map(ASSET_GROUPS_KEY);
+        let rounding: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(ROUNDING_KEY);
+        let settle_failures: Box<dyn StoreMap<Hash, u64>> =
+            sdk.alloc_or_recover_map(SETTLE_FAILURES_KEY);
+        let expiry_grace: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(EXPIRY_GRACE_KEY);
+        let max_expiry: Box<dyn StoreUint64> = sdk.alloc_or_recover_uint64(MAX_EXPIRY_KEY);
+        let client_orders: Box<dyn StoreMap<Hash, Hash>> =
+            sdk.alloc_or_recover_map(CLIENT_ORDERS_KEY);
+        let book_versions: Box<dyn StoreMap<Hash, u64>> =
+            sdk.alloc_or_recover_map(BOOK_VERSIONS_KEY);
 
         Self {
-            _sdk: sdk,
+            sdk,
             trades,
             buy_orders,
             sell_orders,
             history_orders,
+            order_index,
             validity,
+            pro_rata,
+            fee_rate,
+            referral_rate,
+            maker_rebate,
+            circuit_breaker,
+            max_orders_per_block,
+            order_counters,
+            order_book_snapshot,
+            snapshot_depth,
+            max_notional,
+            last_prices,
+            hook_events,
+            deal_log,
+            total_deals,
+            trade_deals,
+            swaps,
+            net_settlement,
+            asset_groups,
+            rounding,
+            settle_failures,
+            expiry_grace,
+            max_expiry,
+            client_orders,
+            book_versions,
+            correlation_id: String::new(),
+            net_deltas: None,
             asset,
         }
     }
 
     #[genesis]
     fn init_genesis(&mut self, payload: GenesisPayload) {
-        self.validity.set(payload.order_validity)
+        assert!(payload.fee_rate <= BPS_DENOMINATOR);
+        assert!(payload.referral_rate <= BPS_DENOMINATOR);
+        assert!(payload.maker_rebate <= payload.fee_rate);
+
+        self.validity.set(payload.order_validity);
+        self.pro_rata.set(payload.pro_rata);
+        self.fee_rate.set(payload.fee_rate);
+        self.referral_rate.set(payload.referral_rate);
+        self.maker_rebate.set(payload.maker_rebate);
+        self.circuit_breaker.set(payload.circuit_breaker);
+        self.max_orders_per_block.set(payload.max_orders_per_block);
+        self.order_book_snapshot.set(payload.order_book_snapshot);
+        self.snapshot_depth.set(payload.snapshot_depth);
+        self.max_notional.set(payload.max_notional);
+        self.net_settlement.set(payload.net_settlement);
+        self.rounding.set(u64::from(&payload.rounding));
+        self.expiry_grace.set(payload.expiry_grace);
+        self.max_expiry.set(payload.max_expiry);
+        for trade in payload.trades.into_iter() {
+            self.trades.insert(trade.id.clone(), trade);
+        }
+        for order in payload.orders.into_iter() {
+            assert!(self.trades.contains(&order.trade_id));
+            self.insert_order(order, 0);
+        }
+        self.sdk
+            .set_value(FEE_ACCOUNT_KEY.to_owned(), payload.fee_account);
+        self.sdk.set_value(ADMIN_KEY.to_owned(), payload.admin);
+        self.sdk
+            .set_value(NATIVE_ASSET_KEY.to_owned(), payload.native_asset)
     }
 
     #[cycles(210_00)]
@@ -121,24 +311,40 @@ impl<SDK: 'static + ServiceSDK, A: AssetFacade> DexService<SDK, A> {
         let base_asset = payload.base_asset;
         let counter_party = payload.counter_party;
 
-        if base_asset == counter_party {
+        if base_asset == counter_party
+            || self.same_asset_group(&base_asset, &counter_party)
+            || (payload.max_fee != 0 && payload.min_fee > payload.max_fee)
+        {
             return DexError::IllegalTrade.into();
         }
 
-        let trade_id = if base_asset < counter_party {
-            Hash::digest(Bytes::from(base_asset.as_hex() + &counter_party.as_hex()))
-        } else {
-            Hash::digest(Bytes::from(counter_party.as_hex() + &base_asset.as_hex()))
-        };
+        let trade_id = canonical_trade_id(&base_asset, &counter_party);
 
         if self.trades.contains(&trade_id) {
             return DexError::TradeExisted.into();
         }
 
+        // an asset unknown to the asset service is taken as having no decimals
+        let base_decimals = self.get_asset_decimals(base_asset.clone());
+        let counter_decimals = self.get_asset_decimals(counter_party.clone());
+
         let trade = Trade {
             id: trade_id.clone(),
             base_asset,
             counter_party,
+            price_rule: payload.price_rule,
+            min_notional: payload.min_notional,
+            price_scale: std::cmp::max(payload.price_scale, 1),
+            tick_size: std::cmp::max(payload.tick_size, 1),
+            lot_size: std::cmp::max(payload.lot_size, 1),
+            base_decimals,
+            counter_decimals,
+            allow_buy: true,
+            allow_sell: true,
+            validity: 0,
+            min_fee: payload.min_fee,
+            max_fee: payload.max_fee,
+            rounding: Rounding::from(self.rounding.get()),
         };
 
         self.trades.insert(trade_id, trade.clone());
@@ -147,419 +353,2470 @@ impl<SDK: 'static + ServiceSDK, A: AssetFacade> DexService<SDK, A> {
         ServiceResponse::from_succeed(())
     }
 
+    #[cycles(210_00)]
+    #[write]
+    fn update_trade_params(
+        &mut self,
+        ctx: ServiceContext,
+        payload: UpdateTradeParamsPayload,
+    ) -> ServiceResponse<()> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
+
+        let mut trade = check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+        trade.tick_size = std::cmp::max(payload.tick_size, 1);
+        trade.lot_size = std::cmp::max(payload.lot_size, 1);
+        trade.min_notional = payload.min_notional;
+        trade.allow_buy = payload.allow_buy;
+        trade.allow_sell = payload.allow_sell;
+        trade.validity = payload.validity;
+
+        self.trades.insert(payload.trade_id, trade.clone());
+        let event_json = serde_json_string!(trade);
+        ctx.emit_event("UpdateTradeParams".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
     #[read]
-    fn get_trades(&self, _ctx: ServiceContext) -> ServiceResponse<GetTradesResponse> {
-        let mut trades = Vec::<Trade>::new();
-        for (_, trade) in self.trades.iter() {
-            trades.push(trade);
+    fn find_offgrid_orders(
+        &self,
+        _ctx: ServiceContext,
+        payload: OffgridOrdersPayload,
+    ) -> ServiceResponse<FindOffgridOrdersResponse> {
+        let trade = check_get_or_return!(self.get_trade(payload.trade_id));
+
+        let orders = self.offgrid_orders(&trade);
+        ServiceResponse::from_succeed(FindOffgridOrdersResponse { orders })
+    }
+
+    // refund and cancel every order find_offgrid_orders lists
+    #[cycles(210_00)]
+    #[write]
+    fn cancel_offgrid_orders(
+        &mut self,
+        ctx: ServiceContext,
+        payload: OffgridOrdersPayload,
+    ) -> ServiceResponse<()> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
         }
 
-        ServiceResponse::from_succeed(GetTradesResponse { trades })
+        let trade = check_get_or_return!(self.get_trade(payload.trade_id));
+        for order in self.offgrid_orders(&trade).iter() {
+            let res = self.cancel(&trade, order, ctx.get_current_height());
+            if res.is_error() {
+                return res;
+            }
+            let event_json = serde_json_string!(order);
+            ctx.emit_event("CancelOrder".to_owned(), event_json);
+        }
+        ServiceResponse::from_succeed(())
     }
 
+    // disabling a trade rejects new orders on both sides. resting orders
+    // still settle unless cancel_orders is set, which refunds and cancels
+    // every one of them
     #[cycles(210_00)]
     #[write]
-    fn order(&mut self, ctx: ServiceContext, payload: OrderPayload) -> ServiceResponse<()> {
-        let trade_id = payload.trade_id;
-        if !self.trades.contains(&trade_id) {
-            return DexError::TradeNotExisted.into();
+    fn set_trade_enabled(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SetTradeEnabledPayload,
+    ) -> ServiceResponse<()> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
         }
-        if payload.expiry > ctx.get_current_height() + self.validity.get() {
-            return DexError::OrderOverdue.into();
+
+        let mut trade = check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+        trade.allow_buy = payload.enabled;
+        trade.allow_sell = payload.enabled;
+        self.trades.insert(payload.trade_id.clone(), trade.clone());
+
+        if !payload.enabled && payload.cancel_orders {
+            let mut orders = self.get_open_orders(&trade.id, OrderKind::Buy);
+            orders.extend(self.get_open_orders(&trade.id, OrderKind::Sell));
+            for order in orders.iter() {
+                let res = self.cancel(&trade, order, ctx.get_current_height());
+                if res.is_error() {
+                    return res;
+                }
+                let event_json = serde_json_string!(order);
+                ctx.emit_event("CancelOrder".to_owned(), event_json);
+            }
         }
 
-        let order = Order {
-            trade_id: trade_id.clone(),
-            tx_hash: ctx.get_tx_hash().expect("tx hash should exist"),
-            kind: payload.kind.clone(),
-            price: payload.price,
-            amount: payload.amount,
-            height: ctx.get_current_height(),
-            user: ctx.get_caller(),
-            expiry: payload.expiry,
-            status: OrderStatus::Fresh,
-            deals: Vec::new(),
-        };
+        let event_json = serde_json_string!(trade);
+        ctx.emit_event("SetTradeEnabled".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
 
-        match order.kind {
-            OrderKind::Buy => {
-                let trade = check_get_or_return!(self.get_trade(trade_id.clone()));
+    #[cycles(210_00)]
+    #[write]
+    fn set_asset_group(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SetAssetGroupPayload,
+    ) -> ServiceResponse<()> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
 
-                let lock_asset_payload = ModifyAssetPayload {
-                    asset_id: trade.base_asset,
-                    user: ctx.get_caller(),
-                    value: order.amount * order.price,
-                };
+        if payload.group.is_empty() {
+            self.asset_groups.remove(&payload.asset_id);
+        } else {
+            self.asset_groups
+                .insert(payload.asset_id.clone(), payload.group.clone());
+        }
 
-                call_and_parse_service_response!(self, lock_asset, lock_asset_payload);
-                self.buy_orders.insert(
-                    ctx.get_tx_hash().expect("tx hash should exist"),
-                    order.clone(),
-                )
-            }
-            OrderKind::Sell => {
-                let trade = check_get_or_return!(self.get_trade(trade_id.clone()));
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("SetAssetGroup".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
 
-                let lock_asset_payload = ModifyAssetPayload {
-                    asset_id: trade.counter_party,
-                    user: ctx.get_caller(),
-                    value: order.amount,
-                };
+    #[cycles(210_00)]
+    #[write]
+    fn set_fee_rate(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SetFeeRatePayload,
+    ) -> ServiceResponse<()> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
 
-                call_and_parse_service_response!(self, lock_asset, lock_asset_payload);
+        if payload.fee_rate > MAX_FEE_RATE || payload.fee_rate < self.maker_rebate.get() {
+            return DexError::IllegalFeeRate.into();
+        }
 
-                self.sell_orders.insert(
-                    ctx.get_tx_hash().expect("tx hash should exist"),
-                    order.clone(),
-                )
-            }
-        };
+        self.fee_rate.set(payload.fee_rate);
 
-        let event_json = serde_json_string!(order);
-        ctx.emit_event("Order".to_owned(), event_json);
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("FeeRateChanged".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    // max_expiry is an absolute height, so once the chain passes it every new
+    // order is rejected until the admin moves it on. orders already resting
+    // keep their expiry
+    #[cycles(210_00)]
+    #[write]
+    fn set_max_expiry(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SetMaxExpiryPayload,
+    ) -> ServiceResponse<()> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
+
+        self.max_expiry.set(payload.max_expiry);
+
+        let event_json = serde_json_string!(payload);
+        ctx.emit_event("MaxExpiryChanged".to_owned(), event_json);
         ServiceResponse::from_succeed(())
     }
 
     #[read]
-    fn get_order(
+    fn compute_trade_id(
         &self,
         _ctx: ServiceContext,
-        payload: GetOrderPayload,
-    ) -> ServiceResponse<GetOrderResponse> {
-        if let Some(order) = self.buy_orders.get(&payload.tx_hash) {
-            return ServiceResponse::from_succeed(GetOrderResponse::from_order(
-                &order,
-                DealStatus::Dealing,
-            ));
-        } else if let Some(order) = self.sell_orders.get(&payload.tx_hash) {
-            return ServiceResponse::from_succeed(GetOrderResponse::from_order(
-                &order,
-                DealStatus::Dealing,
-            ));
-        } else if let Some(order) = self.history_orders.get(&payload.tx_hash) {
-            return ServiceResponse::from_succeed(GetOrderResponse::from_order(
-                &order,
-                DealStatus::Dealt,
-            ));
-        }
-
-        DexError::OrderNotExisted.into()
-    }
-
-    #[hook_after]
-    fn match_and_deal(&mut self, params: &ExecutorParams) {
-        self.remove_expiry_orders(params.height);
-
-        let mut buy_queue = Vec::<Order>::new();
-        for (_, order) in self.buy_orders.iter() {
-            buy_queue.push(order);
+        payload: ComputeTradeIdPayload,
+    ) -> ServiceResponse<ComputeTradeIdResponse> {
+        if payload.base_asset == payload.counter_party {
+            return DexError::IllegalTrade.into();
         }
-        buy_queue.sort();
 
-        let mut sell_queue = Vec::<Order>::new();
-        for (_, order) in self.sell_orders.iter() {
-            sell_queue.push(order);
+        ServiceResponse::from_succeed(ComputeTradeIdResponse {
+            trade_id: canonical_trade_id(&payload.base_asset, &payload.counter_party),
+        })
+    }
+
+    #[read]
+    fn get_trades(&self, _ctx: ServiceContext) -> ServiceResponse<GetTradesResponse> {
+        let mut trades = Vec::<Trade>::new();
+        for (_, trade) in self.trades.iter() {
+            trades.push(trade);
         }
-        sell_queue.sort();
 
-        loop {
-            let opt_buy = buy_queue.pop();
-            let opt_sell = sell_queue.pop();
-            if opt_buy.is_none() || opt_sell.is_none() {
-                break;
-            }
-            let current_buy = opt_buy.unwrap();
-            let current_sell = opt_sell.unwrap();
-            if current_buy.price < current_sell.price {
-                break;
-            }
-            let deal_price = (current_buy.price + current_sell.price) / 2;
+        ServiceResponse::from_succeed(GetTradesResponse { trades })
+    }
 
-            let buy_left = match current_buy.status {
-                OrderStatus::Fresh => current_buy.amount,
-                OrderStatus::Partial(v) => current_buy.amount - v,
-                OrderStatus::Full => unreachable!(),
-            };
+    #[read]
+    fn get_trade_by_id(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetTradePayload,
+    ) -> ServiceResponse<Trade> {
+        self.get_trade(payload.trade_id)
+    }
 
-            let sell_left = match current_sell.status {
-                OrderStatus::Fresh => current_sell.amount,
-                OrderStatus::Partial(v) => current_sell.amount - v,
-                OrderStatus::Full => unreachable!(),
-            };
+    #[cycles(210_00)]
+    #[write]
+    fn order(&mut self, ctx: ServiceContext, payload: OrderPayload) -> ServiceResponse<()> {
+        let tx_hash = ctx.get_tx_hash().expect("tx hash should exist");
+        let res = self.new_order(&ctx, payload, tx_hash);
+        let order = check_get_or_return!(res);
 
-            if buy_left < sell_left {
-                let next_sell = self.settle_buyer(
-                    deal_price,
-                    buy_left,
-                    current_buy.clone(),
-                    current_sell.clone(),
-                );
+        let event_json = serde_json_string!(order);
+        ctx.emit_event("Order".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
 
-                if next_sell.is_error() {
-                    continue;
+    // all or nothing: when one order fails, the orders placed before it are
+    // taken off the book and their locks released
+    #[cycles(210_00)]
+    #[write]
+    fn batch_order(
+        &mut self,
+        ctx: ServiceContext,
+        payload: BatchOrderPayload,
+    ) -> ServiceResponse<BatchOrderResponse> {
+        let tx_hash = ctx.get_tx_hash().expect("tx hash should exist");
+        let counter = self.order_counters.get(&ctx.get_caller());
+
+        let mut placed = Vec::<(Order, bool)>::new();
+        for (index, order_payload) in payload.orders.into_iter().enumerate() {
+            let use_locked = order_payload.use_locked;
+            let res = self.new_order(&ctx, order_payload, derive_order_hash(&tx_hash, index));
+            if res.is_error() {
+                for (order, use_locked) in placed.iter().rev() {
+                    self.remove_order(order, ctx.get_current_height());
+                    if *use_locked {
+                        continue;
+                    }
+                    let trade = check_get_or_return!(self.get_trade(order.trade_id.clone()));
+                    let unlock_payload = ModifyAssetPayload {
+                        asset_id: trade.locked_asset(&order.kind).clone(),
+                        user: order.user.clone(),
+                        value: order.locked(&trade),
+                    };
+                    call_and_parse_service_response!(self, unlock_asset, unlock_payload);
                 }
-                sell_queue.push(next_sell.succeed_data);
-            } else if buy_left > sell_left {
-                let next_buy = self.settle_seller(
-                    deal_price,
-                    sell_left,
-                    current_buy.clone(),
-                    current_sell.clone(),
-                );
-                if next_buy.is_error() {
-                    continue;
+                if let Some(counter) = counter {
+                    self.order_counters.insert(ctx.get_caller(), counter);
+                } else {
+                    self.order_counters.remove(&ctx.get_caller());
                 }
-                buy_queue.push(next_buy.succeed_data);
-            } else {
-                self.settle_both(
-                    deal_price,
-                    buy_left,
-                    current_buy.clone(),
-                    current_sell.clone(),
-                );
+                return ServiceResponse::from_error(res.code, res.error_message);
             }
+            placed.push((res.succeed_data, use_locked));
         }
-        ()
+
+        let response = BatchOrderResponse {
+            tx_hashes: placed.into_iter().map(|(order, _)| order.tx_hash).collect(),
+        };
+        let event_json = serde_json_string!(response);
+        ctx.emit_event("BatchOrder".to_owned(), event_json);
+        ServiceResponse::from_succeed(response)
     }
 
-    fn settle_buyer(
+    // validates, locks and books one order under tx_hash
+    fn new_order(
         &mut self,
-        deal_price: u64,
-        deal_amount: u64,
-        mut current_buy: Order,
-        mut current_sell: Order,
+        ctx: &ServiceContext,
+        payload: OrderPayload,
+        tx_hash: Hash,
     ) -> ServiceResponse<Order> {
-        let trade_id = current_buy.trade_id.clone();
+        // orders are keyed by tx_hash, never let a replayed one overwrite
+        if !self.find_order(&tx_hash).is_error() {
+            return DexError::DuplicateOrder.into();
+        }
+
+        let trade_id = payload.trade_id;
         let trade = check_get_or_return!(self.get_trade(trade_id.clone()));
+        if self.is_trade_frozen(&trade) {
+            return DexError::AssetFrozen.into();
+        }
+        if !self.is_trade_tradable(&trade) {
+            return DexError::AssetNotTradable.into();
+        }
+        if !trade.allows(&payload.kind) {
+            return DexError::SideDisabled.into();
+        }
 
-        let unlock_buyer = ModifyAssetPayload {
-            asset_id: trade.base_asset.clone(),
-            user: current_buy.user.clone(),
-            value: deal_amount * current_buy.price,
+        let mut counter = self
+            .order_counters
+            .get(&ctx.get_caller())
+            .unwrap_or_default();
+        if counter.height != ctx.get_current_height() {
+            counter = OrderCounter {
+                height: ctx.get_current_height(),
+                count: 0,
+            };
+        }
+        let max_orders = self.max_orders_per_block.get();
+        if max_orders != 0 && counter.count >= max_orders {
+            return DexError::RateLimited.into();
+        }
+        if payload.price % trade.tick_size != 0 {
+            return DexError::OffTick.into();
+        }
+        if payload.amount % trade.lot_size != 0 {
+            return DexError::OffLot.into();
+        }
+        if trade.notional(payload.amount, payload.price) < trade.min_notional {
+            return DexError::BelowMinNotional.into();
+        }
+        let max_notional = self.max_notional.get();
+        if max_notional != 0 && trade.notional(payload.amount, payload.price) > max_notional {
+            return DexError::NotionalTooLarge.into();
+        }
+        // an order the whole supply couldn't fill would only sit locked.
+        // balances are left to the lock below
+        if payload.amount > self.get_asset_supply(trade.counter_party.clone())
+            || (payload.kind == OrderKind::Buy
+                && trade.notional(payload.amount, payload.price)
+                    > self.get_asset_supply(trade.base_asset.clone()))
+        {
+            return DexError::InvalidOrder.into();
+        }
+        // the ceiling holds whatever the time in force, and an order that
+        // would already be expired is never placed
+        let max_expiry = self.max_expiry.get();
+        let expiry = match payload.time_in_force {
+            TimeInForce::GoodTillDate => {
+                let height = ctx.get_current_height();
+                let latest = height + self.order_validity(&trade);
+                let expiry = match payload.expiry {
+                    Some(expiry) => expiry,
+                    None if max_expiry != 0 => std::cmp::min(latest, max_expiry),
+                    None => latest,
+                };
+                if expiry <= height || expiry > latest || (max_expiry != 0 && expiry > max_expiry) {
+                    return DexError::OrderOverdue.into();
+                }
+                expiry
+            }
+            TimeInForce::GoodTillCancel if max_expiry != 0 => return DexError::OrderOverdue.into(),
+            TimeInForce::GoodTillCancel => GOOD_TILL_CANCEL,
         };
-        call_and_parse_service_response!(self, unlock_asset, unlock_buyer);
+        // a client id freed up once its order left the book
+        let client_key = payload
+            .client_order_id
+            .as_ref()
+            .map(|client_order_id| client_order_key(&ctx.get_caller(), client_order_id));
+        if let Some(key) = client_key.as_ref() {
+            if let Some(tx_hash) = self.client_orders.get(key) {
+                if !self.get_open_order(&tx_hash).is_error() {
+                    return DexError::DuplicateOrder.into();
+                }
+            }
+        }
 
-        let add_buyer = ModifyAssetPayload {
-            asset_id: trade.counter_party.clone(),
-            user: current_buy.user.clone(),
-            value: deal_amount,
+        let order = Order {
+            trade_id: trade_id.clone(),
+            tx_hash,
+            kind: payload.kind.clone(),
+            price: payload.price,
+            amount: payload.amount,
+            height: ctx.get_current_height(),
+            user: ctx.get_caller(),
+            expiry,
+            status: OrderStatus::Fresh,
+            filled: 0,
+            deals: Vec::new(),
+            referrer: payload.referrer.clone(),
+            fees: 0,
+            hidden: payload.hidden,
         };
-        call_and_parse_service_response!(self, add_value, add_buyer);
 
-        let sub_buyer = ModifyAssetPayload {
-            asset_id: trade.base_asset.clone(),
-            user: current_buy.user.clone(),
-            value: deal_amount * deal_price,
+        // a buy locks the notional in base asset, a sell its amount of counter party
+        let lock_asset_payload = ModifyAssetPayload {
+            asset_id: trade.locked_asset(&order.kind).clone(),
+            user: ctx.get_caller(),
+            value: order.locked(&trade),
         };
-
-        call_and_parse_service_response!(self, sub_value, sub_buyer);
-
-        let unlock_seller = ModifyAssetPayload {
-            asset_id: trade.counter_party.clone(),
-            user: current_sell.user.clone(),
-            value: deal_amount,
+        let balance_payload = GetBalancePayload {
+            asset_id: lock_asset_payload.asset_id.clone(),
+            user: ctx.get_caller(),
         };
-
-        call_and_parse_service_response!(self, unlock_asset, unlock_seller);
-
-        let add_seller = ModifyAssetPayload {
-            asset_id: trade.base_asset,
-            user: current_sell.user.clone(),
-            value: deal_amount * deal_price,
+        let balance_res = self
+            .asset
+            .query_balance(self.get_call_asset_ctx(), balance_payload);
+        let balance = check_get_or_return!(balance_res);
+        if payload.reduce_only
+            && (order.kind == OrderKind::Buy || order.amount > balance.current)
+        {
+            return DexError::ReduceOnlyViolated.into();
+        }
+        let available = if payload.use_locked {
+            let accounted: u64 = self
+                .get_user_locks(&order.user, &lock_asset_payload.asset_id)
+                .iter()
+                .map(|lock| lock.locked)
+                .sum();
+            balance.locked.saturating_sub(accounted)
+        } else {
+            balance.current
         };
+        if available < lock_asset_payload.value {
+            return DexError::InsufficientFundsToLock {
+                required: lock_asset_payload.value,
+                available,
+            }
+            .into();
+        }
+        if !payload.use_locked {
+            call_and_parse_service_response!(self, lock_asset, lock_asset_payload);
+        }
+        self.insert_order(order.clone(), ctx.get_current_height());
+        if let Some(key) = client_key {
+            self.client_orders.insert(key, order.tx_hash.clone());
+        }
 
-        call_and_parse_service_response!(self, add_value, add_seller);
+        counter.count += 1;
+        self.order_counters.insert(ctx.get_caller(), counter);
 
-        let sub_seller = ModifyAssetPayload {
-            asset_id: trade.counter_party,
-            user: current_sell.user.clone(),
-            value: deal_amount,
-        };
+        ServiceResponse::from_succeed(order)
+    }
 
-        call_and_parse_service_response!(self, sub_value, sub_seller);
+    #[cycles(210_00)]
+    #[write]
+    fn market_order(
+        &mut self,
+        ctx: ServiceContext,
+        payload: MarketOrderPayload,
+    ) -> ServiceResponse<MarketOrderResponse> {
+        let trade = check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+        if self.is_trade_frozen(&trade) {
+            return DexError::AssetFrozen.into();
+        }
+        if !self.is_trade_tradable(&trade) {
+            return DexError::AssetNotTradable.into();
+        }
+        if !trade.allows(&payload.kind) {
+            return DexError::SideDisabled.into();
+        }
 
-        let settle_deal = Deal {
-            price: deal_price,
-            amount: deal_amount,
+        let avg_price_limit = match payload.kind {
+            OrderKind::Buy => payload.max_avg_price,
+            OrderKind::Sell => payload.min_avg_price,
         };
 
-        current_buy.status = OrderStatus::Full;
-        current_buy.deals.push(settle_deal.clone());
+        let height = ctx.get_current_height();
+        let mut taker = Order {
+            trade_id: trade.id.clone(),
+            tx_hash: ctx.get_tx_hash().expect("tx hash should exist"),
+            kind: payload.kind.clone(),
+            height,
+            user: ctx.get_caller(),
+            expiry: height,
+            ..Default::default()
+        };
 
-        current_sell.deals.push(settle_deal.clone());
-        let mut dealt_amount: u64 = match current_sell.status {
-            OrderStatus::Fresh => 0,
-            OrderStatus::Partial(v) => v,
-            OrderStatus::Full => panic!("should not be full"),
+        // a buy with a quote budget locks all of it up front and gets back
+        // whatever it didn't spend, every other fill is locked as it goes
+        let budget = match payload.kind {
+            OrderKind::Buy => payload.quote_amount,
+            OrderKind::Sell => None,
+        };
+        let amount = match budget {
+            Some(budget) => {
+                let lock_payload = ModifyAssetPayload {
+                    asset_id: trade.base_asset.clone(),
+                    user: taker.user.clone(),
+                    value: budget,
+                };
+                call_and_parse_service_response!(self, lock_asset, lock_payload);
+                std::u64::MAX
+            }
+            None => payload.amount,
         };
-        dealt_amount += settle_deal.amount;
-        current_sell.status = OrderStatus::Partial(dealt_amount);
 
-        self.buy_orders.remove(&current_buy.tx_hash);
-        self.history_orders
-            .insert(current_buy.tx_hash.clone(), current_buy);
+        let sweep_res = self.sweep(&trade, &mut taker, amount, budget, avg_price_limit);
+        let (deals, spent) = check_get_or_return!(sweep_res);
+        let filled: u64 = deals.iter().map(|deal| deal.amount).sum();
+        let quote: u128 = deals
+            .iter()
+            .map(|deal| deal.amount as u128 * deal.price as u128)
+            .sum();
+
+        if let Some(budget) = budget {
+            if budget != spent {
+                let refund_payload = ModifyAssetPayload {
+                    asset_id: trade.base_asset.clone(),
+                    user: taker.user.clone(),
+                    value: budget - spent,
+                };
+                call_and_parse_service_response!(self, unlock_asset, refund_payload);
+            }
+        }
 
-        self.sell_orders
-            .insert(current_sell.tx_hash.clone(), current_sell.clone());
+        // the unfilled remainder is dropped, and the fills are recorded as one order
+        let avg_price = if filled == 0 {
+            0
+        } else {
+            (quote / filled as u128) as u64
+        };
+        if filled != 0 {
+            taker.price = avg_price;
+            taker.amount = filled;
+            taker.status = OrderStatus::Full;
+            taker.filled = filled;
+            taker.deals = deals;
+            self.history_orders.insert(taker.tx_hash.clone(), taker);
+        }
 
-        ServiceResponse::from_succeed(current_sell)
+        let response = MarketOrderResponse { filled, avg_price };
+        let event_json = serde_json_string!(response);
+        ctx.emit_event("MarketOrder".to_owned(), event_json);
+        ServiceResponse::from_succeed(response)
     }
 
-    fn settle_seller(
+    #[cycles(210_00)]
+    #[write]
+    fn convert_to_market(
         &mut self,
-        deal_price: u64,
-        deal_amount: u64,
-        mut current_buy: Order,
-        mut current_sell: Order,
-    ) -> ServiceResponse<Order> {
-        let trade_id = current_buy.trade_id.clone();
-        let trade = check_get_or_return!(self.get_trade(trade_id.clone()));
-
-        let unlock_seller = ModifyAssetPayload {
-            asset_id: trade.counter_party.clone(),
-            user: current_sell.user.clone(),
-            value: deal_amount,
-        };
+        ctx: ServiceContext,
+        payload: ConvertToMarketPayload,
+    ) -> ServiceResponse<MarketOrderResponse> {
+        // a Full order has already left the book
+        let order = check_get_or_return!(self.get_open_order(&payload.tx_hash));
+        if order.user != ctx.get_caller() {
+            return DexError::PermissionDenial.into();
+        }
+        let trade = check_get_or_return!(self.get_trade(order.trade_id.clone()));
+        if self.is_trade_frozen(&trade) {
+            return DexError::AssetFrozen.into();
+        }
+        if !self.is_trade_tradable(&trade) {
+            return DexError::AssetNotTradable.into();
+        }
+        if !trade.allows(&order.kind) {
+            return DexError::SideDisabled.into();
+        }
 
-        call_and_parse_service_response!(self, unlock_asset, unlock_seller);
+        // release what the limit price locked, each fill is locked at its
+        // own price instead
+        let cancel_res = self.cancel(&trade, &order, ctx.get_current_height());
+        check_get_or_return!(cancel_res);
 
-        let add_seller = ModifyAssetPayload {
-            asset_id: trade.base_asset.clone(),
-            user: current_sell.user.clone(),
-            value: deal_amount * deal_price,
+        let avg_price_limit = match order.kind {
+            OrderKind::Buy => payload.max_avg_price,
+            OrderKind::Sell => payload.min_avg_price,
         };
-
-        call_and_parse_service_response!(self, add_value, add_seller);
-
-        let sub_seller = ModifyAssetPayload {
-            asset_id: trade.counter_party.clone(),
-            user: current_sell.user.clone(),
-            value: deal_amount,
+        // fills are taken at the current height, so the order is the taker
+        let mut taker = Order {
+            height: ctx.get_current_height(),
+            ..order.clone()
         };
+        let sweep_res = self.sweep(&trade, &mut taker, order.remaining(), None, avg_price_limit);
+        let (deals, _) = check_get_or_return!(sweep_res);
+        let filled: u64 = deals.iter().map(|deal| deal.amount).sum();
+        let quote: u128 = deals
+            .iter()
+            .map(|deal| deal.amount as u128 * deal.price as u128)
+            .sum();
+
+        // the order keeps its earlier fills and the rest stays cancelled
+        let mut converted = order;
+        for deal in deals.into_iter() {
+            converted.fill(deal);
+        }
+        converted.fees = taker.fees;
+        self.history_orders
+            .insert(converted.tx_hash.clone(), converted);
 
-        call_and_parse_service_response!(self, sub_value, sub_seller);
-
-        let unlock_buyer = ModifyAssetPayload {
-            asset_id: trade.base_asset.clone(),
-            user: current_buy.user.clone(),
-            value: deal_amount * current_buy.price,
+        let avg_price = if filled == 0 {
+            0
+        } else {
+            (quote / filled as u128) as u64
         };
+        let response = MarketOrderResponse { filled, avg_price };
+        let event_json = serde_json_string!(response);
+        ctx.emit_event("ConvertToMarket".to_owned(), event_json);
+        ServiceResponse::from_succeed(response)
+    }
 
-        call_and_parse_service_response!(self, unlock_asset, unlock_buyer);
-
-        let add_buyer = ModifyAssetPayload {
-            asset_id: trade.counter_party,
-            user: current_buy.user.clone(),
-            value: deal_amount,
-        };
+    #[cycles(210_00)]
+    #[write]
+    fn split_order(
+        &mut self,
+        ctx: ServiceContext,
+        payload: SplitOrderPayload,
+    ) -> ServiceResponse<SplitOrderResponse> {
+        let order = check_get_or_return!(self.get_open_order(&payload.tx_hash));
+        if order.user != ctx.get_caller() {
+            return DexError::PermissionDenial.into();
+        }
+        let trade = check_get_or_return!(self.get_trade(order.trade_id.clone()));
 
-        call_and_parse_service_response!(self, add_value, add_buyer);
+        let total: u128 = payload.amounts.iter().map(|amount| *amount as u128).sum();
+        if total != order.remaining() as u128 || payload.amounts.contains(&0) {
+            return DexError::IllegalSplit.into();
+        }
+        if payload.amounts.iter().any(|amount| amount % trade.lot_size != 0) {
+            return DexError::OffLot.into();
+        }
 
-        let sub_buyer = ModifyAssetPayload {
-            asset_id: trade.base_asset,
-            user: current_buy.user.clone(),
-            value: deal_amount * deal_price,
+        let parts: Vec<Order> = payload
+            .amounts
+            .iter()
+            .enumerate()
+            .map(|(index, amount)| Order {
+                tx_hash: derive_order_hash(&order.tx_hash, index),
+                amount: *amount,
+                height: ctx.get_current_height(),
+                status: OrderStatus::Fresh,
+                filled: 0,
+                deals: Vec::new(),
+                fees: 0,
+                ..order.clone()
+            })
+            .collect();
+
+        // the parts keep the original lock, except that a buy's notional is
+        // rounded part by part: dust rounding down no longer charges is
+        // released, and what rounding up adds is locked on top
+        let order_locked = order.locked(&trade);
+        let parts_locked = parts
+            .iter()
+            .fold(0u64, |sum, part| sum.saturating_add(part.locked(&trade)));
+        let diff_payload = |value: u64| ModifyAssetPayload {
+            asset_id: trade.locked_asset(&order.kind).clone(),
+            user: order.user.clone(),
+            value,
         };
+        if parts_locked < order_locked {
+            let unlock_payload = diff_payload(order_locked - parts_locked);
+            call_and_parse_service_response!(self, unlock_asset, unlock_payload);
+        } else if parts_locked > order_locked {
+            let lock_payload = diff_payload(parts_locked - order_locked);
+            call_and_parse_service_response!(self, lock_asset, lock_payload);
+        }
 
-        call_and_parse_service_response!(self, sub_value, sub_buyer);
+        self.remove_order(&order, ctx.get_current_height());
+        self.history_orders
+            .insert(order.tx_hash.clone(), order.clone());
+        let tx_hashes = parts.iter().map(|part| part.tx_hash.clone()).collect();
+        for part in parts.into_iter() {
+            self.insert_order(part, ctx.get_current_height());
+        }
 
-        let settle_deal = Deal {
-            price: deal_price,
-            amount: deal_amount,
+        let response = SplitOrderResponse { tx_hashes };
+        let event_json = serde_json_string!(response);
+        ctx.emit_event("SplitOrder".to_owned(), event_json);
+        ServiceResponse::from_succeed(response)
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn cancel_order(
+        &mut self,
+        ctx: ServiceContext,
+        payload: CancelOrderPayload,
+    ) -> ServiceResponse<()> {
+        let order = check_get_or_return!(self.get_open_order(&payload.tx_hash));
+        if order.user != ctx.get_caller() {
+            return DexError::PermissionDenial.into();
+        }
+        let trade = check_get_or_return!(self.get_trade(order.trade_id.clone()));
+        let res = self.cancel(&trade, &order, ctx.get_current_height());
+        if res.is_error() {
+            return res;
+        }
+
+        let event_json = serde_json_string!(order);
+        ctx.emit_event("CancelOrder".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn cancel_by_client_id(
+        &mut self,
+        ctx: ServiceContext,
+        payload: CancelByClientIdPayload,
+    ) -> ServiceResponse<()> {
+        let key = client_order_key(&ctx.get_caller(), &payload.client_order_id);
+        let tx_hash = match self.client_orders.get(&key) {
+            Some(tx_hash) => tx_hash,
+            None => return DexError::OrderNotExisted.into(),
+        };
+        let order = check_get_or_return!(self.get_open_order(&tx_hash));
+        let trade = check_get_or_return!(self.get_trade(order.trade_id.clone()));
+        let res = self.cancel(&trade, &order, ctx.get_current_height());
+        if res.is_error() {
+            return res;
+        }
+        self.client_orders.remove(&key);
+
+        let event_json = serde_json_string!(order);
+        ctx.emit_event("CancelOrder".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    // only a new price costs the order its time priority, amending the
+    // amount at the same price keeps its height
+    #[cycles(210_00)]
+    #[write]
+    fn amend_order(
+        &mut self,
+        ctx: ServiceContext,
+        payload: AmendOrderPayload,
+    ) -> ServiceResponse<()> {
+        let order = check_get_or_return!(self.get_open_order(&payload.tx_hash));
+        if order.user != ctx.get_caller() {
+            return DexError::PermissionDenial.into();
+        }
+        let trade = check_get_or_return!(self.get_trade(order.trade_id.clone()));
+        if self.is_trade_frozen(&trade) {
+            return DexError::AssetFrozen.into();
+        }
+        if !self.is_trade_tradable(&trade) {
+            return DexError::AssetNotTradable.into();
+        }
+        if !trade.allows(&order.kind) {
+            return DexError::SideDisabled.into();
+        }
+        if payload.amount <= order.filled {
+            return DexError::InvalidOrder.into();
+        }
+        if payload.price % trade.tick_size != 0 {
+            return DexError::OffTick.into();
+        }
+        if payload.amount % trade.lot_size != 0 {
+            return DexError::OffLot.into();
+        }
+        if trade.notional(payload.amount, payload.price) < trade.min_notional {
+            return DexError::BelowMinNotional.into();
+        }
+        let max_notional = self.max_notional.get();
+        if max_notional != 0 && trade.notional(payload.amount, payload.price) > max_notional {
+            return DexError::NotionalTooLarge.into();
+        }
+
+        let height = if payload.price == order.price {
+            order.height
+        } else {
+            ctx.get_current_height()
+        };
+        let amended = Order {
+            price: payload.price,
+            amount: payload.amount,
+            height,
+            ..order.clone()
+        };
+
+        // lock or release the difference between the two locks
+        let locked = order.locked(&trade);
+        let amended_locked = amended.locked(&trade);
+        let lock_payload = ModifyAssetPayload {
+            asset_id: trade.locked_asset(&order.kind).clone(),
+            user: order.user.clone(),
+            value: if amended_locked > locked {
+                amended_locked - locked
+            } else {
+                locked - amended_locked
+            },
+        };
+        if amended_locked > locked {
+            call_and_parse_service_response!(self, lock_asset, lock_payload);
+        } else if amended_locked < locked {
+            call_and_parse_service_response!(self, unlock_asset, lock_payload);
+        }
+        self.insert_order(amended.clone(), ctx.get_current_height());
+
+        let event_json = serde_json_string!(amended);
+        ctx.emit_event("AmendOrder".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn increase_order(
+        &mut self,
+        ctx: ServiceContext,
+        payload: IncreaseOrderPayload,
+    ) -> ServiceResponse<()> {
+        // a Full order has already left the book
+        let order = check_get_or_return!(self.get_open_order(&payload.tx_hash));
+        if order.user != ctx.get_caller() {
+            return DexError::PermissionDenial.into();
+        }
+        let trade = check_get_or_return!(self.get_trade(order.trade_id.clone()));
+        if self.is_trade_frozen(&trade) {
+            return DexError::AssetFrozen.into();
+        }
+        if !self.is_trade_tradable(&trade) {
+            return DexError::AssetNotTradable.into();
+        }
+        if !trade.allows(&order.kind) {
+            return DexError::SideDisabled.into();
+        }
+        if payload.additional_amount == 0 {
+            return DexError::InvalidOrder.into();
+        }
+        if payload.additional_amount % trade.lot_size != 0 {
+            return DexError::OffLot.into();
+        }
+        let amount = match order.amount.checked_add(payload.additional_amount) {
+            Some(amount) => amount,
+            None => return DexError::InvalidOrder.into(),
+        };
+        let max_notional = self.max_notional.get();
+        if max_notional != 0 && trade.notional(amount, order.price) > max_notional {
+            return DexError::NotionalTooLarge.into();
+        }
+
+        let increased = Order {
+            amount,
+            ..order.clone()
+        };
+        let lock_payload = ModifyAssetPayload {
+            asset_id: trade.locked_asset(&order.kind).clone(),
+            user: order.user.clone(),
+            value: increased.locked(&trade) - order.locked(&trade),
+        };
+        call_and_parse_service_response!(self, lock_asset, lock_payload);
+        self.insert_order(increased.clone(), ctx.get_current_height());
+
+        let event_json = serde_json_string!(increased);
+        ctx.emit_event("IncreaseOrder".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    // add_trade keys a pair by canonical_trade_id, so a duplicate pair only
+    // comes in through genesis, which keeps the ids of the trades it imports
+    // as given. the order keeps its lock and its time priority on the new trade
+    #[cycles(210_00)]
+    #[write]
+    fn move_order(
+        &mut self,
+        ctx: ServiceContext,
+        payload: MoveOrderPayload,
+    ) -> ServiceResponse<()> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
+
+        let order = check_get_or_return!(self.get_open_order(&payload.tx_hash));
+        let trade = check_get_or_return!(self.get_trade(order.trade_id.clone()));
+        let new_trade = check_get_or_return!(self.get_trade(payload.new_trade_id.clone()));
+        if !trade.is_compatible(&new_trade) {
+            return DexError::IllegalTrade.into();
+        }
+
+        self.remove_order(&order, ctx.get_current_height());
+        let moved = Order {
+            trade_id: new_trade.id,
+            ..order
+        };
+        self.insert_order(moved.clone(), ctx.get_current_height());
+
+        let event_json = serde_json_string!(moved);
+        ctx.emit_event("MoveOrder".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn create_swap(
+        &mut self,
+        ctx: ServiceContext,
+        payload: CreateSwapPayload,
+    ) -> ServiceResponse<Swap> {
+        if payload.offer_amount == 0
+            || payload.want_amount == 0
+            || payload.offer_asset == payload.want_asset
+            || payload.counterparty == ctx.get_caller()
+        {
+            return DexError::IllegalSwap.into();
+        }
+
+        let swap = Swap {
+            id: ctx.get_tx_hash().expect("tx hash should exist"),
+            maker: ctx.get_caller(),
+            counterparty: payload.counterparty,
+            offer_asset: payload.offer_asset,
+            offer_amount: payload.offer_amount,
+            want_asset: payload.want_asset,
+            want_amount: payload.want_amount,
+        };
+
+        let lock_payload = ModifyAssetPayload {
+            asset_id: swap.offer_asset.clone(),
+            user: swap.maker.clone(),
+            value: swap.offer_amount,
+        };
+        call_and_parse_service_response!(self, lock_asset, lock_payload);
+        self.swaps.insert(swap.id.clone(), swap.clone());
+
+        let event_json = serde_json_string!(swap);
+        ctx.emit_event("CreateSwap".to_owned(), event_json);
+        ServiceResponse::from_succeed(swap)
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn accept_swap(&mut self, ctx: ServiceContext, payload: SwapPayload) -> ServiceResponse<()> {
+        let swap = check_get_or_return!(self.get_swap(&payload.swap_id));
+        if swap.counterparty != ctx.get_caller() {
+            return DexError::PermissionDenial.into();
+        }
+
+        // take the counterparty's side first, so a short balance fails the
+        // accept before the maker's escrow is touched
+        let take_want_payload = ModifyAssetPayload {
+            asset_id: swap.want_asset.clone(),
+            user: swap.counterparty.clone(),
+            value: swap.want_amount,
+        };
+        call_and_parse_service_response!(self, sub_value, take_want_payload);
+        let give_want_payload = ModifyAssetPayload {
+            asset_id: swap.want_asset.clone(),
+            user: swap.maker.clone(),
+            value: swap.want_amount,
+        };
+        call_and_parse_service_response!(self, add_value, give_want_payload);
+
+        let give_offer_payload = SettleAssetPayload {
+            asset_id: swap.offer_asset.clone(),
+            from: swap.maker.clone(),
+            to: swap.counterparty.clone(),
+            value: swap.offer_amount,
+        };
+        call_and_parse_service_response!(self, settle_locked, give_offer_payload);
+
+        self.swaps.remove(&swap.id);
+
+        let event_json = serde_json_string!(swap);
+        ctx.emit_event("AcceptSwap".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn cancel_swap(&mut self, ctx: ServiceContext, payload: SwapPayload) -> ServiceResponse<()> {
+        let swap = check_get_or_return!(self.get_swap(&payload.swap_id));
+        if swap.maker != ctx.get_caller() {
+            return DexError::PermissionDenial.into();
+        }
+
+        let unlock_payload = ModifyAssetPayload {
+            asset_id: swap.offer_asset.clone(),
+            user: swap.maker.clone(),
+            value: swap.offer_amount,
+        };
+        call_and_parse_service_response!(self, unlock_asset, unlock_payload);
+        self.swaps.remove(&swap.id);
+
+        let event_json = serde_json_string!(swap);
+        ctx.emit_event("CancelSwap".to_owned(), event_json);
+        ServiceResponse::from_succeed(())
+    }
+
+    #[read]
+    fn get_config(&self, _ctx: ServiceContext) -> ServiceResponse<GetConfigResponse> {
+        ServiceResponse::from_succeed(GetConfigResponse {
+            order_validity: self.validity.get(),
+            pro_rata: self.pro_rata.get(),
+            fee_rate: self.fee_rate.get(),
+            referral_rate: self.referral_rate.get(),
+            maker_rebate: self.maker_rebate.get(),
+            fee_account: self.get_fee_account(),
+            circuit_breaker: self.circuit_breaker.get(),
+            admin: self
+                .sdk
+                .get_value(&ADMIN_KEY.to_owned())
+                .unwrap_or_default(),
+            max_orders_per_block: self.max_orders_per_block.get(),
+            order_book_snapshot: self.order_book_snapshot.get(),
+            snapshot_depth: self.snapshot_depth.get(),
+            native_asset: self.get_native_asset_id(),
+            max_notional: self.max_notional.get(),
+            net_settlement: self.net_settlement.get(),
+            rounding: Rounding::from(self.rounding.get()),
+            expiry_grace: self.expiry_grace.get(),
+            max_expiry: self.max_expiry.get(),
+        })
+    }
+
+    #[read]
+    fn get_native_asset(&self, _ctx: ServiceContext) -> ServiceResponse<GetNativeAssetResponse> {
+        ServiceResponse::from_succeed(GetNativeAssetResponse {
+            asset_id: self.get_native_asset_id(),
+        })
+    }
+
+    #[read]
+    fn get_order(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetOrderPayload,
+    ) -> ServiceResponse<GetOrderResponse> {
+        let (order, status) = check_get_or_return!(self.find_order(&payload.tx_hash));
+
+        let mut response = GetOrderResponse::from_order(&order, status);
+        if !payload.with_deals {
+            response.deals.clear();
+        }
+        ServiceResponse::from_succeed(response)
+    }
+
+    #[read]
+    fn get_order_deals(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetOrderDealsPayload,
+    ) -> ServiceResponse<GetOrderDealsResponse> {
+        let (order, _) = check_get_or_return!(self.find_order(&payload.tx_hash));
+
+        let total = order.deals.len() as u64;
+        let deals = order
+            .deals
+            .into_iter()
+            .skip(payload.start as usize)
+            .take(payload.limit as usize)
+            .collect();
+        ServiceResponse::from_succeed(GetOrderDealsResponse { deals, total })
+    }
+
+    #[read]
+    fn export_state(
+        &self,
+        ctx: ServiceContext,
+        payload: ExportStatePayload,
+    ) -> ServiceResponse<ExportStateResponse> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
+
+        let mut trades: Vec<Trade> = self.trades.iter().map(|(_, trade)| trade).collect();
+        trades.sort_by_key(|trade| trade.id.as_hex());
+        let mut orders = Vec::<Order>::new();
+        for trade in trades.iter() {
+            let mut open = self.get_open_orders(&trade.id, OrderKind::Buy);
+            open.extend(self.get_open_orders(&trade.id, OrderKind::Sell));
+            open.sort_by_key(|order| order.tx_hash.as_hex());
+            orders.extend(open);
+        }
+
+        let total = (trades.len() + orders.len()) as u64;
+        let start = payload.start as usize;
+        let limit = payload.limit as usize;
+        let skipped_trades = std::cmp::min(start, trades.len());
+        let trades: Vec<Trade> = trades.into_iter().skip(start).take(limit).collect();
+        let orders = orders
+            .into_iter()
+            .skip(start - skipped_trades)
+            .take(limit - trades.len())
+            .collect();
+        ServiceResponse::from_succeed(ExportStateResponse {
+            trades,
+            orders,
+            total,
+        })
+    }
+
+    #[read]
+    fn get_order_receipt(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetOrderReceiptPayload,
+    ) -> ServiceResponse<GetOrderReceiptResponse> {
+        let (order, _) = check_get_or_return!(self.find_order(&payload.tx_hash));
+
+        let filled: u64 = order.deals.iter().map(|deal| deal.amount).sum();
+        let quote: u128 = order
+            .deals
+            .iter()
+            .map(|deal| deal.amount as u128 * deal.price as u128)
+            .sum();
+        let avg_price = if filled == 0 {
+            0
+        } else {
+            (quote / filled as u128) as u64
+        };
+
+        ServiceResponse::from_succeed(GetOrderReceiptResponse {
+            tx_hash: order.tx_hash,
+            status: order.status,
+            filled,
+            avg_price,
+            fees: order.fees,
+            deal_heights: order.deals.iter().map(|deal| deal.height).collect(),
+        })
+    }
+
+    // the price two open orders would deal at if they were matched now
+    #[read]
+    fn compute_deal_price(
+        &self,
+        _ctx: ServiceContext,
+        payload: ComputeDealPricePayload,
+    ) -> ServiceResponse<ComputeDealPriceResponse> {
+        let buy = check_get_or_return!(self.get_open_order(&payload.buy_tx_hash));
+        let sell = check_get_or_return!(self.get_open_order(&payload.sell_tx_hash));
+        if buy.kind != OrderKind::Buy
+            || sell.kind != OrderKind::Sell
+            || buy.trade_id != sell.trade_id
+        {
+            return DexError::OrderMismatch.into();
+        }
+        if buy.price < sell.price {
+            return DexError::NotCrossed.into();
+        }
+
+        let trade = check_get_or_return!(self.get_trade(buy.trade_id.clone()));
+        ServiceResponse::from_succeed(ComputeDealPriceResponse {
+            trade_id: trade.id.clone(),
+            deal_price: trade.price_rule.deal_price(&buy, &sell),
+        })
+    }
+
+    #[read]
+    fn get_history(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetHistoryPayload,
+    ) -> ServiceResponse<GetHistoryResponse> {
+        let orders = self
+            .history_orders
+            .iter()
+            .map(|(_, order)| order)
+            .filter(|order| payload.user.as_ref().map_or(true, |user| &order.user == user))
+            .skip(payload.start as usize)
+            .take(payload.limit as usize)
+            .map(|order| GetOrderResponse::from_order(&order, DealStatus::Dealt))
+            .collect();
+
+        ServiceResponse::from_succeed(GetHistoryResponse { orders })
+    }
+
+    // drop history orders placed before a height, and hook events raised
+    // before it
+    #[cycles(210_00)]
+    #[write]
+    fn prune_history(
+        &mut self,
+        ctx: ServiceContext,
+        payload: PruneHistoryPayload,
+    ) -> ServiceResponse<PruneHistoryResponse> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
+
+        let mut stale = Vec::<Hash>::new();
+        for (tx_hash, order) in self.history_orders.iter() {
+            let open = self.buy_orders.contains(tx_hash) || self.sell_orders.contains(tx_hash);
+            if order.height < payload.before_height && !open {
+                stale.push(tx_hash.clone());
+            }
+        }
+        for tx_hash in stale.iter() {
+            self.history_orders.remove(tx_hash);
+        }
+
+        // the log is in height order, so the stale events are a prefix of it
+        let mut pruned_hook_events = 0;
+        while let Some(event) = self.hook_events.get(0) {
+            if event.height >= payload.before_height {
+                break;
+            }
+            self.hook_events.remove(0);
+            pruned_hook_events += 1;
+        }
+
+        let response = PruneHistoryResponse {
+            pruned: stale.len() as u64,
+            pruned_hook_events,
+        };
+        let event_json = serde_json_string!(response);
+        ctx.emit_event("PruneHistory".to_owned(), event_json);
+        ServiceResponse::from_succeed(response)
+    }
+
+    #[read]
+    fn get_deals_by_range(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetDealsByRangePayload,
+    ) -> ServiceResponse<GetDealsResponse> {
+        if payload.from_height > payload.to_height {
+            return DexError::IllegalHeightRange.into();
+        }
+
+        let mut deals = Vec::<DealRecord>::new();
+        for index in (0..self.deal_log.len()).rev() {
+            if deals.len() as u64 >= payload.limit {
+                break;
+            }
+
+            let deal = self.deal_log.get(index).expect("deal log should be continuous");
+            // the log is in settlement order, so older deals only get older
+            if deal.height < payload.from_height {
+                break;
+            }
+            if deal.trade_id != payload.trade_id || deal.height > payload.to_height {
+                continue;
+            }
+            deals.push(deal);
+        }
+
+        ServiceResponse::from_succeed(GetDealsResponse { deals })
+    }
+
+    #[read]
+    fn get_24h_stats(
+        &self,
+        ctx: ServiceContext,
+        payload: Get24hStatsPayload,
+    ) -> ServiceResponse<Get24hStatsResponse> {
+        let height = ctx.get_current_height();
+        let from_height = (height + 1).saturating_sub(payload.blocks_per_day);
+
+        let mut stats = Get24hStatsResponse::default();
+        for index in (0..self.deal_log.len()).rev() {
+            let deal = self.deal_log.get(index).expect("deal log should be continuous");
+            // the log is in settlement order, so older deals only get older
+            if deal.height < from_height {
+                break;
+            }
+            if deal.trade_id != payload.trade_id || deal.height > height {
+                continue;
+            }
+
+            if stats.deals == 0 {
+                stats.close = deal.price;
+                stats.low = deal.price;
+            }
+            stats.open = deal.price;
+            stats.high = std::cmp::max(stats.high, deal.price);
+            stats.low = std::cmp::min(stats.low, deal.price);
+            stats.volume = stats.volume.saturating_add(deal.amount);
+            stats.deals += 1;
+        }
+
+        ServiceResponse::from_succeed(stats)
+    }
+
+    // newest first, across every trade
+    #[read]
+    fn get_user_fills(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetUserFillsPayload,
+    ) -> ServiceResponse<GetDealsResponse> {
+        let mut deals = Vec::<DealRecord>::new();
+        for index in (0..self.deal_log.len()).rev() {
+            if deals.len() as u64 >= payload.limit {
+                break;
+            }
+
+            let deal = self.deal_log.get(index).expect("deal log should be continuous");
+            if deal.maker == payload.user || deal.taker == payload.user {
+                deals.push(deal);
+            }
+        }
+
+        ServiceResponse::from_succeed(GetDealsResponse { deals })
+    }
+
+    #[read]
+    fn get_locked_breakdown(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetLockedBreakdownPayload,
+    ) -> ServiceResponse<GetLockedBreakdownResponse> {
+        let locks = self.get_user_locks(&payload.user, &payload.asset_id);
+        let total = locks.iter().map(|lock| lock.locked).sum();
+
+        ServiceResponse::from_succeed(GetLockedBreakdownResponse {
+            asset_id: payload.asset_id,
+            locks,
+            total,
+        })
+    }
+
+    // compares what open orders and swaps should hold locked on an asset
+    // with the total the asset service has locked on it
+    #[read]
+    fn audit_locks(
+        &self,
+        _ctx: ServiceContext,
+        payload: AuditLocksPayload,
+    ) -> ServiceResponse<AuditLocksResponse> {
+        let expected = self
+            .get_asset_locks(&payload.asset_id)
+            .iter()
+            .map(|(_, lock)| lock.locked as u128)
+            .sum::<u128>();
+        let locked_payload = GetAssetPayload {
+            id: payload.asset_id.clone(),
+        };
+        let locked_res = self
+            .asset
+            .query_locked_total(self.get_call_asset_ctx(), locked_payload);
+        let locked = check_get_or_return!(locked_res);
+
+        ServiceResponse::from_succeed(AuditLocksResponse {
+            asset_id: payload.asset_id,
+            expected: std::cmp::min(expected, std::u64::MAX as u128) as u64,
+            locked,
+            balanced: expected == locked as u128,
+        })
+    }
+
+    // there is no locked total kept per asset, so this scans every open
+    // order and swap
+    #[read]
+    fn get_tvl(&self, _ctx: ServiceContext) -> ServiceResponse<GetTvlResponse> {
+        let mut assets = Vec::<AssetTvl>::new();
+        let mut add = |asset_id: &Hash, locked: u64| {
+            match assets.iter_mut().find(|tvl| &tvl.asset_id == asset_id) {
+                Some(tvl) => tvl.locked = tvl.locked.saturating_add(locked),
+                None => assets.push(AssetTvl {
+                    asset_id: asset_id.clone(),
+                    locked,
+                }),
+            }
+        };
+
+        for (_, trade) in self.trades.iter() {
+            for kind in [OrderKind::Buy, OrderKind::Sell].iter() {
+                for order in self.get_open_orders(&trade.id, kind.clone()) {
+                    add(trade.locked_asset(kind), order.locked(&trade));
+                }
+            }
+        }
+        for (_, swap) in self.swaps.iter() {
+            add(&swap.offer_asset, swap.offer_amount);
+        }
+
+        ServiceResponse::from_succeed(GetTvlResponse { assets })
+    }
+
+    #[read]
+    fn get_open_interest(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetOpenInterestPayload,
+    ) -> ServiceResponse<GetOpenInterestResponse> {
+        let trade = check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        let mut interest = GetOpenInterestResponse {
+            trade_id: payload.trade_id.clone(),
+            ..Default::default()
+        };
+        for order in self.get_open_orders(&payload.trade_id, OrderKind::Buy) {
+            interest.buy_amount += order.remaining();
+            interest.buy_notional += trade.notional(order.remaining(), order.price);
+        }
+        for order in self.get_open_orders(&payload.trade_id, OrderKind::Sell) {
+            interest.sell_amount += order.remaining();
+            interest.sell_notional += trade.notional(order.remaining(), order.price);
+        }
+
+        ServiceResponse::from_succeed(interest)
+    }
+
+    #[read]
+    fn get_order_book(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetOrderBookPayload,
+    ) -> ServiceResponse<OrderBook> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        ServiceResponse::from_succeed(self.order_book(&payload.trade_id, payload.depth))
+    }
+
+    // whether the best bid reaches the best ask, i.e. the book would match
+    #[read]
+    fn is_crossed(
+        &self,
+        _ctx: ServiceContext,
+        payload: IsCrossedPayload,
+    ) -> ServiceResponse<IsCrossedResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        let crossed = match self.best_prices(&payload.trade_id) {
+            (Some(best_bid), Some(best_ask)) => best_bid >= best_ask,
+            _ => false,
+        };
+        ServiceResponse::from_succeed(IsCrossedResponse { crossed })
+    }
+
+    // every trade whose best bid reaches its best ask. matching clears those,
+    // so a book still listed after a block has liquidity stuck in it
+    #[read]
+    fn detect_crossed_books(
+        &self,
+        _ctx: ServiceContext,
+    ) -> ServiceResponse<DetectCrossedBooksResponse> {
+        let mut books = Vec::new();
+        for (trade_id, _) in self.trades.iter() {
+            if let (Some(best_bid), Some(best_ask)) = self.best_prices(trade_id) {
+                if best_bid >= best_ask {
+                    books.push(CrossedBook {
+                        trade_id: trade_id.clone(),
+                        best_bid,
+                        best_ask,
+                    });
+                }
+            }
+        }
+        books.sort_by_key(|book| book.trade_id.as_hex());
+        ServiceResponse::from_succeed(DetectCrossedBooksResponse { books })
+    }
+
+    // whether the order, once placed, would be the new best price of its
+    // side. a hidden order never shows as the best price
+    #[read]
+    fn is_price_improving(
+        &self,
+        _ctx: ServiceContext,
+        payload: OrderPayload,
+    ) -> ServiceResponse<IsPriceImprovingResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        let (best_bid, best_ask) = self.best_prices(&payload.trade_id);
+        let improving = !payload.hidden
+            && match payload.kind {
+                OrderKind::Buy => best_bid.map_or(true, |best_bid| payload.price > best_bid),
+                OrderKind::Sell => best_ask.map_or(true, |best_ask| payload.price < best_ask),
+            };
+        ServiceResponse::from_succeed(IsPriceImprovingResponse { improving })
+    }
+
+    #[read]
+    fn get_level_counts(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetLevelCountsPayload,
+    ) -> ServiceResponse<GetLevelCountsResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        let count = |kind: OrderKind| self.price_levels(&payload.trade_id, kind, 0).len() as u64;
+        ServiceResponse::from_succeed(GetLevelCountsResponse {
+            bids: count(OrderKind::Buy),
+            asks: count(OrderKind::Sell),
+        })
+    }
+
+    // a book cached at this height or later is still current
+    #[read]
+    fn get_book_version(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetBookVersionPayload,
+    ) -> ServiceResponse<GetBookVersionResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        ServiceResponse::from_succeed(GetBookVersionResponse {
+            height: self.book_versions.get(&payload.trade_id).unwrap_or(0),
+            trade_id: payload.trade_id,
+        })
+    }
+
+    // walk the book the way match_trade would without settling anything.
+    // pro rata fills and the circuit breaker are not modelled, so this is an
+    // estimate rather than a prediction
+    #[read]
+    fn estimate_match_cost(
+        &self,
+        _ctx: ServiceContext,
+        payload: EstimateMatchCostPayload,
+    ) -> ServiceResponse<EstimateMatchCostResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        let mut buy_queue = self.get_open_orders(&payload.trade_id, OrderKind::Buy);
+        buy_queue.sort();
+        let mut sell_queue = self.get_open_orders(&payload.trade_id, OrderKind::Sell);
+        sell_queue.sort();
+
+        let mut crossing = Vec::<Hash>::new();
+        let mut settles = 0u64;
+        while let (Some(mut buy), Some(mut sell)) = (buy_queue.pop(), sell_queue.pop()) {
+            if buy.price < sell.price {
+                break;
+            }
+
+            let deal = Deal {
+                price: sell.price,
+                amount: std::cmp::min(buy.remaining(), sell.remaining()),
+                height: 0,
+            };
+            for order in [&buy, &sell].iter() {
+                if !crossing.contains(&order.tx_hash) {
+                    crossing.push(order.tx_hash.clone());
+                }
+            }
+            buy.fill(deal.clone());
+            sell.fill(deal);
+            settles += 1;
+
+            if buy.status != OrderStatus::Full {
+                buy_queue.push(buy);
+            }
+            if sell.status != OrderStatus::Full {
+                sell_queue.push(sell);
+            }
+        }
+
+        ServiceResponse::from_succeed(EstimateMatchCostResponse {
+            crossing_orders: crossing.len() as u64,
+            settles,
+            cycles: settles.saturating_mul(SETTLE_CYCLES),
+        })
+    }
+
+    #[read]
+    fn get_fill_price_for_quantity(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetFillPricePayload,
+    ) -> ServiceResponse<GetFillPriceResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        let response = self.fill_price(&payload.trade_id, &payload.kind, payload.quantity, None);
+        ServiceResponse::from_succeed(response)
+    }
+
+    // how far the order would fill from the reference price before it is
+    // placed, see EstimateSlippageResponse
+    #[read]
+    fn estimate_slippage(
+        &self,
+        _ctx: ServiceContext,
+        payload: OrderPayload,
+    ) -> ServiceResponse<EstimateSlippageResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        let fill = self.fill_price(
+            &payload.trade_id,
+            &payload.kind,
+            payload.amount,
+            Some(payload.price),
+        );
+        let reference_price = self.reference_price(&payload.trade_id).price;
+        let adverse = match payload.kind {
+            OrderKind::Buy => fill.avg_price.saturating_sub(reference_price),
+            OrderKind::Sell => reference_price.saturating_sub(fill.avg_price),
+        };
+        let slippage_bps = if fill.filled == 0 || reference_price == 0 {
+            0
+        } else {
+            (adverse as u128 * BPS_DENOMINATOR as u128 / reference_price as u128) as u64
         };
-        current_sell.status = OrderStatus::Full;
-        current_sell.deals.push(settle_deal.clone());
+        ServiceResponse::from_succeed(EstimateSlippageResponse {
+            avg_price: fill.avg_price,
+            reference_price,
+            slippage_bps,
+            sufficient: fill.sufficient,
+        })
+    }
+
+    // the queue match_trade works through, in the order it takes from it
+    #[read]
+    fn get_priority_queue(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetPriorityQueuePayload,
+    ) -> ServiceResponse<GetPriorityQueueResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        let mut queue = self.get_open_orders(&payload.trade_id, payload.kind);
+        queue.sort();
+        let orders = queue
+            .iter()
+            .rev()
+            .map(|order| QueuedOrder {
+                tx_hash: order.tx_hash.clone(),
+                price: order.price,
+                amount: order.amount,
+                remaining: order.remaining(),
+                height: order.height,
+            })
+            .collect();
+
+        ServiceResponse::from_succeed(GetPriorityQueueResponse { orders })
+    }
+
+    #[read]
+    fn get_reference_price(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetReferencePricePayload,
+    ) -> ServiceResponse<GetReferencePriceResponse> {
+        check_get_or_return!(self.get_trade(payload.trade_id.clone()));
+
+        ServiceResponse::from_succeed(self.reference_price(&payload.trade_id))
+    }
+
+    // trades in which the user has at least one open order
+    #[read]
+    fn get_user_trades(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetUserTradesPayload,
+    ) -> ServiceResponse<GetUserTradesResponse> {
+        let mut trade_ids = Vec::<Hash>::new();
+        for (trade_id, _) in self.trades.iter() {
+            let active = [OrderKind::Buy, OrderKind::Sell].iter().any(|kind| {
+                self.get_open_orders(trade_id, kind.clone())
+                    .iter()
+                    .any(|order| order.user == payload.user)
+            });
+            if active {
+                trade_ids.push(trade_id.clone());
+            }
+        }
+
+        ServiceResponse::from_succeed(GetUserTradesResponse { trade_ids })
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn reconcile_locks(
+        &mut self,
+        ctx: ServiceContext,
+        payload: ReconcileLocksPayload,
+    ) -> ServiceResponse<ReconcileLocksResponse> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
+
+        let expected: u64 = self
+            .get_user_locks(&payload.user, &payload.asset_id)
+            .iter()
+            .map(|lock| lock.locked)
+            .sum();
+
+        let balance_payload = GetBalancePayload {
+            asset_id: payload.asset_id.clone(),
+            user: payload.user.clone(),
+        };
+        let balance_res = self
+            .asset
+            .query_balance(self.get_call_asset_ctx(), balance_payload);
+        let balance = check_get_or_return!(balance_res);
+
+        let mut unlocked = 0;
+        if balance.locked > expected {
+            unlocked = balance.locked - expected;
+            let unlock_payload = ModifyAssetPayload {
+                asset_id: payload.asset_id.clone(),
+                user: payload.user.clone(),
+                value: unlocked,
+            };
+            call_and_parse_service_response!(self, unlock_asset, unlock_payload);
+        }
+
+        let response = ReconcileLocksResponse {
+            user: payload.user,
+            asset_id: payload.asset_id,
+            expected,
+            unlocked,
+        };
+        let event_json = serde_json_string!(response);
+        ctx.emit_event("ReconcileLocks".to_owned(), event_json);
+        ServiceResponse::from_succeed(response)
+    }
+
+    #[read]
+    fn get_stats(&self, _ctx: ServiceContext) -> ServiceResponse<GetStatsResponse> {
+        let mut trade_deals = Vec::<TradeDealCount>::new();
+        for (trade_id, deals) in self.trade_deals.iter() {
+            trade_deals.push(TradeDealCount {
+                trade_id: trade_id.clone(),
+                deals,
+            });
+        }
+
+        ServiceResponse::from_succeed(GetStatsResponse {
+            total_deals: self.total_deals.get(),
+            trade_deals,
+        })
+    }
+
+    #[read]
+    fn get_hook_events(
+        &self,
+        _ctx: ServiceContext,
+        payload: GetHookEventsPayload,
+    ) -> ServiceResponse<GetHookEventsResponse> {
+        let mut events = Vec::<HookEvent>::new();
+        for index in (0..self.hook_events.len()).rev() {
+            let event = self
+                .hook_events
+                .get(index)
+                .expect("hook event log should be continuous");
+            if event.height < payload.height {
+                break;
+            }
+            if event.height == payload.height {
+                events.push(event);
+            }
+        }
+        events.reverse();
+
+        ServiceResponse::from_succeed(GetHookEventsResponse { events })
+    }
+
+    #[cycles(210_00)]
+    #[write]
+    fn force_match(&mut self, ctx: ServiceContext) -> ServiceResponse<()> {
+        if !self.is_admin(&ctx) {
+            return DexError::PermissionDenial.into();
+        }
+
+        // the hook runs for free, so pay for every order the round may touch
+        let mut orders = 0;
+        for (_, trade) in self.trades.iter() {
+            orders += self.get_open_orders(&trade.id, OrderKind::Buy).len() as u64;
+            orders += self.get_open_orders(&trade.id, OrderKind::Sell).len() as u64;
+        }
+        if ctx.sub_cycles(orders.saturating_mul(FORCE_MATCH_ORDER_CYCLES)).is_err() {
+            return DexError::OutOfCycles.into();
+        }
+
+        self.matching_round(ctx.get_current_height());
+        ServiceResponse::from_succeed(())
+    }
+
+    #[hook_after]
+    fn match_and_deal(&mut self, params: &ExecutorParams) {
+        self.matching_round(params.height);
+    }
+
+    // every node has to emit the same events in the same order, so nothing
+    // here follows the iteration order of a store map: expired orders are
+    // swept by tx_hash, then trades are matched and snapshotted by id
+    fn matching_round(&mut self, height: u64) {
+        let expired = self.remove_expiry_orders(height);
+
+        let mut trades = Vec::<Trade>::new();
+        for (_, trade) in self.trades.iter() {
+            trades.push(trade);
+        }
+        trades.sort_by_key(|trade| trade.id.as_hex());
+
+        let mut round = MatchingRoundEvent {
+            height,
+            expired,
+            ..MatchingRoundEvent::default()
+        };
+        if self.net_settlement.get() {
+            self.net_deltas = Some(Vec::new());
+        }
+        for trade in trades.iter() {
+            let assets_res = self.check_trade_assets(trade);
+            if assets_res.is_error() {
+                self.invalidate_orders(trade, height, assets_res.error_message);
+                continue;
+            }
+            if self.is_trade_frozen(trade) {
+                continue;
+            }
+
+            let mut orders = self.get_open_orders(&trade.id, OrderKind::Buy);
+            orders.extend(self.get_open_orders(&trade.id, OrderKind::Sell));
+            let deals_before = self.trade_deals.get(&trade.id).unwrap_or(0);
+
+            self.match_trade(trade, height);
+
+            round.considered += orders.len() as u64;
+            round.matched += orders
+                .iter()
+                .filter(|order| {
+                    let (current, _) = self.find_order(&order.tx_hash).succeed_data;
+                    current.filled != order.filled
+                })
+                .count() as u64;
+            let deals = self.trade_deals.get(&trade.id).unwrap_or(0) - deals_before;
+            if deals != 0 {
+                round.trade_fills.push(TradeDealCount {
+                    trade_id: trade.id.clone(),
+                    deals,
+                });
+            }
+        }
+        if let Some(deltas) = self.net_deltas.take() {
+            self.apply_net_deltas(deltas, height);
+        }
+        // an idle round leaves nothing worth keeping in the hook event log
+        if round.matched != 0 || round.expired != 0 {
+            self.emit_hook_event(height, "MatchingRound", &round);
+        }
+
+        if self.order_book_snapshot.get() {
+            self.snapshot_order_books(&trades, height);
+        }
+    }
+
+    fn snapshot_order_books(&mut self, trades: &[Trade], height: u64) {
+        let depth = self.snapshot_depth.get();
+        for trade in trades.iter() {
+            let book = self.order_book(&trade.id, depth);
+            if book.buys.is_empty() && book.sells.is_empty() {
+                continue;
+            }
+            self.emit_hook_event(height, "OrderBookSnapshot", &book);
+        }
+    }
+
+    fn order_book(&self, trade_id: &Hash, depth: u64) -> OrderBook {
+        OrderBook {
+            trade_id: trade_id.clone(),
+            buys: self.price_levels(trade_id, OrderKind::Buy, depth),
+            sells: self.price_levels(trade_id, OrderKind::Sell, depth),
+        }
+    }
+
+    // the best bid and the best ask of a trade, if any
+    fn best_prices(&self, trade_id: &Hash) -> (Option<u64>, Option<u64>) {
+        let best_bid = self
+            .price_levels(trade_id, OrderKind::Buy, 1)
+            .first()
+            .map(|level| level.price);
+        let best_ask = self
+            .price_levels(trade_id, OrderKind::Sell, 1)
+            .first()
+            .map(|level| level.price);
+        (best_bid, best_ask)
+    }
+
+    fn offgrid_orders(&self, trade: &Trade) -> Vec<Order> {
+        let mut orders = self.get_open_orders(&trade.id, OrderKind::Buy);
+        orders.extend(self.get_open_orders(&trade.id, OrderKind::Sell));
+        orders
+            .into_iter()
+            .filter(|order| order.price % trade.tick_size != 0)
+            .collect()
+    }
+
+    fn reference_price(&self, trade_id: &Hash) -> GetReferencePriceResponse {
+        match self.best_prices(trade_id) {
+            (Some(best_bid), Some(best_ask)) => GetReferencePriceResponse {
+                price: ((best_bid as u128 + best_ask as u128) / 2) as u64,
+                source: PriceSource::BookMid,
+            },
+            _ => match self.last_prices.get(trade_id) {
+                Some(last_price) => GetReferencePriceResponse {
+                    price: last_price,
+                    source: PriceSource::LastTrade,
+                },
+                None => GetReferencePriceResponse::default(),
+            },
+        }
+    }
+
+    // fill quantity of an order of `kind` from the visible opposite book, at
+    // the resting prices and no further than limit_price
+    fn fill_price(
+        &self,
+        trade_id: &Hash,
+        kind: &OrderKind,
+        quantity: u64,
+        limit_price: Option<u64>,
+    ) -> GetFillPriceResponse {
+        let opposite = match kind {
+            OrderKind::Buy => OrderKind::Sell,
+            OrderKind::Sell => OrderKind::Buy,
+        };
+        let mut filled = 0u64;
+        let mut quote = 0u128;
+        for level in self.price_levels(trade_id, opposite, 0).iter() {
+            let beyond_limit = limit_price.map_or(false, |limit_price| match kind {
+                OrderKind::Buy => level.price > limit_price,
+                OrderKind::Sell => level.price < limit_price,
+            });
+            if filled == quantity || beyond_limit {
+                break;
+            }
+            let amount = std::cmp::min(level.amount, quantity - filled);
+            filled += amount;
+            quote += amount as u128 * level.price as u128;
+        }
+
+        let avg_price = if filled == 0 {
+            0
+        } else {
+            (quote / filled as u128) as u64
+        };
+        GetFillPriceResponse {
+            filled,
+            avg_price,
+            sufficient: filled == quantity,
+        }
+    }
+
+    // sum the remaining amount of a side's visible open orders per price, best first
+    fn price_levels(&self, trade_id: &Hash, kind: OrderKind, depth: u64) -> Vec<PriceLevel> {
+        let mut orders: Vec<Order> = self
+            .get_open_orders(trade_id, kind)
+            .into_iter()
+            .filter(|order| !order.hidden)
+            .collect();
+        orders.sort();
+
+        let mut levels = Vec::<PriceLevel>::new();
+        for order in orders.iter().rev() {
+            match levels.last_mut() {
+                Some(level) if level.price == order.price => level.amount += order.remaining(),
+                _ => {
+                    if depth != 0 && levels.len() as u64 == depth {
+                        break;
+                    }
+                    levels.push(PriceLevel {
+                        price: order.price,
+                        amount: order.remaining(),
+                    });
+                }
+            }
+        }
+        levels
+    }
+
+    fn match_trade(&mut self, trade: &Trade, height: u64) {
+        let mut buy_queue = self.get_open_orders(&trade.id, OrderKind::Buy);
+        buy_queue.sort();
+
+        let mut sell_queue = self.get_open_orders(&trade.id, OrderKind::Sell);
+        sell_queue.sort();
+
+        loop {
+            let opt_buy = buy_queue.pop();
+            let opt_sell = sell_queue.pop();
+            if opt_buy.is_none() || opt_sell.is_none() {
+                break;
+            }
+            let current_buy = opt_buy.unwrap();
+            let current_sell = opt_sell.unwrap();
+            if current_buy.price < current_sell.price {
+                break;
+            }
+
+            // halt the trade for this block rather than deal far away from the last price
+            let deal_price = trade.price_rule.deal_price(&current_buy, &current_sell);
+            let last_price = self.last_prices.get(&trade.id).unwrap_or(0);
+            if exceeds_deviation(last_price, deal_price, self.circuit_breaker.get()) {
+                let event = CircuitBreakerEvent {
+                    trade_id: trade.id.clone(),
+                    last_price,
+                    deal_price,
+                };
+                self.emit_hook_event(height, "CircuitBreaker", &event);
+                break;
+            }
+
+            if self.pro_rata.get() {
+                // the later order takes from every resting order sharing the maker's price
+                if current_buy.height < current_sell.height {
+                    let level = Self::pop_level(&mut buy_queue, current_buy);
+                    let (next_sell, rest) =
+                        self.match_pro_rata(trade, current_sell, level, height);
+                    buy_queue.extend(rest);
+                    sell_queue.extend(next_sell);
+                } else {
+                    let level = Self::pop_level(&mut sell_queue, current_sell);
+                    let (next_buy, rest) =
+                        self.match_pro_rata(trade, current_buy, level, height);
+                    sell_queue.extend(rest);
+                    buy_queue.extend(next_buy);
+                }
+                buy_queue.sort();
+                sell_queue.sort();
+                continue;
+            }
+
+            let deal_amount = std::cmp::min(current_buy.remaining(), current_sell.remaining());
+
+            let res = self.settle(
+                deal_price,
+                deal_amount,
+                height,
+                current_buy.clone(),
+                current_sell.clone(),
+            );
+            if res.is_error() {
+                self.settle_failed(trade, &current_buy, &current_sell, height);
+                continue;
+            }
+
+            let (next_buy, next_sell) = res.succeed_data;
+            if next_buy.status != OrderStatus::Full {
+                buy_queue.push(next_buy);
+            }
+            if next_sell.status != OrderStatus::Full {
+                sell_queue.push(next_sell);
+            }
+        }
+    }
+
+    // pop every order sharing the price of `first`, keeping their priority order
+    fn pop_level(queue: &mut Vec<Order>, first: Order) -> Vec<Order> {
+        let price = first.price;
+        let mut level = vec![first];
+        while queue.last().map_or(false, |order| order.price == price) {
+            level.push(queue.pop().unwrap());
+        }
+        level
+    }
+
+    // fill the taker against a price level, splitting the fill across the
+    // level in proportion to each resting order's remaining amount.
+    // returns what is left of the taker and of the level
+    fn match_pro_rata(
+        &mut self,
+        trade: &Trade,
+        mut taker: Order,
+        level: Vec<Order>,
+        height: u64,
+    ) -> (Option<Order>, Vec<Order>) {
+        let weights: Vec<u64> = level.iter().map(|order| order.remaining()).collect();
+        let allocation = pro_rata_allocate(taker.remaining(), &weights);
+
+        let mut rest = Vec::<Order>::new();
+        for (maker, deal_amount) in level.into_iter().zip(allocation.into_iter()) {
+            if deal_amount == 0 {
+                rest.push(maker);
+                continue;
+            }
+
+            let (buy, sell) = match taker.kind {
+                OrderKind::Buy => (taker.clone(), maker),
+                OrderKind::Sell => (maker, taker.clone()),
+            };
+            let deal_price = trade.price_rule.deal_price(&buy, &sell);
+
+            let res = self.settle(deal_price, deal_amount, height, buy.clone(), sell.clone());
+            if res.is_error() {
+                self.settle_failed(trade, &buy, &sell, height);
+                continue;
+            }
+
+            let (next_buy, next_sell) = res.succeed_data;
+            let next_maker = match taker.kind {
+                OrderKind::Buy => {
+                    taker = next_buy;
+                    next_sell
+                }
+                OrderKind::Sell => {
+                    taker = next_sell;
+                    next_buy
+                }
+            };
+            if next_maker.status != OrderStatus::Full {
+                rest.push(next_maker);
+            }
+        }
+
+        if taker.status == OrderStatus::Full {
+            (None, rest)
+        } else {
+            (Some(taker), rest)
+        }
+    }
+
+    // fill `taker` against the opposite book, best price first, until
+    // `amount` is filled, the book runs out or the average price limit is
+    // reached. a budget caps the notional spent and is locked by the caller,
+    // otherwise every fill is locked as it goes. returns the deals and the
+    // notional spent, the fees paid are added to the taker
+    fn sweep(
+        &mut self,
+        trade: &Trade,
+        taker: &mut Order,
+        amount: u64,
+        budget: Option<u64>,
+        avg_price_limit: Option<u64>,
+    ) -> ServiceResponse<(Vec<Deal>, u64)> {
+        let maker_kind = match taker.kind {
+            OrderKind::Buy => OrderKind::Sell,
+            OrderKind::Sell => OrderKind::Buy,
+        };
+        let mut makers = self.get_open_orders(&trade.id, maker_kind);
+        makers.sort();
+
+        let height = taker.height;
+        let mut spent = 0u64;
+        let mut deals = Vec::<Deal>::new();
+        let mut filled = 0u64;
+        let mut quote = 0u128;
+
+        while filled < amount {
+            let maker = match makers.pop() {
+                Some(maker) => maker,
+                None => break,
+            };
+
+            // stop short of a level the circuit breaker wouldn't match at
+            let deal_price = maker.price;
+            let last_price = self.last_prices.get(&trade.id).unwrap_or(0);
+            if exceeds_deviation(last_price, deal_price, self.circuit_breaker.get()) {
+                break;
+            }
+            let mut wanted = std::cmp::min(amount - filled, maker.remaining());
+            if let Some(budget) = budget {
+                // round down to what the rest of the budget fully covers
+                let affordable = trade.amount_for_notional(budget - spent, deal_price);
+                wanted = std::cmp::min(wanted, affordable);
+            }
+            let deal_amount = avg_price_limit.map_or(wanted, |limit| {
+                fill_within_average(&taker.kind, filled, quote, deal_price, limit, wanted)
+            });
+            if deal_amount == 0 {
+                break;
+            }
 
-        current_buy.deals.push(settle_deal.clone());
-        let mut dealt_amount: u64 = match current_buy.status {
-            OrderStatus::Fresh => 0,
-            OrderStatus::Partial(v) => v,
-            OrderStatus::Full => panic!("should not be full"),
-        };
-        dealt_amount += settle_deal.amount;
-        current_buy.status = OrderStatus::Partial(dealt_amount);
+            // each fill is locked and settled as a taker order of its own
+            taker.price = deal_price;
+            taker.amount = deal_amount;
+            taker.status = OrderStatus::Fresh;
+            taker.filled = 0;
+            taker.deals = Vec::new();
+            if budget.is_none() {
+                let lock_payload = ModifyAssetPayload {
+                    asset_id: trade.locked_asset(&taker.kind).clone(),
+                    user: taker.user.clone(),
+                    value: taker.locked(trade),
+                };
+                call_and_parse_service_response!(self, lock_asset, lock_payload);
+            }
 
-        self.sell_orders.remove(&current_sell.tx_hash);
-        self.history_orders
-            .insert(current_sell.tx_hash.clone(), current_sell);
+            let (buy, sell) = match taker.kind {
+                OrderKind::Buy => (taker.clone(), maker),
+                OrderKind::Sell => (maker, taker.clone()),
+            };
+            let settle_res = self.settle(deal_price, deal_amount, height, buy, sell);
+            let (buy, sell) = check_get_or_return!(settle_res);
+            taker.fees = match taker.kind {
+                OrderKind::Buy => buy.fees,
+                OrderKind::Sell => sell.fees,
+            };
+
+            deals.push(Deal {
+                price: deal_price,
+                amount: deal_amount,
+                height,
+            });
+            filled += deal_amount;
+            quote += deal_amount as u128 * deal_price as u128;
+            spent += trade.notional(deal_amount, deal_price);
+            if deal_amount < wanted {
+                break;
+            }
+        }
 
-        self.buy_orders
-            .insert(current_buy.tx_hash.clone(), current_buy.clone());
+        ServiceResponse::from_succeed((deals, spent))
+    }
 
-        ServiceResponse::from_succeed(current_buy)
+    // the balance changes of a deal carry "<trade id>/<deal index in the
+    // trade>" as correlation id, the same id its deal record keeps. a netted
+    // change carries the ids of every deal it nets, comma separated
+    fn settle(
+        &mut self,
+        deal_price: u64,
+        deal_amount: u64,
+        height: u64,
+        current_buy: Order,
+        current_sell: Order,
+    ) -> ServiceResponse<(Order, Order)> {
+        let trade_id = &current_buy.trade_id;
+        let deal_index = self.trade_deals.get(trade_id).unwrap_or(0);
+        self.correlation_id = format!("{}/{}", trade_id.as_hex(), deal_index);
+
+        let res = self.settle_deal(deal_price, deal_amount, height, current_buy, current_sell);
+
+        self.correlation_id.clear();
+        res
     }
 
-    fn settle_both(
+    fn settle_deal(
         &mut self,
         deal_price: u64,
         deal_amount: u64,
+        height: u64,
         mut current_buy: Order,
         mut current_sell: Order,
-    ) -> ServiceResponse<()> {
+    ) -> ServiceResponse<(Order, Order)> {
         let trade_id = current_buy.trade_id.clone();
         let trade = check_get_or_return!(self.get_trade(trade_id.clone()));
 
-        let unlock_seller = ModifyAssetPayload {
+        // release the drop in the buyer's locked notional rather than the
+        // notional of the deal itself, so rounding can't leave dust locked.
+        // rounding up may price a deal above that drop, the buyer then pays
+        // no more than the drop
+        let buy_remaining = current_buy.remaining();
+        let buyer_released = trade.notional(buy_remaining, current_buy.price)
+            - trade.notional(buy_remaining - deal_amount, current_buy.price);
+
+        // the taker pays the fee out of what it receives, and part of it is
+        // rebated to the maker in the same asset
+        let seller_receive = std::cmp::min(trade.notional(deal_amount, deal_price), buyer_released);
+        let ((seller_fee, seller_rebate), (buyer_fee, buyer_rebate)) =
+            match taker_kind(&current_buy, &current_sell) {
+                OrderKind::Buy => ((0, 0), self.taker_fee(&trade, deal_amount)),
+                OrderKind::Sell => (self.taker_fee(&trade, seller_receive), (0, 0)),
+            };
+
+        // each side pays straight out of its lock. the seller's counter
+        // party goes first, so a seller short of it fails the deal untouched
+        let pay_buyer = SettleAssetPayload {
             asset_id: trade.counter_party.clone(),
-            user: current_sell.user.clone(),
-            value: deal_amount,
+            from: current_sell.user.clone(),
+            to: current_buy.user.clone(),
+            value: deal_amount - buyer_fee,
         };
-        call_and_parse_service_response!(self, unlock_asset, unlock_seller);
-
-        let add_seller = ModifyAssetPayload {
+        call_and_parse_service_response!(self, settle_locked, pay_buyer);
+        let buyer_fee_res = self.collect_fee(
+            trade.counter_party.clone(),
+            buyer_fee,
+            buyer_rebate,
+            current_sell.user.clone(),
+            current_buy.referrer.clone(),
+        );
+        check_get_or_return!(buyer_fee_res);
+
+        let pay_seller = SettleAssetPayload {
             asset_id: trade.base_asset.clone(),
-            user: current_sell.user.clone(),
-            value: deal_amount * deal_price,
+            from: current_buy.user.clone(),
+            to: current_sell.user.clone(),
+            value: seller_receive - seller_fee,
         };
-        call_and_parse_service_response!(self, add_value, add_seller);
+        call_and_parse_service_response!(self, settle_locked, pay_seller);
+        let seller_fee_res = self.collect_fee(
+            trade.base_asset.clone(),
+            seller_fee,
+            seller_rebate,
+            current_buy.user.clone(),
+            current_sell.referrer.clone(),
+        );
+        check_get_or_return!(seller_fee_res);
+
+        if buyer_released > seller_receive {
+            let unlock_buyer = ModifyAssetPayload {
+                asset_id: trade.base_asset,
+                user: current_buy.user.clone(),
+                value: buyer_released - seller_receive,
+            };
+            call_and_parse_service_response!(self, unlock_asset, unlock_buyer);
+        }
 
-        let sub_seller = ModifyAssetPayload {
-            asset_id: trade.counter_party.clone(),
-            user: current_sell.user.clone(),
-            value: deal_amount,
+        let settle_deal = Deal {
+            price: deal_price,
+            amount: deal_amount,
+            height,
         };
-        call_and_parse_service_response!(self, sub_value, sub_seller);
-
-        let unlock_buyer = ModifyAssetPayload {
-            asset_id: trade.base_asset.clone(),
-            user: current_buy.user.clone(),
-            value: deal_amount * current_buy.price,
+        current_buy.fees += buyer_fee;
+        current_sell.fees += seller_fee;
+        current_buy.fill(settle_deal.clone());
+        current_sell.fill(settle_deal);
+
+        self.last_prices.insert(trade_id.clone(), deal_price);
+        self.total_deals.set(self.total_deals.get() + 1);
+        let trade_deals = self.trade_deals.get(&trade_id).unwrap_or(0);
+        self.trade_deals.insert(trade_id.clone(), trade_deals + 1);
+        let (maker, taker) = match taker_kind(&current_buy, &current_sell) {
+            OrderKind::Buy => (current_sell.user.clone(), current_buy.user.clone()),
+            OrderKind::Sell => (current_buy.user.clone(), current_sell.user.clone()),
         };
-        call_and_parse_service_response!(self, unlock_asset, unlock_buyer);
+        self.deal_log.push(DealRecord {
+            trade_id,
+            price: deal_price,
+            amount: deal_amount,
+            height,
+            maker,
+            taker,
+            correlation_id: self.correlation_id.clone(),
+        });
 
-        let add_buyer = ModifyAssetPayload {
-            asset_id: trade.counter_party,
-            user: current_buy.user.clone(),
-            value: deal_amount,
-        };
-        call_and_parse_service_response!(self, add_value, add_buyer);
+        self.update_book(current_buy.clone(), height);
+        self.update_book(current_sell.clone(), height);
 
-        let sub_buyer = ModifyAssetPayload {
-            asset_id: trade.base_asset,
-            user: current_buy.user.clone(),
-            value: deal_amount * deal_price,
+        ServiceResponse::from_succeed((current_buy, current_sell))
+    }
+
+    // the fee and maker rebate charged on `receive`, what the taker receives
+    fn taker_fee(&self, trade: &Trade, receive: u64) -> (u64, u64) {
+        let fee_rate = self.fee_rate.get();
+        if fee_rate == 0 {
+            return (0, 0);
+        }
+
+        // a capped fee may fall below the rebate, which is capped in turn
+        let fee = trade.clamp_fee(trade.rounding.apply_bps(receive, fee_rate), receive);
+        let rebate = std::cmp::min(
+            trade.rounding.apply_bps(receive, self.maker_rebate.get()),
+            fee,
+        );
+        (fee, rebate)
+    }
+
+    // pay the maker its rebate out of a taker fee, then split the rest between
+    // the taker's referrer, if any, and fee_account. the fee comes out of the
+    // maker's lock, which holds what the taker receives
+    fn collect_fee(
+        &mut self,
+        asset_id: Hash,
+        fee: u64,
+        rebate: u64,
+        maker: Address,
+        referrer: Option<Address>,
+    ) -> ServiceResponse<()> {
+        if fee == 0 {
+            return ServiceResponse::from_succeed(());
+        }
+
+        // the rebate never exceeds the fee
+        let mut treasury_fee = fee - rebate;
+        if rebate != 0 {
+            let pay_maker = SettleAssetPayload {
+                asset_id: asset_id.clone(),
+                from: maker.clone(),
+                to: maker.clone(),
+                value: rebate,
+            };
+            call_and_parse_service_response!(self, settle_locked, pay_maker);
+        }
+
+        if let Some(referrer) = referrer {
+            let referral_fee = apply_bps(treasury_fee, self.referral_rate.get());
+            treasury_fee -= referral_fee;
+
+            let pay_referrer = SettleAssetPayload {
+                asset_id: asset_id.clone(),
+                from: maker.clone(),
+                to: referrer,
+                value: referral_fee,
+            };
+            call_and_parse_service_response!(self, settle_locked, pay_referrer);
+        }
+
+        let pay_treasury = SettleAssetPayload {
+            asset_id,
+            from: maker,
+            to: self.get_fee_account(),
+            value: treasury_fee,
         };
-        call_and_parse_service_response!(self, sub_value, sub_buyer);
+        call_and_parse_service_response!(self, settle_locked, pay_treasury);
 
-        let settle_deal = Deal {
-            price: deal_price,
-            amount: deal_amount,
+        ServiceResponse::from_succeed(())
+    }
+
+    fn same_asset_group(&self, asset_a: &Hash, asset_b: &Hash) -> bool {
+        match (self.asset_groups.get(asset_a), self.asset_groups.get(asset_b)) {
+            (Some(group_a), Some(group_b)) => group_a == group_b,
+            _ => false,
+        }
+    }
+
+    fn is_admin(&self, ctx: &ServiceContext) -> bool {
+        let admin: Option<Address> = self.sdk.get_value(&ADMIN_KEY.to_owned());
+        admin.map_or(false, |admin| admin == ctx.get_caller())
+    }
+
+    // locks held by a user's open orders and swaps on the given asset
+    fn get_user_locks(&self, user: &Address, asset_id: &Hash) -> Vec<OrderLock> {
+        self.get_asset_locks(asset_id)
+            .into_iter()
+            .filter(|(owner, _)| owner == user)
+            .map(|(_, lock)| lock)
+            .collect()
+    }
+
+    // locks held by every open order and swap on the given asset, with
+    // the user holding each of them
+    fn get_asset_locks(&self, asset_id: &Hash) -> Vec<(Address, OrderLock)> {
+        let mut locks = Vec::<(Address, OrderLock)>::new();
+        for (_, trade) in self.trades.iter() {
+            for kind in [OrderKind::Buy, OrderKind::Sell].iter() {
+                if trade.locked_asset(kind) != asset_id {
+                    continue;
+                }
+
+                for order in self.get_open_orders(&trade.id, kind.clone()) {
+                    let lock = OrderLock {
+                        tx_hash: order.tx_hash.clone(),
+                        locked: order.locked(&trade),
+                    };
+                    locks.push((order.user, lock));
+                }
+            }
+        }
+        for (_, swap) in self.swaps.iter() {
+            if &swap.offer_asset == asset_id {
+                let lock = OrderLock {
+                    tx_hash: swap.id.clone(),
+                    locked: swap.offer_amount,
+                };
+                locks.push((swap.maker, lock));
+            }
+        }
+        locks
+    }
+
+    fn get_native_asset_id(&self) -> Hash {
+        self.sdk
+            .get_value(&NATIVE_ASSET_KEY.to_owned())
+            .unwrap_or_default()
+    }
+
+    fn get_fee_account(&self) -> Address {
+        self.sdk
+            .get_value(&FEE_ACCOUNT_KEY.to_owned())
+            .unwrap_or_default()
+    }
+
+    // write a settled order back to its book, or move it to history once full
+    fn update_book(&mut self, order: Order, height: u64) {
+        if order.status == OrderStatus::Full {
+            self.remove_order(&order, height);
+            self.history_orders.insert(order.tx_hash.clone(), order);
+        } else {
+            self.insert_order(order, height);
+        }
+    }
+
+    // buy_orders and sell_orders must only be modified through insert_order
+    // and remove_order so the per-trade index and book version stay
+    // consistent with them
+    fn insert_order(&mut self, order: Order, height: u64) {
+        let trade_id = order.trade_id.clone();
+        let mut index = self.order_index.get(&trade_id).unwrap_or_default();
+
+        let (book, hashes) = match order.kind {
+            OrderKind::Buy => (&mut self.buy_orders, &mut index.buys),
+            OrderKind::Sell => (&mut self.sell_orders, &mut index.sells),
         };
-        current_sell.status = OrderStatus::Full;
-        current_sell.deals.push(settle_deal.clone());
+        if !book.contains(&order.tx_hash) {
+            hashes.push(order.tx_hash.clone());
+        }
+        book.insert(order.tx_hash.clone(), order);
 
-        current_buy.status = OrderStatus::Full;
-        current_buy.deals.push(settle_deal.clone());
+        self.order_index.insert(trade_id.clone(), index);
+        self.book_versions.insert(trade_id, height);
+    }
 
-        self.sell_orders.remove(&current_sell.tx_hash);
-        self.history_orders
-            .insert(current_sell.tx_hash.clone(), current_sell);
+    fn remove_order(&mut self, order: &Order, height: u64) {
+        let mut index = self.order_index.get(&order.trade_id).unwrap_or_default();
 
-        self.buy_orders.remove(&current_buy.tx_hash);
-        self.history_orders
-            .insert(current_buy.tx_hash.clone(), current_buy);
+        let (book, hashes) = match order.kind {
+            OrderKind::Buy => (&mut self.buy_orders, &mut index.buys),
+            OrderKind::Sell => (&mut self.sell_orders, &mut index.sells),
+        };
+        book.remove(&order.tx_hash);
+        hashes.retain(|hash| hash != &order.tx_hash);
+        if self.settle_failures.contains(&order.tx_hash) {
+            self.settle_failures.remove(&order.tx_hash);
+        }
 
-        ServiceResponse::from_succeed(())
+        self.order_index.insert(order.trade_id.clone(), index);
+        self.book_versions.insert(order.trade_id.clone(), height);
+    }
+
+    fn get_open_orders(&self, trade_id: &Hash, kind: OrderKind) -> Vec<Order> {
+        let index = self.order_index.get(trade_id).unwrap_or_default();
+
+        let (book, hashes) = match kind {
+            OrderKind::Buy => (&self.buy_orders, index.buys),
+            OrderKind::Sell => (&self.sell_orders, index.sells),
+        };
+        hashes.iter().filter_map(|hash| book.get(hash)).collect()
     }
 
     fn lock_asset(&mut self, payload: ModifyAssetPayload) -> ServiceResponse<()> {
@@ -567,46 +2824,331 @@ impl<SDK: 'static + ServiceSDK, A: AssetFacade> DexService<SDK, A> {
             asset_id: payload.asset_id.clone(),
             user: payload.user.clone(),
             value: payload.value,
+            correlation_id: String::new(),
         };
 
         self.asset
-            .lock(self.get_call_asset_ctx(), lock_asset_payload)
+            .lock(self.get_admitted_asset_ctx(LOCK_TOKEN), lock_asset_payload)
     }
 
     fn unlock_asset(&mut self, payload: ModifyAssetPayload) -> ServiceResponse<()> {
+        if self.net_deltas.is_some() {
+            let check_res = self.check_net_debit(&payload, true);
+            check_get_or_return!(check_res);
+            let check_res = self.check_net_credit(&payload, payload.value, 0);
+            check_get_or_return!(check_res);
+            self.net_delta(&payload).unlocked += payload.value;
+            return ServiceResponse::from_succeed(());
+        }
+
         let unlock_asset_payload = ModifyBalancePayload {
             asset_id: payload.asset_id.clone(),
             user: payload.user.clone(),
             value: payload.value,
+            correlation_id: String::new(),
         };
 
         self.asset
-            .unlock(self.get_call_asset_ctx(), unlock_asset_payload)
+            .unlock(self.get_admitted_asset_ctx(LOCK_TOKEN), unlock_asset_payload)
     }
 
     fn add_value(&mut self, payload: ModifyAssetPayload) -> ServiceResponse<()> {
+        if self.net_deltas.is_some() {
+            let check_res = self.check_net_credit(&payload, 0, payload.value as i128);
+            check_get_or_return!(check_res);
+            self.net_delta(&payload).net += payload.value as i128;
+            return ServiceResponse::from_succeed(());
+        }
+
         let add_asset_payload = ModifyBalancePayload {
             asset_id: payload.asset_id.clone(),
             user: payload.user.clone(),
             value: payload.value,
+            correlation_id: self.correlation_id.clone(),
         };
 
         self.asset
-            .add_value(self.get_call_asset_ctx(), add_asset_payload)
+            .add_value(self.get_admitted_asset_ctx(BALANCE_TOKEN), add_asset_payload)
     }
 
     fn sub_value(&mut self, payload: ModifyAssetPayload) -> ServiceResponse<()> {
+        if self.net_deltas.is_some() {
+            let check_res = self.check_net_debit(&payload, false);
+            check_get_or_return!(check_res);
+            self.net_delta(&payload).net -= payload.value as i128;
+            return ServiceResponse::from_succeed(());
+        }
+
         let sub_asset_payload = ModifyBalancePayload {
             asset_id: payload.asset_id.clone(),
             user: payload.user.clone(),
             value: payload.value,
+            correlation_id: self.correlation_id.clone(),
+        };
+
+        self.asset
+            .sub_value(self.get_admitted_asset_ctx(BALANCE_TOKEN), sub_asset_payload)
+    }
+
+    fn settle_locked(&mut self, payload: SettleAssetPayload) -> ServiceResponse<()> {
+        if self.net_deltas.is_some() {
+            let from = ModifyAssetPayload {
+                asset_id: payload.asset_id.clone(),
+                user: payload.from.clone(),
+                value: payload.value,
+            };
+            let check_res = self.check_net_debit(&from, true);
+            check_get_or_return!(check_res);
+            let to = ModifyAssetPayload {
+                user: payload.to.clone(),
+                ..from.clone()
+            };
+            let check_res = if from.user == to.user {
+                self.check_net_credit(&from, payload.value, 0)
+            } else {
+                let net = -(payload.value as i128);
+                let check_res = self.check_net_credit(&from, payload.value, net);
+                check_get_or_return!(check_res);
+                self.check_net_credit(&to, 0, payload.value as i128)
+            };
+            check_get_or_return!(check_res);
+            let from_delta = self.net_delta(&from);
+            from_delta.unlocked += payload.value;
+            from_delta.net -= payload.value as i128;
+            self.net_delta(&to).net += payload.value as i128;
+            return ServiceResponse::from_succeed(());
+        }
+
+        let settle_payload = SettleLockedPayload {
+            asset_id: payload.asset_id.clone(),
+            from: payload.from.clone(),
+            to: payload.to.clone(),
+            value: payload.value,
+            correlation_id: self.correlation_id.clone(),
+        };
+
+        self.asset
+            .settle_locked(self.get_admitted_asset_ctx(BALANCE_TOKEN), settle_payload)
+    }
+
+    fn net_delta(&mut self, payload: &ModifyAssetPayload) -> &mut NetDelta {
+        let correlation_id = &self.correlation_id;
+        let deltas = self
+            .net_deltas
+            .as_mut()
+            .expect("net deltas should be collected");
+        let index = match deltas
+            .iter()
+            .position(|delta| delta.user == payload.user && delta.asset_id == payload.asset_id)
+        {
+            Some(index) => index,
+            None => {
+                deltas.push(NetDelta {
+                    asset_id: payload.asset_id.clone(),
+                    user: payload.user.clone(),
+                    unlocked: 0,
+                    net: 0,
+                    correlation_ids: Vec::new(),
+                });
+                deltas.len() - 1
+            }
+        };
+
+        let delta = &mut deltas[index];
+        if !correlation_id.is_empty() && !delta.correlation_ids.contains(correlation_id) {
+            delta.correlation_ids.push(correlation_id.clone());
+        }
+        delta
+    }
+
+    // a user's balance as it stands once the deltas collected so far apply
+    fn projected_balance(&self, user: &Address, asset_id: &Hash) -> ServiceResponse<Balance> {
+        let balance_payload = GetBalancePayload {
+            asset_id: asset_id.clone(),
+            user: user.clone(),
+        };
+        let balance_res = self
+            .asset
+            .query_balance(self.get_call_asset_ctx(), balance_payload);
+        let mut balance = check_get_or_return!(balance_res);
+
+        let delta = self.net_deltas.as_ref().and_then(|deltas| {
+            deltas
+                .iter()
+                .find(|delta| &delta.user == user && &delta.asset_id == asset_id)
+        });
+        // check_net_debit and check_net_credit keep both of these in range
+        if let Some(delta) = delta {
+            balance.locked -= delta.unlocked;
+            balance.current = (balance.current as i128 + delta.unlocked as i128 + delta.net) as u64;
+        }
+        ServiceResponse::from_succeed(balance)
+    }
+
+    // a deferred debit is refused whenever settling deal by deal would have
+    // refused it, so a deal that can't settle fails before any fill is marked
+    fn check_net_debit(&self, payload: &ModifyAssetPayload, locked: bool) -> ServiceResponse<()> {
+        let balance_res = self.projected_balance(&payload.user, &payload.asset_id);
+        let balance = check_get_or_return!(balance_res);
+        let had = if locked {
+            balance.locked
+        } else {
+            balance.current
+        };
+        if had < payload.value {
+            return DexError::InsufficientBalance {
+                wanted: payload.value,
+                had,
+            }
+            .into();
+        }
+        ServiceResponse::from_succeed(())
+    }
+
+    // a deferred credit is refused whenever the balance would overflow on the
+    // way, since the deltas apply as the whole unlock and then the net
+    fn check_net_credit(
+        &self,
+        payload: &ModifyAssetPayload,
+        unlocked: u64,
+        net: i128,
+    ) -> ServiceResponse<()> {
+        let balance_payload = GetBalancePayload {
+            asset_id: payload.asset_id.clone(),
+            user: payload.user.clone(),
+        };
+        let balance_res = self
+            .asset
+            .query_balance(self.get_call_asset_ctx(), balance_payload);
+        let balance = check_get_or_return!(balance_res);
+
+        let (mut total_unlocked, mut total_net) = (unlocked as i128, net);
+        let delta = self.net_deltas.as_ref().and_then(|deltas| {
+            deltas
+                .iter()
+                .find(|delta| delta.user == payload.user && delta.asset_id == payload.asset_id)
+        });
+        if let Some(delta) = delta {
+            total_unlocked += delta.unlocked as i128;
+            total_net += delta.net;
+        }
+        let peak = balance.current as i128 + total_unlocked + std::cmp::max(total_net, 0);
+        if peak > u64::MAX as i128 {
+            return DexError::BalanceOverflow.into();
+        }
+        ServiceResponse::from_succeed(())
+    }
+
+    // every deal unlocks what it takes before taking it, so unlocking the
+    // total first leaves enough current balance for any net debit. the checks
+    // above should keep every delta applicable, but a hook can't fail, so a
+    // delta that still doesn't apply is logged and the rest go on
+    fn apply_net_deltas(&mut self, deltas: Vec<NetDelta>, height: u64) {
+        for delta in deltas.into_iter() {
+            self.correlation_id = delta.correlation_ids.join(",");
+            let res = self.apply_net_delta(&delta);
+            self.correlation_id.clear();
+
+            if res.is_error() {
+                let event = NetDeltaFailedEvent {
+                    asset_id: delta.asset_id,
+                    user: delta.user,
+                    correlation_ids: delta.correlation_ids,
+                    error: res.error_message,
+                };
+                self.emit_hook_event(height, "NetDeltaFailed", &event);
+            }
+        }
+    }
+
+    fn apply_net_delta(&mut self, delta: &NetDelta) -> ServiceResponse<()> {
+        let payload = |value: u64| ModifyAssetPayload {
+            asset_id: delta.asset_id.clone(),
+            user: delta.user.clone(),
+            value,
         };
+        if delta.unlocked != 0 {
+            call_and_parse_service_response!(self, unlock_asset, payload(delta.unlocked));
+        }
+        if delta.net > 0 {
+            call_and_parse_service_response!(self, add_value, payload(delta.net as u64));
+        } else if delta.net < 0 {
+            call_and_parse_service_response!(self, sub_value, payload((-delta.net) as u64));
+        }
+        ServiceResponse::from_succeed(())
+    }
+
+    fn order_validity(&self, trade: &Trade) -> u64 {
+        if trade.validity != 0 {
+            trade.validity
+        } else {
+            self.validity.get()
+        }
+    }
+
+    fn is_trade_frozen(&self, trade: &Trade) -> bool {
+        [&trade.base_asset, &trade.counter_party].iter().any(|asset_id| {
+            let payload = GetAssetPayload {
+                id: (*asset_id).clone(),
+            };
+            self.asset
+                .query_asset(self.get_call_asset_ctx(), payload)
+                .succeed_data
+                .frozen
+        })
+    }
+
+    fn check_trade_assets(&self, trade: &Trade) -> ServiceResponse<()> {
+        let base = trade.base_asset.clone();
+        let counter_party = trade.counter_party.clone();
+        if !self.asset_exists(base) || !self.asset_exists(counter_party) {
+            return DexError::AssetMissing.into();
+        }
+        ServiceResponse::from_succeed(())
+    }
+
+    fn asset_exists(&self, asset_id: Hash) -> bool {
+        !self
+            .asset
+            .query_asset(self.get_call_asset_ctx(), GetAssetPayload { id: asset_id })
+            .is_error()
+    }
+
+    fn is_trade_tradable(&self, trade: &Trade) -> bool {
+        [&trade.base_asset, &trade.counter_party].iter().all(|asset_id| {
+            let payload = GetAssetPayload {
+                id: (*asset_id).clone(),
+            };
+            self.asset
+                .query_asset(self.get_call_asset_ctx(), payload)
+                .succeed_data
+                .dex_tradable
+        })
+    }
+
+    fn get_asset_decimals(&self, asset_id: Hash) -> u8 {
+        self.asset
+            .query_asset(self.get_call_asset_ctx(), GetAssetPayload { id: asset_id })
+            .succeed_data
+            .decimals
+    }
 
+    fn get_asset_supply(&self, asset_id: Hash) -> u64 {
         self.asset
-            .sub_value(self.get_call_asset_ctx(), sub_asset_payload)
+            .query_asset(self.get_call_asset_ctx(), GetAssetPayload { id: asset_id })
+            .succeed_data
+            .supply
     }
 
     fn get_call_asset_ctx(&self) -> ServiceContext {
+        self.asset_ctx(None)
+    }
+
+    fn get_admitted_asset_ctx(&self, token: Bytes) -> ServiceContext {
+        self.asset_ctx(Some(token))
+    }
+
+    fn asset_ctx(&self, extra: Option<Bytes>) -> ServiceContext {
         let params = ServiceContextParams {
             tx_hash: None,
             nonce: None,
@@ -619,62 +3161,202 @@ impl<SDK: 'static + ServiceSDK, A: AssetFacade> DexService<SDK, A> {
             service_name: "".to_owned(),
             service_method: "".to_owned(),
             service_payload: "".to_owned(),
-            extra: Some(ADMISSION_TOKEN.clone()),
+            extra,
             events: Rc::new(RefCell::new(vec![])),
         };
 
         ServiceContext::new(params)
     }
 
-    fn remove_expiry_orders(&mut self, current_height: u64) {
-        let mut expiry_buys = Vec::<(Hash, Order)>::new();
-        for (tx_hash, order) in self.buy_orders.iter() {
-            if order.expiry < current_height {
-                expiry_buys.push((tx_hash.clone(), order.clone()));
+    // returns how many orders expired
+    // an order is swept once the grace period after its expiry has passed,
+    // until then it still rests and matches
+    fn remove_expiry_orders(&mut self, current_height: u64) -> u64 {
+        let grace = self.expiry_grace.get();
+        let mut expired = Vec::<Order>::new();
+        for (_, order) in self.buy_orders.iter().chain(self.sell_orders.iter()) {
+            if order.expiry != GOOD_TILL_CANCEL
+                && order.expiry.saturating_add(grace) < current_height
+            {
+                expired.push(order.clone());
             }
         }
-        for (hash, order) in expiry_buys.iter() {
-            self.buy_orders.remove(hash);
-            let unlock_amount = match order.status {
-                OrderStatus::Fresh => order.amount,
-                OrderStatus::Partial(p) => order.amount - p,
-                OrderStatus::Full => 0,
-            };
-            if unlock_amount != 0 {
-                let payload = ModifyAssetPayload {
-                    asset_id: self.trades.get(&order.trade_id).unwrap().base_asset,
-                    user: order.user.clone(),
-                    value: unlock_amount,
-                };
-                self.unlock_asset(payload);
-            }
+        expired.sort_by_key(|order| order.tx_hash.as_hex());
+
+        let refunds = self.expiry_refunds(&expired);
+        for order in expired.iter() {
+            self.remove_order(order, current_height);
             self.history_orders
                 .insert(order.tx_hash.clone(), order.clone());
         }
+        for refund in refunds.into_iter() {
+            self.unlock_asset(refund);
+        }
+
+        expired.len() as u64
+    }
+
+    // what the orders still hold locked, summed into one unlock per user and
+    // asset. a buy locked the notional of its remaining amount, not the
+    // amount itself
+    fn expiry_refunds(&self, orders: &[Order]) -> Vec<ModifyAssetPayload> {
+        let mut refunds = Vec::<ModifyAssetPayload>::new();
+        for order in orders.iter() {
+            let trade = self.trades.get(&order.trade_id).unwrap();
+            let value = order.locked(&trade);
+            if value == 0 {
+                continue;
+            }
 
-        let mut expiry_sells = Vec::<(Hash, Order)>::new();
-        for (tx_hash, order) in self.sell_orders.iter() {
-            if order.expiry < current_height {
-                expiry_sells.push((tx_hash.clone(), order.clone()));
+            let asset_id = trade.locked_asset(&order.kind);
+            match refunds
+                .iter_mut()
+                .find(|refund| refund.user == order.user && &refund.asset_id == asset_id)
+            {
+                // the sum is bounded by the user's locked balance
+                Some(refund) => refund.value += value,
+                None => refunds.push(ModifyAssetPayload {
+                    asset_id: asset_id.clone(),
+                    user: order.user.clone(),
+                    value,
+                }),
             }
         }
-        for (hash, order) in expiry_sells.iter() {
-            self.sell_orders.remove(hash);
-            let unlock_amount = match order.status {
-                OrderStatus::Fresh => order.amount,
-                OrderStatus::Partial(p) => order.amount - p,
-                OrderStatus::Full => 0,
+        refunds
+    }
+
+    // refunds what the order still holds locked and moves it to history
+    fn cancel(&mut self, trade: &Trade, order: &Order, height: u64) -> ServiceResponse<()> {
+        // only what is still locked for the unfilled part goes back, never
+        // the whole order amount, or a partially filled order would release
+        // funds locked by the user's other orders
+        let unlock_payload = ModifyAssetPayload {
+            asset_id: trade.locked_asset(&order.kind).clone(),
+            user: order.user.clone(),
+            value: order.locked(trade),
+        };
+        if cfg!(debug_assertions) {
+            let balance_payload = GetBalancePayload {
+                asset_id: unlock_payload.asset_id.clone(),
+                user: unlock_payload.user.clone(),
             };
-            if unlock_amount != 0 {
-                let payload = ModifyAssetPayload {
-                    asset_id: self.trades.get(&order.trade_id).unwrap().counter_party,
-                    user: order.user.clone(),
-                    value: unlock_amount,
-                };
-                self.unlock_asset(payload);
+            let balance_res = self
+                .asset
+                .query_balance(self.get_call_asset_ctx(), balance_payload);
+            debug_assert!(unlock_payload.value <= balance_res.succeed_data.locked);
+        }
+        call_and_parse_service_response!(self, unlock_asset, unlock_payload);
+
+        self.remove_order(order, height);
+        self.history_orders
+            .insert(order.tx_hash.clone(), order.clone());
+        ServiceResponse::from_succeed(())
+    }
+
+    // settling against an asset that no longer exists would fail every
+    // block, so the trade's orders leave the book instead. what is still
+    // locked goes back where the locked asset itself exists
+    fn invalidate_orders(&mut self, trade: &Trade, height: u64, reason: String) {
+        let mut orders = self.get_open_orders(&trade.id, OrderKind::Buy);
+        orders.extend(self.get_open_orders(&trade.id, OrderKind::Sell));
+        if orders.is_empty() {
+            return;
+        }
+
+        for order in orders.iter() {
+            let asset_id = trade.locked_asset(&order.kind).clone();
+            let refunded =
+                self.asset_exists(asset_id) && !self.cancel(trade, order, height).is_error();
+            if !refunded {
+                self.remove_order(order, height);
+                self.history_orders
+                    .insert(order.tx_hash.clone(), order.clone());
             }
-            self.history_orders
-                .insert(order.tx_hash.clone(), order.clone());
+        }
+
+        let event = InvalidateOrdersEvent {
+            trade_id: trade.id.clone(),
+            reason,
+            orders: orders.into_iter().map(|order| order.tx_hash).collect(),
+        };
+        self.emit_hook_event(height, "InvalidateOrders", &event);
+    }
+
+    // a deal that fails to settle is skipped for the block, and is retried
+    // the next one. the failure counts against whichever order no longer
+    // has its funds locked, or against both when neither is short, and an
+    // order that keeps failing leaves the book rather than stall matching
+    fn settle_failed(&mut self, trade: &Trade, buy: &Order, sell: &Order, height: u64) {
+        let buy_covered = self.lock_covered(trade, buy);
+        let sell_covered = self.lock_covered(trade, sell);
+        let neither_short = buy_covered && sell_covered;
+
+        let mut blamed = Vec::<(&Order, bool)>::new();
+        if !buy_covered || neither_short {
+            blamed.push((buy, buy_covered));
+        }
+        if !sell_covered || neither_short {
+            blamed.push((sell, sell_covered));
+        }
+
+        for (order, covered) in blamed.into_iter() {
+            let failures = self.settle_failures.get(&order.tx_hash).unwrap_or(0) + 1;
+            if failures < MAX_SETTLE_FAILURES {
+                self.settle_failures.insert(order.tx_hash.clone(), failures);
+                continue;
+            }
+
+            // only refund what the user still has locked for the order
+            let refunded = covered && !self.cancel(trade, order, height).is_error();
+            if !refunded {
+                self.remove_order(order, height);
+                self.history_orders
+                    .insert(order.tx_hash.clone(), order.clone());
+            }
+
+            let event = InvalidateOrdersEvent {
+                trade_id: trade.id.clone(),
+                reason: "SettlementFailed".to_owned(),
+                orders: vec![order.tx_hash.clone()],
+            };
+            self.emit_hook_event(height, "InvalidateOrders", &event);
+        }
+    }
+
+    // whether the user's locked balance still covers what the order locks,
+    // counting what the round has already settled under net settlement
+    fn lock_covered(&self, trade: &Trade, order: &Order) -> bool {
+        let balance_res = self.projected_balance(&order.user, trade.locked_asset(&order.kind));
+        !balance_res.is_error() && balance_res.succeed_data.locked >= order.locked(trade)
+    }
+
+    fn get_open_order(&self, tx_hash: &Hash) -> ServiceResponse<Order> {
+        match self
+            .buy_orders
+            .get(tx_hash)
+            .or_else(|| self.sell_orders.get(tx_hash))
+        {
+            Some(order) => ServiceResponse::from_succeed(order),
+            None => DexError::OrderNotExisted.into(),
+        }
+    }
+
+    fn find_order(&self, tx_hash: &Hash) -> ServiceResponse<(Order, DealStatus)> {
+        if let Some(order) = self.buy_orders.get(tx_hash) {
+            ServiceResponse::from_succeed((order, DealStatus::Dealing))
+        } else if let Some(order) = self.sell_orders.get(tx_hash) {
+            ServiceResponse::from_succeed((order, DealStatus::Dealing))
+        } else if let Some(order) = self.history_orders.get(tx_hash) {
+            ServiceResponse::from_succeed((order, DealStatus::Dealt))
+        } else {
+            DexError::OrderNotExisted.into()
+        }
+    }
+
+    fn get_swap(&self, swap_id: &Hash) -> ServiceResponse<Swap> {
+        match self.swaps.get(swap_id) {
+            Some(swap) => ServiceResponse::from_succeed(swap),
+            None => DexError::SwapNotExisted.into(),
         }
     }
 
@@ -686,6 +3368,19 @@ impl<SDK: 'static + ServiceSDK, A: AssetFacade> DexService<SDK, A> {
     }
 }
 
+impl<SDK: 'static + ServiceSDK, A: AssetFacade> DexService<SDK, A> {
+    // hooks have no ServiceContext to emit events through, so events raised
+    // while matching are kept in the hook event log instead
+    fn emit_hook_event<T: Serialize>(&mut self, height: u64, name: &str, event: &T) {
+        let data = serde_json::to_string(event).expect("hook event should be serializable");
+        self.hook_events.push(HookEvent {
+            height,
+            name: name.to_owned(),
+            data,
+        });
+    }
+}
+
 #[derive(Debug, Display)]
 pub enum DexError {
     #[display(fmt = "Parsing payload to json failed {:?}", _0)]
@@ -700,6 +3395,62 @@ pub enum DexError {
     OrderOverdue,
 
     OrderNotExisted,
+
+    IllegalHeightRange,
+
+    BelowMinNotional,
+
+    PermissionDenial,
+
+    RateLimited,
+
+    OffTick,
+
+    OffLot,
+
+    AssetFrozen,
+
+    IllegalSplit,
+
+    SideDisabled,
+
+    NotCrossed,
+
+    OrderMismatch,
+
+    SwapNotExisted,
+
+    IllegalSwap,
+
+    NotionalTooLarge,
+
+    InvalidOrder,
+
+    DuplicateOrder,
+
+    OutOfCycles,
+
+    AssetNotTradable,
+
+    AssetMissing,
+
+    IllegalFeeRate,
+
+    ReduceOnlyViolated,
+
+    BalanceOverflow,
+
+    #[display(fmt = "Insufficient balance, wanted {} had {}", wanted, had)]
+    InsufficientBalance {
+        wanted: u64,
+        had: u64,
+    },
+
+    #[display(fmt = "Insufficient funds to lock, required {} available {}", required, available)]
+    InsufficientFundsToLock {
+        required: u64,
+        available: u64,
+    },
 }
 
 impl DexError {
@@ -711,6 +3462,30 @@ impl DexError {
             DexError::TradeNotExisted { .. } => 204,
             DexError::OrderOverdue => 205,
             DexError::OrderNotExisted => 206,
+            DexError::IllegalHeightRange => 207,
+            DexError::BelowMinNotional => 208,
+            DexError::PermissionDenial => 209,
+            DexError::RateLimited => 210,
+            DexError::OffTick => 211,
+            DexError::OffLot => 212,
+            DexError::AssetFrozen => 213,
+            DexError::InsufficientFundsToLock { .. } => 214,
+            DexError::IllegalSplit => 215,
+            DexError::SideDisabled => 216,
+            DexError::NotCrossed => 217,
+            DexError::OrderMismatch => 218,
+            DexError::SwapNotExisted => 219,
+            DexError::IllegalSwap => 220,
+            DexError::NotionalTooLarge => 221,
+            DexError::InvalidOrder => 222,
+            DexError::DuplicateOrder => 223,
+            DexError::OutOfCycles => 224,
+            DexError::AssetNotTradable => 225,
+            DexError::AssetMissing => 226,
+            DexError::IllegalFeeRate => 227,
+            DexError::ReduceOnlyViolated => 228,
+            DexError::InsufficientBalance { .. } => 229,
+            DexError::BalanceOverflow => 230,
         }
     }
 }
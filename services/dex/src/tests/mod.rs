@@ -8,73 +8,4088 @@ use cita_trie::MemoryDB;
 
 use framework::binding::sdk::{DefalutServiceSDK, DefaultChainQuerier};
 use framework::binding::state::{GeneralServiceState, MPTTrie};
-use protocol::traits::{NoopDispatcher, Storage};
+use protocol::traits::{NoopDispatcher, ServiceResponse, Storage};
 use protocol::types::{
     Address, Epoch, Hash, Proof, Receipt, ServiceContext, ServiceContextParams, SignedTransaction,
 };
 use protocol::ProtocolResult;
 
-use crate::types::{Trade, OrderKind, OrderPayload};
+use asset::types::{
+    Balance, CreateAssetPayload, FreezePayload, GetBalancePayload, ModifyBalancePayload,
+    SetDexTradablePayload, TransferPayload,
+};
+use asset::{AssetFacade, AssetService};
+
+use crate::types::{
+    derive_order_hash, pro_rata_allocate, AddTradePayload, AmendOrderPayload, AuditLocksPayload,
+    AuditLocksResponse, BatchOrderPayload, CancelByClientIdPayload, CancelOrderPayload,
+    ComputeDealPricePayload, ComputeTradeIdPayload, ConvertToMarketPayload, CreateSwapPayload,
+    CrossedBook, DealRecord, EstimateMatchCostPayload, EstimateSlippageResponse, ExportStatePayload,
+    GenesisPayload, Get24hStatsPayload, Get24hStatsResponse, GetBookVersionPayload,
+    GetConfigResponse, GetDealsByRangePayload, GetFillPricePayload, GetFillPriceResponse,
+    GetHistoryPayload, GetHookEventsPayload, GetLevelCountsPayload, GetLevelCountsResponse,
+    GetLockedBreakdownPayload, GetOpenInterestPayload, GetOrderDealsPayload, GetOrderPayload,
+    GetOrderReceiptPayload, GetPriorityQueuePayload, GetReferencePricePayload,
+    GetReferencePriceResponse, GetTradePayload, GetUserFillsPayload, GetUserTradesPayload,
+    IncreaseOrderPayload, InvalidateOrdersEvent, IsCrossedPayload, MarketOrderPayload,
+    MatchingRoundEvent, ModifyAssetPayload, MoveOrderPayload, OffgridOrdersPayload, Order,
+    OrderBook, OrderKind, OrderPayload, OrderStatus, PriceLevel, PriceRule, PriceSource,
+    PruneHistoryPayload, ReconcileLocksPayload, Rounding, SetAssetGroupPayload, SetFeeRatePayload,
+    SetMaxExpiryPayload, SetTradeEnabledPayload, SplitOrderPayload, SwapPayload, TimeInForce, Trade,
+    TradeDealCount, UpdateTradeParamsPayload,
+};
 use crate::DexService;
 
+type TestSDK = DefalutServiceSDK<
+    GeneralServiceState<MemoryDB>,
+    DefaultChainQuerier<MockStorage>,
+    NoopDispatcher,
+>;
+type TestDexService = DexService<TestSDK, AssetService<TestSDK>>;
+
+const CYCLES_LIMIT: u64 = 1024 * 1024 * 1024; // 1073741824
+
+#[test]
+fn test_json() {
+    let o = OrderPayload {
+        trade_id: Hash::from_empty(),
+        kind:            OrderKind::Sell,
+        price:           2,
+        amount:          100,
+        expiry:          Some(99999),
+        referrer:        None,
+        time_in_force:   TimeInForce::GoodTillDate,
+        use_locked:      false,
+        hidden:          false,
+        client_order_id: None,
+        reduce_only:     false,
+    };
+    println!("buy, {:?}", serde_json::to_string(&o).unwrap());
+
+    println!("hash, {:?}", &Hash::from_empty());
+}
+
+#[test]
+fn test_add_trade() {
+    let cycles_limit = 1024 * 1024 * 1024; // 1073741824
+    let caller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let context = mock_context(cycles_limit, caller);
+
+    let mut service = new_dex_service();
+
+    let trade = service.add_trade(context.clone(), AddTradePayload {
+        base_asset:    Hash::digest(Bytes::from_static(b"base")),
+        counter_party: Hash::digest(Bytes::from_static(b"counter")),
+        price_rule:    PriceRule::Midpoint,
+        min_notional:  0,
+        price_scale:   1,
+        tick_size:     1,
+        lot_size:      1,
+    });
+    assert!(!trade.is_error());
+
+    let trades = service.get_trades(context.clone()).succeed_data;
+
+    println!("get trades:{:?}", trades);
+    assert_eq!(trades.trades.len(), 1);
+}
+
+#[test]
+fn test_compute_trade_id() {
+    let caller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let base = Hash::digest(Bytes::from_static(b"base"));
+    let counter = Hash::digest(Bytes::from_static(b"counter"));
+
+    let mut service = new_dex_service();
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let context = mock_context(CYCLES_LIMIT, caller);
+    let forward = service.compute_trade_id(context.clone(), ComputeTradeIdPayload {
+        base_asset:    base.clone(),
+        counter_party: counter.clone(),
+    });
+    let backward = service.compute_trade_id(context.clone(), ComputeTradeIdPayload {
+        base_asset:    counter,
+        counter_party: base.clone(),
+    });
+    assert_eq!(forward.succeed_data.trade_id, trade.id);
+    assert_eq!(backward.succeed_data.trade_id, trade.id);
+
+    let res = service.compute_trade_id(context, ComputeTradeIdPayload {
+        base_asset:    base.clone(),
+        counter_party: base,
+    });
+    assert_eq!(res.code, 202);
+}
+
+#[test]
+fn test_get_trade_by_id() {
+    let caller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let base = Hash::digest(Bytes::from_static(b"base"));
+    let counter = Hash::digest(Bytes::from_static(b"counter"));
+
+    let mut service = new_dex_service();
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let context = mock_context(CYCLES_LIMIT, caller);
+    let res = service.get_trade_by_id(context.clone(), GetTradePayload {
+        trade_id: trade.id.clone(),
+    });
+    assert_eq!(res.succeed_data, trade);
+
+    let res = service.get_trade_by_id(context, GetTradePayload {
+        trade_id: Hash::digest(Bytes::from_static(b"missing")),
+    });
+    assert_eq!(res.code, 204);
+}
+
+#[test]
+fn test_price_rule() {
+    let buy = Order {
+        kind: OrderKind::Buy,
+        price: 10,
+        amount: 100,
+        height: 1,
+        ..Default::default()
+    };
+    let sell = Order {
+        kind: OrderKind::Sell,
+        price: 6,
+        amount: 100,
+        height: 2,
+        ..Default::default()
+    };
+
+    // the buy rests first, so it is the maker and the sell is the taker
+    assert_eq!(PriceRule::Midpoint.deal_price(&buy, &sell), 8);
+    assert_eq!(PriceRule::MakerPrice.deal_price(&buy, &sell), 10);
+    assert_eq!(PriceRule::TakerPrice.deal_price(&buy, &sell), 6);
+}
+
+#[test]
+fn test_pro_rata_allocate() {
+    // three asks of equal price share a buy of 20, the 2 units lost to
+    // rounding go to the asks with the best time priority
+    assert_eq!(pro_rata_allocate(20, &[10, 10, 10]), vec![7, 7, 6]);
+    assert_eq!(pro_rata_allocate(50, &[10, 30, 20]), vec![9, 25, 16]);
+
+    // a buy larger than the whole level fills every ask
+    assert_eq!(pro_rata_allocate(100, &[10, 30, 20]), vec![10, 30, 20]);
+}
+
+#[test]
+fn test_rounding() {
+    // 7 / 2 = 3.5, 10 / 3 = 3.33.., 11 / 3 = 3.66..
+    assert_eq!(Rounding::Floor.div(7, 2), 3);
+    assert_eq!(Rounding::Floor.div(10, 3), 3);
+    assert_eq!(Rounding::Floor.div(11, 3), 3);
+    assert_eq!(Rounding::Ceil.div(7, 2), 4);
+    assert_eq!(Rounding::Ceil.div(10, 3), 4);
+    assert_eq!(Rounding::Ceil.div(11, 3), 4);
+    assert_eq!(Rounding::Nearest.div(7, 2), 4);
+    assert_eq!(Rounding::Nearest.div(10, 3), 3);
+    assert_eq!(Rounding::Nearest.div(11, 3), 4);
+
+    // even divisions are never rounded
+    for rounding in [Rounding::Floor, Rounding::Ceil, Rounding::Nearest].iter() {
+        assert_eq!(rounding.div(9, 3), 3);
+        assert_eq!(rounding.div(0, 3), 0);
+    }
+
+    // a 30 bps fee on 1_001 is 3.003
+    assert_eq!(Rounding::Floor.apply_bps(1_001, 30), 3);
+    assert_eq!(Rounding::Ceil.apply_bps(1_001, 30), 4);
+    assert_eq!(Rounding::Nearest.apply_bps(1_001, 30), 3);
+
+    // a notional of 10 * 15 / 100 = 1.5
+    let trade = Trade {
+        price_scale: 100,
+        rounding:    Rounding::Ceil,
+        ..Default::default()
+    };
+    assert_eq!(trade.notional(10, 15), 2);
+    let trade = Trade {
+        rounding: Rounding::Floor,
+        ..trade
+    };
+    assert_eq!(trade.notional(10, 15), 1);
+}
+
+#[test]
+fn test_get_deals_by_range() {
+    let cycles_limit = 1024 * 1024 * 1024; // 1073741824
+    let caller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let context = mock_context(cycles_limit, caller);
+
+    let mut service = new_dex_service();
+
+    let trade_id = Hash::digest(Bytes::from_static(b"trade"));
+    let other_trade_id = Hash::digest(Bytes::from_static(b"other"));
+    for height in 1..=10 {
+        service.deal_log.push(DealRecord {
+            trade_id: trade_id.clone(),
+            price: 10,
+            amount: height,
+            height,
+            ..DealRecord::default()
+        });
+        service.deal_log.push(DealRecord {
+            trade_id: other_trade_id.clone(),
+            price: 10,
+            amount: height,
+            height,
+            ..DealRecord::default()
+        });
+    }
+
+    let deals = service
+        .get_deals_by_range(context.clone(), GetDealsByRangePayload {
+            trade_id:    trade_id.clone(),
+            from_height: 3,
+            to_height:   8,
+            limit:       4,
+        })
+        .succeed_data
+        .deals;
+
+    let heights: Vec<u64> = deals.iter().map(|deal| deal.height).collect();
+    assert_eq!(heights, vec![8, 7, 6, 5]);
+    assert!(deals.iter().all(|deal| deal.trade_id == trade_id));
+
+    let res = service.get_deals_by_range(context, GetDealsByRangePayload {
+        trade_id,
+        from_height: 8,
+        to_height: 3,
+        limit: 4,
+    });
+    assert!(res.is_error());
+}
+
+#[test]
+fn test_24h_stats() {
+    let caller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+
+    let trade_id = Hash::digest(Bytes::from_static(b"trade"));
+    let other_trade_id = Hash::digest(Bytes::from_static(b"other"));
+    let deals: Vec<(&Hash, u64, u64, u64)> = vec![
+        (&trade_id, 2, 50, 5),
+        (&trade_id, 5, 12, 10),
+        (&trade_id, 6, 15, 20),
+        (&other_trade_id, 7, 99, 40),
+        (&trade_id, 8, 9, 30),
+        (&trade_id, 10, 11, 5),
+    ];
+    for (trade_id, height, price, amount) in deals.into_iter() {
+        service.deal_log.push(DealRecord {
+            trade_id: trade_id.clone(),
+            price,
+            amount,
+            height,
+            ..DealRecord::default()
+        });
+    }
+
+    let stats = |service: &TestDexService, height: u64, blocks_per_day: u64| {
+        let context = mock_tx_context(caller.clone(), Hash::from_empty(), height);
+        service
+            .get_24h_stats(context, Get24hStatsPayload {
+                trade_id: trade_id.clone(),
+                blocks_per_day,
+            })
+            .succeed_data
+    };
+
+    // heights 5 to 10, leaving out the deal at 2 and the other trade's
+    assert_eq!(stats(&service, 10, 6), Get24hStatsResponse {
+        open:   12,
+        high:   15,
+        low:    9,
+        close:  11,
+        volume: 65,
+        deals:  4,
+    });
+    // deals above the current height aren't in the window
+    assert_eq!(stats(&service, 8, 3).close, 9);
+    assert_eq!(stats(&service, 8, 3).deals, 2);
+
+    assert_eq!(stats(&service, 20, 5), Get24hStatsResponse::default());
+    assert_eq!(stats(&service, 10, 0), Get24hStatsResponse::default());
+}
+
+#[test]
+fn test_order_index() {
+    let mut service = new_dex_service();
+
+    let trade_id = Hash::digest(Bytes::from_static(b"trade"));
+    let new_order = |kind: OrderKind, seed: &'static [u8]| Order {
+        trade_id: trade_id.clone(),
+        tx_hash: Hash::digest(Bytes::from_static(seed)),
+        kind,
+        price: 10,
+        amount: 100,
+        ..Default::default()
+    };
+
+    let buy_1 = new_order(OrderKind::Buy, b"buy_1");
+    let buy_2 = new_order(OrderKind::Buy, b"buy_2");
+    let sell = new_order(OrderKind::Sell, b"sell");
+    service.insert_order(buy_1.clone(), 1);
+    service.insert_order(buy_2.clone(), 1);
+    service.insert_order(sell.clone(), 1);
+    // re-inserting an open order must not duplicate it in the index
+    service.insert_order(buy_1.clone(), 1);
+
+    assert_eq!(service.get_open_orders(&trade_id, OrderKind::Buy).len(), 2);
+    assert_eq!(service.get_open_orders(&trade_id, OrderKind::Sell).len(), 1);
+
+    let mut filled = buy_1.clone();
+    filled.status = OrderStatus::Full;
+    service.update_book(filled, 2);
+    service.remove_order(&sell, 2);
+
+    let buys = service.get_open_orders(&trade_id, OrderKind::Buy);
+    assert_eq!(buys.len(), 1);
+    assert!(buys[0].tx_hash == buy_2.tx_hash);
+    assert!(service.get_open_orders(&trade_id, OrderKind::Sell).is_empty());
+}
+
+#[test]
+fn test_referral_fee() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let referrer = Address::from_hex("0x777cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let treasury = Address::from_hex("0x888cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        fee_rate: 100,
+        referral_rate: 5000,
+        fee_account: treasury.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    // the sell rests first, so the buy is the taker and pays 1% of 1000 counter
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 1000);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let mut buy = order_payload(&trade, OrderKind::Buy, 10, 1000);
+    buy.referrer = Some(referrer.clone());
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+
+    service.match_trade(&trade, 2);
+
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 990);
+    assert_eq!(get_balance(&service, &referrer, &counter).current, 5);
+    assert_eq!(get_balance(&service, &treasury, &counter).current, 5);
+    assert_eq!(get_balance(&service, &seller, &base).current, 10_000);
+}
+
+#[test]
+fn test_maker_rebate() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let treasury = Address::from_hex("0x888cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        fee_rate: 100,
+        maker_rebate: 40,
+        fee_account: treasury.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    // the buy is the taker and pays 10 counter, 4 of which go to the seller
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 1000);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 1000);
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+
+    service.match_trade(&trade, 2);
+
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 990);
+    assert_eq!(get_balance(&service, &seller, &counter).current, 999_004);
+    assert_eq!(get_balance(&service, &treasury, &counter).current, 6);
+    assert_eq!(get_balance(&service, &seller, &base).current, 10_000);
+}
+
+#[test]
+fn test_set_fee_rate() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let treasury = Address::from_hex("0x888cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        fee_rate: 100,
+        maker_rebate: 10,
+        fee_account: treasury.clone(),
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let res = service.set_fee_rate(context, SetFeeRatePayload { fee_rate: 200 });
+    assert_eq!(res.code, 209);
+
+    // above the cap, and below the maker rebate it would have to fund
+    let context = mock_context(CYCLES_LIMIT, admin.clone());
+    let res = service.set_fee_rate(context, SetFeeRatePayload { fee_rate: 1001 });
+    assert_eq!(res.code, 227);
+    let context = mock_context(CYCLES_LIMIT, admin.clone());
+    let res = service.set_fee_rate(context, SetFeeRatePayload { fee_rate: 5 });
+    assert_eq!(res.code, 227);
+
+    let context = mock_context(CYCLES_LIMIT, admin.clone());
+    let res = service.set_fee_rate(context.clone(), SetFeeRatePayload { fee_rate: 200 });
+    assert!(!res.is_error());
+    assert_eq!(service.get_config(context).succeed_data.fee_rate, 200);
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    // the buy is the taker and pays 2% of 1000 counter, 1 of which goes to
+    // the seller as rebate
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 1000);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 1000);
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+
+    service.match_trade(&trade, 2);
+
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 980);
+    assert_eq!(get_balance(&service, &treasury, &counter).current, 19);
+}
+
+#[test]
+fn test_fee_bounds() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let treasury = Address::from_hex("0x888cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        fee_rate: 100,
+        fee_account: treasury.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let mut payload = trade_payload(&base, &counter);
+    payload.min_fee = 5;
+    payload.max_fee = 50;
+    let trade = add_trade(&mut service, payload);
+
+    // the sell is the taker and receives 30 base, 1% of which floors to 5
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 3);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 3);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 2, sell).is_error());
+    service.match_trade(&trade, 2);
+
+    assert_eq!(get_balance(&service, &seller, &base).current, 25);
+    assert_eq!(get_balance(&service, &treasury, &base).current, 5);
+
+    // the buy is the taker and receives 10_000 counter, 1% of which caps to 50
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10_000);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 3, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10_000);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 4, buy).is_error());
+    service.match_trade(&trade, 4);
+
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 3 + 9950);
+    assert_eq!(get_balance(&service, &treasury, &counter).current, 50);
+    assert_eq!(get_balance(&service, &seller, &base).current, 25 + 100_000);
+}
+
+#[test]
+fn test_stats() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 11, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2).is_error());
+    let sell_3 = order_payload(&trade, OrderKind::Sell, 12, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_3", 1, sell_3).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 12, 30);
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+
+    service.match_trade(&trade, 2);
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let stats = service.get_stats(context).succeed_data;
+    assert_eq!(stats.total_deals, 3);
+    assert_eq!(stats.trade_deals, vec![TradeDealCount {
+        trade_id: trade.id,
+        deals:    3,
+    }]);
+}
+
+#[test]
+fn test_export_state() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let genesis = GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    };
+    let mut service = new_dex_service();
+    service.init_genesis(genesis.clone());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let other_counter = create_asset(&mut service, &seller, "OCNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+    let other_trade = add_trade(&mut service, trade_payload(&base, &other_counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 30);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let low_buy = order_payload(&trade, OrderKind::Buy, 8, 10);
+    assert!(!place_order(&mut service, &buyer, b"low_buy", 1, low_buy).is_error());
+    let other_sell = order_payload(&other_trade, OrderKind::Sell, 20, 5);
+    assert!(!place_order(&mut service, &seller, b"other_sell", 1, other_sell).is_error());
+    service.match_trade(&trade, 1);
+
+    let export = |service: &TestDexService, start: u64, limit: u64| {
+        let context = mock_context(CYCLES_LIMIT, admin.clone());
+        service
+            .export_state(context, ExportStatePayload { start, limit })
+            .succeed_data
+    };
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let res = service.export_state(context, ExportStatePayload { start: 0, limit: 10 });
+    assert_eq!(res.code, 209);
+
+    // pages of 2 split the trades from the orders and the orders apart
+    let mut trades = Vec::new();
+    let mut orders = Vec::new();
+    let mut start = 0;
+    loop {
+        let page = export(&service, start, 2);
+        assert_eq!(page.total, 5);
+        if page.trades.is_empty() && page.orders.is_empty() {
+            break;
+        }
+        start += (page.trades.len() + page.orders.len()) as u64;
+        trades.extend(page.trades);
+        orders.extend(page.orders);
+    }
+    let full = export(&service, 0, 10);
+    assert_eq!(trades, full.trades);
+    assert_eq!(orders, full.orders);
+    assert_eq!(trades.len(), 2);
+    assert_eq!(orders.len(), 3);
+    assert!(orders.iter().any(|order| order.filled == 10 && order.deals.len() == 1));
+
+    let mut replayed = new_dex_service();
+    replayed.init_genesis(GenesisPayload {
+        trades,
+        orders,
+        ..genesis
+    });
+    let replayed_full = export(&replayed, 0, 10);
+    assert_eq!(replayed_full.trades, full.trades);
+    assert_eq!(replayed_full.orders, full.orders);
+    assert_eq!(replayed.get_open_orders(&trade.id, OrderKind::Buy).len(), 1);
+    assert_eq!(replayed.get_open_orders(&other_trade.id, OrderKind::Sell).len(), 1);
+}
+
+#[test]
+fn test_get_order_deals() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let seeds: [&'static [u8]; 5] = [b"sell_1", b"sell_2", b"sell_3", b"sell_4", b"sell_5"];
+    for seed in seeds.iter() {
+        let sell = order_payload(&trade, OrderKind::Sell, 10, 2);
+        assert!(!place_order(&mut service, &seller, *seed, 1, sell).is_error());
+    }
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+
+    service.match_trade(&trade, 2);
+
+    let tx_hash = Hash::digest(Bytes::from_static(b"buy"));
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let order = service.get_order(context.clone(), GetOrderPayload {
+        tx_hash:    tx_hash.clone(),
+        with_deals: false,
+    });
+    assert_eq!(order.succeed_data.order_status, OrderStatus::Full);
+    assert!(order.succeed_data.deals.is_empty());
+
+    let mut deals = Vec::new();
+    let mut start = 0;
+    loop {
+        let page = service
+            .get_order_deals(context.clone(), GetOrderDealsPayload {
+                tx_hash: tx_hash.clone(),
+                start,
+                limit:   2,
+            })
+            .succeed_data;
+        assert_eq!(page.total, 5);
+        if page.deals.is_empty() {
+            break;
+        }
+        start += page.deals.len() as u64;
+        deals.extend(page.deals);
+    }
+    assert_eq!(deals.len(), 5);
+    assert!(deals.iter().all(|deal| deal.amount == 2));
+}
+
+#[test]
+fn test_fill_ratio() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 3);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 5, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 1, buy_2).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 1);
+    assert!(!place_order(&mut service, &seller, b"sell", 2, sell).is_error());
+    service.match_trade(&trade, 2);
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let fill_ratio = |service: &TestDexService, seed: &'static [u8]| {
+        service
+            .get_order(context.clone(), GetOrderPayload {
+                tx_hash:    Hash::digest(Bytes::from_static(seed)),
+                with_deals: false,
+            })
+            .succeed_data
+            .fill_ratio_bps
+    };
+    assert_eq!(fill_ratio(&service, b"buy_1"), 3333);
+    assert_eq!(fill_ratio(&service, b"buy_2"), 0);
+    assert_eq!(fill_ratio(&service, b"sell"), 10_000);
+}
+
+#[test]
+fn test_filled_tracking() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 30);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+
+    // each sell fills a third of the buy
+    let tx_hash = Hash::digest(Bytes::from_static(b"buy"));
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 2, sell.clone()).is_error());
+    service.match_trade(&trade, 2);
+    let order = service.get_open_order(&tx_hash).succeed_data;
+    assert_eq!(order.status, OrderStatus::Partial);
+    assert_eq!(order.filled, 10);
+
+    assert!(!place_order(&mut service, &seller, b"sell_2", 3, sell.clone()).is_error());
+    service.match_trade(&trade, 3);
+    let order = service.get_open_order(&tx_hash).succeed_data;
+    assert_eq!(order.status, OrderStatus::Partial);
+    assert_eq!(order.filled, 20);
+    assert_eq!(order.remaining(), 10);
+
+    assert!(!place_order(&mut service, &seller, b"sell_3", 4, sell).is_error());
+    service.match_trade(&trade, 4);
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let order = service
+        .get_order(context, GetOrderPayload {
+            tx_hash,
+            with_deals: true,
+        })
+        .succeed_data;
+    assert_eq!(order.order_status, OrderStatus::Full);
+    assert_eq!(order.filled, 30);
+    assert_eq!(order.deals.len(), 3);
+}
+
+#[test]
+fn test_order_receipt() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        fee_rate: 100,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 100);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 12, 100);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 12, 200);
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+    service.match_trade(&trade, 2);
+
+    // the buy takes 100 at the midpoint 11 and 100 at 12, paying 1% of each
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let receipt = service
+        .get_order_receipt(context, GetOrderReceiptPayload {
+            tx_hash: Hash::digest(Bytes::from_static(b"buy")),
+        })
+        .succeed_data;
+    assert_eq!(receipt.status, OrderStatus::Full);
+    assert_eq!(receipt.filled, 200);
+    assert_eq!(receipt.avg_price, 11);
+    assert_eq!(receipt.fees, 2);
+    assert_eq!(receipt.deal_heights, vec![2, 2]);
+}
+
+#[test]
+fn test_frozen_asset() {
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell.clone()).is_error());
+
+    let context = mock_context(CYCLES_LIMIT, seller.clone());
+    let res = service.asset.freeze(context, FreezePayload {
+        asset_id: base,
+        frozen:   true,
+    });
+    assert!(!res.is_error());
+
+    let res = place_order(&mut service, &seller, b"sell_2", 1, sell);
+    assert_eq!(res.code, 213);
+}
+
+#[test]
+fn test_dex_tradable() {
+    let issuer = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let holder = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &issuer, "BASE");
+    let counter = create_asset(&mut service, &issuer, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let context = mock_context(CYCLES_LIMIT, holder.clone());
+    let res = service.asset.set_dex_tradable(context, SetDexTradablePayload {
+        asset_id: counter.clone(),
+        tradable: false,
+    });
+    assert!(res.is_error());
+
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let res = service.asset.set_dex_tradable(context.clone(), SetDexTradablePayload {
+        asset_id: counter.clone(),
+        tradable: false,
+    });
+    assert!(!res.is_error());
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    let res = place_order(&mut service, &issuer, b"sell", 1, sell.clone());
+    assert_eq!(res.code, 225);
+
+    let res = service.asset.transfer(context.clone(), TransferPayload {
+        asset_id:        counter.clone(),
+        to:              holder.clone(),
+        value:           100,
+        from_subaccount: String::new(),
+    });
+    assert!(!res.is_error());
+    assert_eq!(get_balance(&service, &holder, &counter).current, 100);
+
+    let res = service.asset.set_dex_tradable(context, SetDexTradablePayload {
+        asset_id: counter,
+        tradable: true,
+    });
+    assert!(!res.is_error());
+    assert!(!place_order(&mut service, &issuer, b"sell", 1, sell).is_error());
+}
+
+#[test]
+fn test_cancel_partial_order() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let other_buy = order_payload(&trade, OrderKind::Buy, 5, 100);
+    assert!(!place_order(&mut service, &buyer, b"other_buy", 1, other_buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 30);
+    assert!(!place_order(&mut service, &seller, b"sell", 2, sell).is_error());
+
+    service.match_trade(&trade, 2);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 700 + 500);
+
+    let tx_hash = Hash::digest(Bytes::from_static(b"buy"));
+    let context = mock_context(CYCLES_LIMIT, seller);
+    let res = service.cancel_order(context, CancelOrderPayload {
+        tx_hash: tx_hash.clone(),
+    });
+    assert_eq!(res.code, 209);
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    assert!(!service
+        .cancel_order(context, CancelOrderPayload { tx_hash })
+        .is_error());
+
+    // only the 70 unfilled at 10 are released, other_buy stays locked
+    let balance = get_balance(&service, &buyer, &base);
+    assert_eq!(balance.locked, 500);
+    assert_eq!(balance.current, 1_000_000 - 300 - 500);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Buy).len(), 1);
+}
+
+#[test]
+fn test_cancel_partial_sell() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 100);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let other_sell = order_payload(&trade, OrderKind::Sell, 12, 50);
+    assert!(!place_order(&mut service, &seller, b"other_sell", 1, other_sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 30);
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+
+    service.match_trade(&trade, 2);
+    assert_eq!(get_balance(&service, &seller, &counter).locked, 70 + 50);
+
+    let context = mock_context(CYCLES_LIMIT, seller.clone());
+    assert!(!service
+        .cancel_order(context, CancelOrderPayload {
+            tx_hash: Hash::digest(Bytes::from_static(b"sell")),
+        })
+        .is_error());
+
+    // a sell locks the counter party, so the 70 unfilled go back in it and
+    // the base the seller received is left alone
+    let balance = get_balance(&service, &seller, &counter);
+    assert_eq!(balance.locked, 50);
+    assert_eq!(balance.current, 1_000_000 - 30 - 50);
+    let balance = get_balance(&service, &seller, &base);
+    assert_eq!(balance.current, 300);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Sell).len(), 1);
+}
+
+#[test]
+fn test_cancel_by_client_id() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let other = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &other, "BASE");
+    let counter = create_asset(&mut service, &user, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = OrderPayload {
+        client_order_id: Some("sell-1".to_owned()),
+        ..order_payload(&trade, OrderKind::Sell, 10, 100)
+    };
+    assert!(!place_order(&mut service, &user, b"sell", 1, sell.clone()).is_error());
+    // the id is taken while its order is open, but only for the same user
+    let res = place_order(&mut service, &user, b"sell_again", 1, sell);
+    assert_eq!(res.code, 223);
+    let buy = OrderPayload {
+        client_order_id: Some("sell-1".to_owned()),
+        ..order_payload(&trade, OrderKind::Buy, 8, 10)
+    };
+    assert!(!place_order(&mut service, &other, b"buy", 1, buy).is_error());
+    assert_eq!(get_balance(&service, &user, &counter).locked, 100);
+
+    let cancel = |service: &mut TestDexService, client_order_id: &str| {
+        let context = mock_context(CYCLES_LIMIT, user.clone());
+        service.cancel_by_client_id(context, CancelByClientIdPayload {
+            client_order_id: client_order_id.to_owned(),
+        })
+    };
+    assert_eq!(cancel(&mut service, "sell-2").code, 206);
+    assert!(!cancel(&mut service, "sell-1").is_error());
+    assert_eq!(get_balance(&service, &user, &counter).locked, 0);
+    assert!(service
+        .get_open_order(&Hash::digest(Bytes::from_static(b"sell")))
+        .is_error());
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Buy).len(), 1);
+    assert_eq!(cancel(&mut service, "sell-1").code, 206);
+
+    // and free again once the order is gone
+    let sell = OrderPayload {
+        client_order_id: Some("sell-1".to_owned()),
+        ..order_payload(&trade, OrderKind::Sell, 10, 100)
+    };
+    assert!(!place_order(&mut service, &user, b"sell_again", 2, sell).is_error());
+}
+
+#[test]
+fn test_amend_order() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 2, buy_2).is_error());
+
+    let tx_hash = Hash::digest(Bytes::from_static(b"buy_1"));
+    let amend = |service: &mut TestDexService, price: u64, height: u64| {
+        let context = mock_tx_context(buyer.clone(), Hash::from_empty(), height);
+        service.amend_order(context, AmendOrderPayload {
+            tx_hash: tx_hash.clone(),
+            price,
+            amount: 50,
+        })
+    };
+    let best_buy = |service: &TestDexService| {
+        let mut buys = service.get_open_orders(&trade.id, OrderKind::Buy);
+        buys.sort();
+        buys.pop().unwrap()
+    };
+
+    // shrinking the order at its price keeps its place in the queue
+    assert!(!amend(&mut service, 10, 3).is_error());
+    let best = best_buy(&service);
+    assert_eq!(best.tx_hash, tx_hash);
+    assert_eq!(best.height, 1);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 500 + 1000);
+
+    // moving it away and back costs it its priority
+    assert!(!amend(&mut service, 11, 4).is_error());
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 550 + 1000);
+    assert!(!amend(&mut service, 10, 5).is_error());
+    let best = best_buy(&service);
+    assert_eq!(best.tx_hash, Hash::digest(Bytes::from_static(b"buy_2")));
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 500 + 1000);
+
+    // no amending on a pair that's been delisted
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let res = service.asset.set_dex_tradable(context, SetDexTradablePayload {
+        asset_id: base.clone(),
+        tradable: false,
+    });
+    assert!(!res.is_error());
+    assert_eq!(amend(&mut service, 11, 6).code, 225);
+
+    let context = mock_context(CYCLES_LIMIT, seller);
+    let res = service.amend_order(context, AmendOrderPayload {
+        tx_hash,
+        price: 10,
+        amount: 50,
+    });
+    assert_eq!(res.code, 209);
+}
+
+#[test]
+fn test_increase_order() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 2, buy_2).is_error());
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 30);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 3, sell_1).is_error());
+    service.match_trade(&trade, 3);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 700 + 1000);
+
+    let increase = |service: &mut TestDexService,
+                    user: &Address,
+                    seed: &'static [u8],
+                    additional_amount: u64| {
+        let context = mock_tx_context(user.clone(), Hash::from_empty(), 4);
+        service.increase_order(context, IncreaseOrderPayload {
+            tx_hash: Hash::digest(Bytes::from_static(seed)),
+            additional_amount,
+        })
+    };
+
+    // the partly filled order grows by 50 to 150, and locks only for those
+    assert!(!increase(&mut service, &buyer, b"buy_1", 50).is_error());
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 1200 + 1000);
+    let mut buys = service.get_open_orders(&trade.id, OrderKind::Buy);
+    buys.sort();
+    let best = buys.pop().unwrap();
+    assert_eq!(best.tx_hash, Hash::digest(Bytes::from_static(b"buy_1")));
+    assert_eq!((best.amount, best.filled, best.height), (150, 30, 1));
+
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 12, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 4, sell_2).is_error());
+    assert!(!increase(&mut service, &seller, b"sell_2", 5).is_error());
+    assert_eq!(get_balance(&service, &seller, &counter).locked, 15);
+
+    assert_eq!(increase(&mut service, &seller, b"buy_1", 5).code, 209);
+    assert_eq!(increase(&mut service, &buyer, b"buy_1", 0).code, 222);
+    // sell_1 is Full and no longer in the book
+    assert_eq!(increase(&mut service, &seller, b"sell_1", 5).code, 206);
+
+    // resting orders of a disabled trade can't grow
+    let admin = Address::from_hash(Hash::from_empty()).unwrap();
+    let context = mock_context(CYCLES_LIMIT, admin);
+    let res = service.set_trade_enabled(context, SetTradeEnabledPayload {
+        trade_id:      trade.id.clone(),
+        enabled:       false,
+        cancel_orders: false,
+    });
+    assert!(!res.is_error());
+    assert_eq!(increase(&mut service, &buyer, b"buy_1", 50).code, 216);
+}
+
+#[test]
+fn test_move_order() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let other_counter = create_asset(&mut service, &seller, "OCNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+    let other_trade = add_trade(&mut service, trade_payload(&base, &other_counter));
+
+    // trade ids are canonical now, so a duplicate pair can only be one added
+    // before they were
+    let duplicate = Trade {
+        id: Hash::digest(Bytes::from_static(b"duplicate")),
+        ..trade.clone()
+    };
+    service.trades.insert(duplicate.id.clone(), duplicate.clone());
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let tx_hash = Hash::digest(Bytes::from_static(b"sell"));
+    let move_payload = |new_trade_id: &Hash| MoveOrderPayload {
+        tx_hash:      tx_hash.clone(),
+        new_trade_id: new_trade_id.clone(),
+    };
+
+    let context = mock_context(CYCLES_LIMIT, seller.clone());
+    let res = service.move_order(context, move_payload(&duplicate.id));
+    assert_eq!(res.code, 209);
+    let context = mock_context(CYCLES_LIMIT, admin.clone());
+    let res = service.move_order(context, move_payload(&other_trade.id));
+    assert_eq!(res.code, 202);
+
+    let context = mock_context(CYCLES_LIMIT, admin);
+    assert!(!service.move_order(context, move_payload(&duplicate.id)).is_error());
+    assert!(service.get_open_orders(&trade.id, OrderKind::Sell).is_empty());
+    let moved = service.get_open_order(&tx_hash).succeed_data;
+    assert_eq!(moved.trade_id, duplicate.id);
+    assert!(service.get_open_orders(&duplicate.id, OrderKind::Sell) == vec![moved]);
+
+    // it rests and settles on the new trade with the lock it came with
+    let buy = order_payload(&duplicate, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+    service.match_trade(&duplicate, 2);
+
+    assert!(service.history_orders.contains(&tx_hash));
+    let balance = get_balance(&service, &seller, &counter);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(balance.current, 1_000_000 - 10);
+    assert_eq!(get_balance(&service, &seller, &base).current, 100);
+}
+
+#[test]
+fn test_market_order_avg_price_cap() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 11, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2).is_error());
+    let sell_3 = order_payload(&trade, OrderKind::Sell, 14, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_3", 1, sell_3).is_error());
+
+    // 5 at 10 and 5 at 11 average 10.5, so only 1 more fits at 14 under 11
+    let context = mock_tx_context(buyer.clone(), Hash::digest(Bytes::from_static(b"market")), 2);
+    let res = service.market_order(context, MarketOrderPayload {
+        trade_id:      trade.id.clone(),
+        kind:          OrderKind::Buy,
+        amount:        15,
+        max_avg_price: Some(11),
+        min_avg_price: None,
+        quote_amount:  None,
+    });
+    assert!(!res.is_error());
+    assert_eq!(res.succeed_data.filled, 11);
+    assert_eq!(res.succeed_data.avg_price, 10);
+
+    let sells = service.get_open_orders(&trade.id, OrderKind::Sell);
+    assert_eq!(sells.len(), 1);
+    assert_eq!(sells[0].remaining(), 4);
+
+    let balance = get_balance(&service, &buyer, &base);
+    assert_eq!(balance.current, 1_000_000 - 50 - 55 - 14);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 11);
+}
+
+#[test]
+fn test_convert_to_market() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 5, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 4);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 12, 4);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2).is_error());
+    service.match_trade(&trade, 1);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 50);
+
+    let tx_hash = Hash::digest(Bytes::from_static(b"buy"));
+    let payload = ConvertToMarketPayload {
+        tx_hash:       tx_hash.clone(),
+        max_avg_price: None,
+        min_avg_price: None,
+    };
+    let context = mock_tx_context(seller, Hash::digest(Bytes::from_static(b"convert")), 2);
+    let res = service.convert_to_market(context, payload.clone());
+    assert_eq!(res.code, 209);
+
+    // a disabled side can't be converted either
+    let mut params = UpdateTradeParamsPayload {
+        trade_id:     trade.id.clone(),
+        tick_size:    1,
+        lot_size:     1,
+        min_notional: 0,
+        allow_buy:    false,
+        allow_sell:   true,
+        validity:     0,
+    };
+    let admin = mock_genesis().admin;
+    let context = mock_context(CYCLES_LIMIT, admin.clone());
+    let res = service.update_trade_params(context, params.clone());
+    assert!(!res.is_error());
+    let context = mock_tx_context(buyer.clone(), Hash::digest(Bytes::from_static(b"convert")), 2);
+    let res = service.convert_to_market(context, payload.clone());
+    assert_eq!(res.code, 216);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 50);
+    params.allow_buy = true;
+    let res = service.update_trade_params(mock_context(CYCLES_LIMIT, admin), params);
+    assert!(!res.is_error());
+
+    // both sells are swept and the last 2 are cancelled
+    let context = mock_tx_context(buyer.clone(), Hash::digest(Bytes::from_static(b"convert")), 2);
+    let res = service.convert_to_market(context.clone(), payload.clone());
+    assert!(!res.is_error());
+    assert_eq!(res.succeed_data.filled, 8);
+    assert_eq!(res.succeed_data.avg_price, 11);
+
+    assert!(service.get_open_orders(&trade.id, OrderKind::Buy).is_empty());
+    assert!(service.get_open_orders(&trade.id, OrderKind::Sell).is_empty());
+    let balance = get_balance(&service, &buyer, &base);
+    assert_eq!(balance.current, 1_000_000 - 40 - 48);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 8);
+
+    let order = service.get_order(context.clone(), GetOrderPayload {
+        tx_hash,
+        with_deals: true,
+    });
+    assert_eq!(order.succeed_data.order_status, OrderStatus::Partial);
+    assert_eq!(order.succeed_data.filled, 8);
+    assert_eq!(order.succeed_data.deals.len(), 2);
+
+    let res = service.convert_to_market(context, payload);
+    assert_eq!(res.code, 206);
+}
+
+#[test]
+fn test_insufficient_funds_to_lock() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &buyer, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let first_buy = order_payload(&trade, OrderKind::Buy, 10, 60_000);
+    assert!(!place_order(&mut service, &buyer, b"first_buy", 1, first_buy).is_error());
+
+    // 50_000 at 10 needs 500_000 base, but only 400_000 is left unlocked
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 50_000);
+    let res = place_order(&mut service, &buyer, b"buy", 1, buy);
+    assert_eq!(res.code, 214);
+    assert!(res.error_message.contains("required 500000 available 400000"));
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 600_000);
+}
+
+#[test]
+fn test_order_above_supply() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 1_000_001);
+    let res = place_order(&mut service, &seller, b"sell", 1, sell);
+    assert_eq!(res.code, 222);
+
+    // 100_001 at 10 needs more base than exists
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 100_001);
+    let res = place_order(&mut service, &buyer, b"buy", 1, buy);
+    assert_eq!(res.code, 222);
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 100_000);
+    assert!(!place_order(&mut service, &buyer, b"full_buy", 1, buy).is_error());
+}
+
+#[test]
+fn test_split_order() {
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 100);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let tx_hash = Hash::digest(Bytes::from_static(b"sell"));
+
+    let context = mock_tx_context(seller.clone(), tx_hash.clone(), 2);
+    let res = service.split_order(context.clone(), SplitOrderPayload {
+        tx_hash: tx_hash.clone(),
+        amounts: vec![30, 30],
+    });
+    assert_eq!(res.code, 215);
+
+    let res = service.split_order(context, SplitOrderPayload {
+        tx_hash: tx_hash.clone(),
+        amounts: vec![50, 30, 20],
+    });
+    assert!(!res.is_error());
+    assert_eq!(res.succeed_data.tx_hashes.len(), 3);
+
+    let sells = service.get_open_orders(&trade.id, OrderKind::Sell);
+    assert_eq!(sells.len(), 3);
+    assert!(sells.iter().all(|order| order.price == 10 && order.height == 2));
+    assert!(sells.iter().all(|order| order.tx_hash != tx_hash));
+    assert_eq!(sells.iter().map(|order| order.amount).sum::<u64>(), 100);
+    assert_eq!(get_balance(&service, &seller, &counter).locked, 100);
+}
+
+#[test]
+fn test_split_order_rounding() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let run = |rounding: Rounding| {
+        let mut service = new_dex_service();
+        service.init_genesis(GenesisPayload {
+            rounding,
+            ..mock_genesis()
+        });
+
+        let base = create_asset(&mut service, &buyer, "BASE");
+        let counter = create_asset(&mut service, &buyer, "CNT");
+        let trade = add_trade(&mut service, AddTradePayload {
+            price_scale: 2,
+            ..trade_payload(&base, &counter)
+        });
+
+        let buy = order_payload(&trade, OrderKind::Buy, 1, 2);
+        assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+        assert_eq!(get_balance(&service, &buyer, &base).locked, 1);
+
+        let tx_hash = Hash::digest(Bytes::from_static(b"buy"));
+        let context = mock_tx_context(buyer.clone(), tx_hash.clone(), 2);
+        let res = service.split_order(context, SplitOrderPayload {
+            tx_hash,
+            amounts: vec![1, 1],
+        });
+        assert!(!res.is_error());
+        get_balance(&service, &buyer, &base)
+    };
+
+    // each half of 1 at a scale of 2 is worth 0.5, rounded down to nothing
+    // and up to 1 apiece
+    let balance = run(Rounding::Floor);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(balance.current, 1_000_000);
+    let balance = run(Rounding::Ceil);
+    assert_eq!(balance.locked, 2);
+    assert_eq!(balance.current, 1_000_000 - 2);
+}
+
+#[test]
+fn test_market_order_quote_amount() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 12, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2).is_error());
+
+    // 50 buys 5 at 10, the other 50 covers only 4 at 12 and 2 is refunded
+    let context = mock_tx_context(buyer.clone(), Hash::digest(Bytes::from_static(b"market")), 2);
+    let res = service.market_order(context, MarketOrderPayload {
+        trade_id:      trade.id.clone(),
+        kind:          OrderKind::Buy,
+        amount:        0,
+        max_avg_price: None,
+        min_avg_price: None,
+        quote_amount:  Some(100),
+    });
+    assert!(!res.is_error());
+    assert_eq!(res.succeed_data.filled, 9);
+
+    let balance = get_balance(&service, &buyer, &base);
+    assert_eq!(balance.current, 1_000_000 - 98);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 9);
+}
+
+#[test]
+fn test_history() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    service.match_trade(&trade, 1);
+
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 2, sell_2).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 2, buy_2).is_error());
+    service.match_trade(&trade, 2);
+
+    let sell_3 = order_payload(&trade, OrderKind::Sell, 20, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_3", 2, sell_3).is_error());
+
+    let context = mock_context(CYCLES_LIMIT, admin.clone());
+    let history = |service: &TestDexService, user: Option<Address>, start: u64| {
+        service
+            .get_history(context.clone(), GetHistoryPayload {
+                user,
+                start,
+                limit: 1,
+            })
+            .succeed_data
+            .orders
+    };
+    assert_eq!(history(&service, Some(seller.clone()), 0).len(), 1);
+    assert_eq!(history(&service, Some(seller.clone()), 1).len(), 1);
+    assert!(history(&service, Some(seller.clone()), 2).is_empty());
+    assert_eq!(history(&service, None, 3).len(), 1);
+    assert!(history(&service, None, 4).is_empty());
+
+    let context = mock_context(CYCLES_LIMIT, seller.clone());
+    let res = service.prune_history(context, PruneHistoryPayload { before_height: 2 });
+    assert_eq!(res.code, 209);
+
+    let context = mock_context(CYCLES_LIMIT, admin);
+    let res = service.prune_history(context, PruneHistoryPayload { before_height: 2 });
+    assert_eq!(res.succeed_data.pruned, 2);
+    assert!(history(&service, None, 2).is_empty());
+    assert!(history(&service, None, 0).iter().all(|order| order.height == 2));
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Sell).len(), 1);
+}
+
+#[test]
+fn test_sell_only_trade() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &user, "BASE");
+    let counter = create_asset(&mut service, &user, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let context = mock_context(CYCLES_LIMIT, admin);
+    let res = service.update_trade_params(context, UpdateTradeParamsPayload {
+        trade_id:     trade.id.clone(),
+        tick_size:    1,
+        lot_size:     1,
+        min_notional: 0,
+        allow_buy:    false,
+        allow_sell:   true,
+        validity:     0,
+    });
+    assert!(!res.is_error());
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert_eq!(place_order(&mut service, &user, b"buy", 1, buy).code, 216);
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &user, b"sell", 1, sell).is_error());
+}
+
+#[test]
+fn test_compute_deal_price() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &user, "BASE");
+    let counter = create_asset(&mut service, &user, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &user, b"sell", 1, sell).is_error());
+    let high_buy = order_payload(&trade, OrderKind::Buy, 14, 10);
+    assert!(!place_order(&mut service, &user, b"high_buy", 1, high_buy).is_error());
+    let low_buy = order_payload(&trade, OrderKind::Buy, 8, 10);
+    assert!(!place_order(&mut service, &user, b"low_buy", 1, low_buy).is_error());
+
+    let sell_tx_hash = Hash::digest(Bytes::from_static(b"sell"));
+    let context = mock_context(CYCLES_LIMIT, user);
+    let res = service.compute_deal_price(context.clone(), ComputeDealPricePayload {
+        buy_tx_hash:  Hash::digest(Bytes::from_static(b"high_buy")),
+        sell_tx_hash: sell_tx_hash.clone(),
+    });
+    assert_eq!(res.succeed_data.deal_price, 12);
+
+    let res = service.compute_deal_price(context, ComputeDealPricePayload {
+        buy_tx_hash: Hash::digest(Bytes::from_static(b"low_buy")),
+        sell_tx_hash,
+    });
+    assert_eq!(res.code, 217);
+}
+
+#[test]
+fn test_is_crossed() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &user, "BASE");
+    let counter = create_asset(&mut service, &user, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let is_crossed = |service: &TestDexService| {
+        service
+            .is_crossed(context.clone(), IsCrossedPayload {
+                trade_id: trade.id.clone(),
+            })
+            .succeed_data
+            .crossed
+    };
+    assert!(!is_crossed(&service));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &user, b"sell", 1, sell).is_error());
+    let low_buy = order_payload(&trade, OrderKind::Buy, 9, 10);
+    assert!(!place_order(&mut service, &user, b"low_buy", 1, low_buy).is_error());
+    assert!(!is_crossed(&service));
+
+    let high_buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &user, b"high_buy", 1, high_buy).is_error());
+    assert!(is_crossed(&service));
+}
+
+#[test]
+fn test_detect_crossed_books() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        circuit_breaker: 1000,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let other = create_asset(&mut service, &seller, "OTHER");
+    let halted = add_trade(&mut service, trade_payload(&base, &counter));
+    let healthy = add_trade(&mut service, trade_payload(&base, &other));
+    service.last_prices.insert(halted.id.clone(), 100);
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let crossed = |service: &TestDexService| {
+        service
+            .detect_crossed_books(context.clone())
+            .succeed_data
+            .books
+    };
+
+    // the breaker keeps the first book from matching, the second clears
+    let sell = order_payload(&halted, OrderKind::Sell, 150, 10);
+    assert!(!place_order(&mut service, &seller, b"halted_sell", 1, sell).is_error());
+    let buy = order_payload(&halted, OrderKind::Buy, 160, 10);
+    assert!(!place_order(&mut service, &buyer, b"halted_buy", 1, buy).is_error());
+    let sell = order_payload(&healthy, OrderKind::Sell, 150, 10);
+    assert!(!place_order(&mut service, &seller, b"healthy_sell", 1, sell).is_error());
+    let buy = order_payload(&healthy, OrderKind::Buy, 160, 10);
+    assert!(!place_order(&mut service, &buyer, b"healthy_buy", 1, buy).is_error());
+    assert_eq!(crossed(&service).len(), 2);
+
+    service.match_trade(&halted, 1);
+    service.match_trade(&healthy, 1);
+    assert_eq!(crossed(&service), vec![CrossedBook {
+        trade_id: halted.id.clone(),
+        best_bid: 160,
+        best_ask: 150,
+    }]);
+}
+
+#[test]
+fn test_is_price_improving() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &user, "BASE");
+    let counter = create_asset(&mut service, &user, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let improving = |service: &TestDexService, kind: OrderKind, price: u64| {
+        let payload = order_payload(&trade, kind, price, 10);
+        service
+            .is_price_improving(context.clone(), payload)
+            .succeed_data
+            .improving
+    };
+
+    // any order improves an empty side
+    assert!(improving(&service, OrderKind::Buy, 5));
+    assert!(improving(&service, OrderKind::Sell, 20));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 9, 10);
+    assert!(!place_order(&mut service, &user, b"buy", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 12, 10);
+    assert!(!place_order(&mut service, &user, b"sell", 1, sell).is_error());
+
+    assert!(improving(&service, OrderKind::Buy, 10));
+    assert!(!improving(&service, OrderKind::Buy, 9));
+    assert!(!improving(&service, OrderKind::Buy, 8));
+    assert!(improving(&service, OrderKind::Sell, 11));
+    assert!(!improving(&service, OrderKind::Sell, 12));
+}
+
+#[test]
+fn test_level_counts() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &user, "BASE");
+    let counter = create_asset(&mut service, &user, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let orders: Vec<(&'static [u8], OrderKind, u64)> = vec![
+        (b"buy_1", OrderKind::Buy, 8),
+        (b"buy_2", OrderKind::Buy, 9),
+        (b"buy_3", OrderKind::Buy, 9),
+        (b"buy_4", OrderKind::Buy, 7),
+        (b"sell_1", OrderKind::Sell, 12),
+        (b"sell_2", OrderKind::Sell, 12),
+    ];
+    for (seed, kind, price) in orders.into_iter() {
+        let payload = order_payload(&trade, kind, price, 10);
+        assert!(!place_order(&mut service, &user, seed, 1, payload).is_error());
+    }
+
+    let context = mock_context(CYCLES_LIMIT, user);
+    let counts = service
+        .get_level_counts(context, GetLevelCountsPayload {
+            trade_id: trade.id.clone(),
+        })
+        .succeed_data;
+    assert_eq!(counts, GetLevelCountsResponse { bids: 3, asks: 1 });
+}
+
+#[test]
+fn test_book_version() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let version = |service: &TestDexService| {
+        service
+            .get_book_version(context.clone(), GetBookVersionPayload {
+                trade_id: trade.id.clone(),
+            })
+            .succeed_data
+            .height
+    };
+    assert_eq!(version(&service), 0);
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell", 3, sell).is_error());
+    assert_eq!(version(&service), 3);
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 4);
+    assert!(!place_order(&mut service, &buyer, b"buy", 5, buy).is_error());
+    assert_eq!(version(&service), 5);
+
+    service.match_trade(&trade, 7);
+    assert_eq!(version(&service), 7);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Sell)[0].filled, 4);
+
+    // a block that leaves the book alone leaves its version alone
+    service.match_trade(&trade, 8);
+    assert_eq!(version(&service), 7);
+}
+
+#[test]
+fn test_fill_price_for_quantity() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &user, "BASE");
+    let counter = create_asset(&mut service, &user, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 5);
+    assert!(!place_order(&mut service, &user, b"sell_1", 1, sell).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 12, 5);
+    assert!(!place_order(&mut service, &user, b"sell_2", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 8, 3);
+    assert!(!place_order(&mut service, &user, b"buy", 1, buy).is_error());
+
+    let quote = |service: &TestDexService, kind: OrderKind, quantity: u64| {
+        let context = mock_context(CYCLES_LIMIT, user.clone());
+        service
+            .get_fill_price_for_quantity(context, GetFillPricePayload {
+                trade_id: trade.id.clone(),
+                kind,
+                quantity,
+            })
+            .succeed_data
+    };
+
+    // 5 at 10 and 3 at 12
+    assert_eq!(quote(&service, OrderKind::Buy, 8), GetFillPriceResponse {
+        filled:     8,
+        avg_price:  10,
+        sufficient: true,
+    });
+    assert_eq!(quote(&service, OrderKind::Buy, 4).avg_price, 10);
+    // the asks hold 10 in all
+    assert_eq!(quote(&service, OrderKind::Buy, 20), GetFillPriceResponse {
+        filled:     10,
+        avg_price:  11,
+        sufficient: false,
+    });
+    assert_eq!(quote(&service, OrderKind::Sell, 3).avg_price, 8);
+    assert_eq!(quote(&service, OrderKind::Sell, 4).sufficient, false);
+
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let res = service.cancel_order(context, CancelOrderPayload {
+        tx_hash: Hash::digest(Bytes::from_static(b"buy")),
+    });
+    assert!(!res.is_error());
+    assert_eq!(quote(&service, OrderKind::Sell, 1), GetFillPriceResponse::default());
+}
+
+#[test]
+fn test_estimate_slippage() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, AddTradePayload {
+        price_rule: PriceRule::MakerPrice,
+        ..trade_payload(&base, &counter)
+    });
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 12, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 8, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy).is_error());
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let estimate = |service: &TestDexService, kind: OrderKind, price: u64, amount: u64| {
+        let payload = order_payload(&trade, kind, price, amount);
+        service
+            .estimate_slippage(context.clone(), payload)
+            .succeed_data
+    };
+
+    // the mid is 9, 10 at 10 and 10 at 12 average 11
+    let expected = estimate(&service, OrderKind::Buy, 12, 20);
+    assert_eq!(expected, EstimateSlippageResponse {
+        avg_price:       11,
+        reference_price: 9,
+        slippage_bps:    2222,
+        sufficient:      true,
+    });
+    assert!(!estimate(&service, OrderKind::Buy, 12, 30).sufficient);
+    // the order's own price caps how deep it walks
+    let capped = estimate(&service, OrderKind::Buy, 10, 20);
+    assert_eq!((capped.avg_price, capped.sufficient), (10, false));
+    assert_eq!(estimate(&service, OrderKind::Sell, 8, 10).slippage_bps, 1111);
+
+    let buy = order_payload(&trade, OrderKind::Buy, 12, 20);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 2, buy).is_error());
+    service.match_trade(&trade, 2);
+
+    let placed = service
+        .history_orders
+        .get(&Hash::digest(Bytes::from_static(b"buy_2")))
+        .unwrap();
+    let quote: u64 = placed.deals.iter().map(|deal| deal.amount * deal.price).sum();
+    let realized = quote / placed.filled;
+    assert_eq!(placed.filled, 20);
+    assert_eq!(realized, expected.avg_price);
+    assert_eq!(
+        (realized - expected.reference_price) * 10_000 / expected.reference_price,
+        expected.slippage_bps
+    );
+}
+
+#[test]
+fn test_estimate_match_cost() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 5);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 9, 5);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 1, buy_2).is_error());
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 8, 3);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 9, 4);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2).is_error());
+    let sell_3 = order_payload(&trade, OrderKind::Sell, 11, 10);
+    assert!(!place_order(&mut service, &seller, b"sell_3", 1, sell_3).is_error());
+
+    // buy_1 takes all of sell_1 and 2 of sell_2, buy_2 the other 2, and
+    // sell_3 never crosses
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let estimate = service
+        .estimate_match_cost(context.clone(), EstimateMatchCostPayload {
+            trade_id: trade.id.clone(),
+        })
+        .succeed_data;
+    assert_eq!(estimate.crossing_orders, 4);
+    assert_eq!(estimate.settles, 3);
+    assert!(estimate.cycles > 0);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Sell).len(), 3);
+
+    service.match_trade(&trade, 1);
+    assert_eq!(service.get_stats(context).succeed_data.total_deals, 3);
+}
+
+#[test]
+fn test_order_from_locked_funds() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &buyer, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    // drop the first order from the book without refunding it, as if it
+    // expired and its funds were left to be claimed
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let order = service.get_open_orders(&trade.id, OrderKind::Buy).remove(0);
+    service.remove_order(&order, 1);
+
+    let mut buy_2 = order_payload(&trade, OrderKind::Buy, 20, 60);
+    buy_2.use_locked = true;
+    let res = place_order(&mut service, &buyer, b"buy_2", 2, buy_2);
+    assert_eq!(res.code, 214);
+
+    let mut buy_3 = order_payload(&trade, OrderKind::Buy, 20, 50);
+    buy_3.use_locked = true;
+    assert!(!place_order(&mut service, &buyer, b"buy_3", 2, buy_3).is_error());
+
+    let balance = get_balance(&service, &buyer, &base);
+    assert_eq!(balance.current, 1_000_000 - 1000);
+    assert_eq!(balance.locked, 1000);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Buy).len(), 1);
+}
+
+#[test]
+fn test_reduce_only() {
+    let seller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 600_000);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+
+    // what the first sell locked is no longer held
+    let mut sell_2 = order_payload(&trade, OrderKind::Sell, 10, 400_010);
+    sell_2.reduce_only = true;
+    let res = place_order(&mut service, &seller, b"sell_2", 1, sell_2);
+    assert_eq!(res.code, 228);
+
+    let mut sell_3 = order_payload(&trade, OrderKind::Sell, 10, 400_000);
+    sell_3.reduce_only = true;
+    assert!(!place_order(&mut service, &seller, b"sell_3", 1, sell_3).is_error());
+    assert_eq!(get_balance(&service, &seller, &counter).current, 0);
+
+    let mut buy = order_payload(&trade, OrderKind::Buy, 5, 10);
+    buy.reduce_only = true;
+    let res = place_order(&mut service, &seller, b"buy", 1, buy);
+    assert_eq!(res.code, 228);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Sell).len(), 2);
+}
+
+#[test]
+fn test_accept_swap() {
+    let maker = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let taker = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let stranger = Address::from_hex("0x777cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let offer = create_asset(&mut service, &maker, "OFR");
+    let want = create_asset(&mut service, &taker, "WNT");
+
+    let context = mock_tx_context(maker.clone(), Hash::digest(Bytes::from_static(b"swap")), 1);
+    let res = service.create_swap(context, CreateSwapPayload {
+        counterparty: taker.clone(),
+        offer_asset:  offer.clone(),
+        offer_amount: 300,
+        want_asset:   want.clone(),
+        want_amount:  200,
+    });
+    assert!(!res.is_error());
+    let swap_id = res.succeed_data.id;
+    assert_eq!(get_balance(&service, &maker, &offer).locked, 300);
+
+    // only the named counterparty can take the swap
+    let context = mock_context(CYCLES_LIMIT, stranger);
+    let res = service.accept_swap(context, SwapPayload {
+        swap_id: swap_id.clone(),
+    });
+    assert_eq!(res.code, 209);
+
+    let context = mock_context(CYCLES_LIMIT, taker.clone());
+    let res = service.accept_swap(context, SwapPayload {
+        swap_id: swap_id.clone(),
+    });
+    assert!(!res.is_error());
+
+    let maker_offer = get_balance(&service, &maker, &offer);
+    assert_eq!(maker_offer.locked, 0);
+    assert_eq!(maker_offer.current, 1_000_000 - 300);
+    assert_eq!(get_balance(&service, &maker, &want).current, 200);
+    assert_eq!(get_balance(&service, &taker, &offer).current, 300);
+    assert_eq!(get_balance(&service, &taker, &want).current, 1_000_000 - 200);
+
+    // a swap can only be taken once
+    let context = mock_context(CYCLES_LIMIT, taker);
+    let res = service.accept_swap(context, SwapPayload { swap_id });
+    assert_eq!(res.code, 219);
+}
+
+#[test]
+fn test_cancel_swap() {
+    let maker = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let taker = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let offer = create_asset(&mut service, &maker, "OFR");
+    let want = create_asset(&mut service, &taker, "WNT");
+
+    let context = mock_tx_context(maker.clone(), Hash::digest(Bytes::from_static(b"swap")), 1);
+    let res = service.create_swap(context, CreateSwapPayload {
+        counterparty: taker.clone(),
+        offer_asset:  offer.clone(),
+        offer_amount: 300,
+        want_asset:   want,
+        want_amount:  200,
+    });
+    let swap_id = res.succeed_data.id;
+
+    let context = mock_context(CYCLES_LIMIT, taker.clone());
+    let res = service.cancel_swap(context, SwapPayload {
+        swap_id: swap_id.clone(),
+    });
+    assert_eq!(res.code, 209);
+
+    let context = mock_context(CYCLES_LIMIT, maker.clone());
+    let res = service.cancel_swap(context, SwapPayload {
+        swap_id: swap_id.clone(),
+    });
+    assert!(!res.is_error());
+
+    let balance = get_balance(&service, &maker, &offer);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(balance.current, 1_000_000);
+
+    let context = mock_context(CYCLES_LIMIT, taker);
+    let res = service.accept_swap(context, SwapPayload { swap_id });
+    assert_eq!(res.code, 219);
+}
+
+#[test]
+fn test_get_user_fills() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let other = Address::from_hex("0x777cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let other_counter = create_asset(&mut service, &seller, "OCNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+    let other_trade = add_trade(&mut service, trade_payload(&base, &other_counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 5);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 5);
+    assert!(!place_order(&mut service, &seller, b"sell", 2, sell).is_error());
+    service.match_trade(&trade, 2);
+
+    let other_sell = order_payload(&other_trade, OrderKind::Sell, 20, 3);
+    assert!(!place_order(&mut service, &seller, b"other_sell", 3, other_sell).is_error());
+    let other_buy = order_payload(&other_trade, OrderKind::Buy, 20, 3);
+    assert!(!place_order(&mut service, &buyer, b"other_buy", 4, other_buy).is_error());
+    service.match_trade(&other_trade, 4);
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let deals = service
+        .get_user_fills(context.clone(), GetUserFillsPayload {
+            user:  buyer.clone(),
+            limit: 10,
+        })
+        .succeed_data
+        .deals;
+    assert_eq!(deals.len(), 2);
+    assert_eq!(deals[0].trade_id, other_trade.id);
+    assert_eq!(deals[0].taker, buyer);
+    assert_eq!(deals[0].maker, seller);
+    assert_eq!(deals[1].trade_id, trade.id);
+    assert_eq!(deals[1].maker, buyer);
+    assert_eq!(deals[1].taker, seller);
+
+    let deals = service
+        .get_user_fills(context.clone(), GetUserFillsPayload {
+            user:  seller,
+            limit: 1,
+        })
+        .succeed_data
+        .deals;
+    assert_eq!(deals.len(), 1);
+    assert_eq!(deals[0].trade_id, other_trade.id);
+
+    let deals = service
+        .get_user_fills(context, GetUserFillsPayload {
+            user:  other,
+            limit: 10,
+        })
+        .succeed_data
+        .deals;
+    assert!(deals.is_empty());
+}
+
+#[test]
+fn test_matching_round() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let mut stale_buy = order_payload(&trade, OrderKind::Buy, 9, 10);
+    stale_buy.expiry = Some(3);
+    assert!(!place_order(&mut service, &buyer, b"stale_buy", 1, stale_buy).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 4);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell.clone()).is_error());
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell).is_error());
+    let high_sell = order_payload(&trade, OrderKind::Sell, 12, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_3", 1, high_sell).is_error());
+
+    service.matching_round(5);
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let events = |service: &TestDexService, height: u64| {
+        service
+            .get_hook_events(context.clone(), GetHookEventsPayload { height })
+            .succeed_data
+            .events
+    };
+    let round_events = events(&service, 5);
+    assert_eq!(round_events.len(), 1);
+    assert_eq!(round_events[0].name, "MatchingRound");
+
+    // the stale buy expires before matching, the high sell is left untouched
+    let round: MatchingRoundEvent = serde_json::from_str(&round_events[0].data).unwrap();
+    assert_eq!(round, MatchingRoundEvent {
+        height:      5,
+        considered:  4,
+        matched:     3,
+        expired:     1,
+        trade_fills: vec![TradeDealCount {
+            trade_id: trade.id,
+            deals:    2,
+        }],
+    });
+
+    // an idle round leaves no event behind
+    service.matching_round(6);
+    assert!(events(&service, 6).is_empty());
+
+    let admin = Address::from_hash(Hash::from_empty()).unwrap();
+    let admin_context = mock_context(CYCLES_LIMIT, admin);
+    let res = service.prune_history(admin_context, PruneHistoryPayload { before_height: 6 });
+    assert!(res.succeed_data.pruned_hook_events >= 1);
+    assert!(events(&service, 5).is_empty());
+}
+
+#[test]
+fn test_event_order() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    // the same block, on books whose trades were added in opposite orders
+    let run = |reverse: bool| {
+        let mut service = new_dex_service();
+        service.init_genesis(GenesisPayload {
+            order_book_snapshot: true,
+            ..mock_genesis()
+        });
+
+        let base = create_asset(&mut service, &buyer, "BASE");
+        let mut counters = vec![
+            create_asset(&mut service, &seller, "CNT"),
+            create_asset(&mut service, &seller, "OCNT"),
+        ];
+        if reverse {
+            counters.reverse();
+        }
+        let mut trades: Vec<Trade> = counters
+            .iter()
+            .map(|counter| add_trade(&mut service, trade_payload(&base, counter)))
+            .collect();
+        trades.sort_by_key(|trade| trade.id.as_hex());
+
+        let seeds: [[&'static [u8]; 3]; 2] = [
+            [b"stale_0", b"buy_0", b"sell_0"],
+            [b"stale_1", b"buy_1", b"sell_1"],
+        ];
+        for (trade, seeds) in trades.iter().zip(seeds.iter()) {
+            let mut stale = order_payload(trade, OrderKind::Buy, 5, 10);
+            stale.expiry = Some(3);
+            assert!(!place_order(&mut service, &buyer, seeds[0], 1, stale).is_error());
+            let buy = order_payload(trade, OrderKind::Buy, 10, 10);
+            assert!(!place_order(&mut service, &buyer, seeds[1], 1, buy).is_error());
+            let sell = order_payload(trade, OrderKind::Sell, 10, 4);
+            assert!(!place_order(&mut service, &seller, seeds[2], 1, sell).is_error());
+        }
+
+        service.matching_round(5);
+        let context = mock_context(CYCLES_LIMIT, buyer.clone());
+        let events = service
+            .get_hook_events(context.clone(), GetHookEventsPayload { height: 5 })
+            .succeed_data
+            .events;
+        let mut deals = service
+            .get_user_fills(context, GetUserFillsPayload {
+                user:  buyer.clone(),
+                limit: 10,
+            })
+            .succeed_data
+            .deals;
+        deals.reverse();
+        (trades, events, deals)
+    };
+
+    let (trades, events, deals) = run(false);
+    let (_, reversed_events, reversed_deals) = run(true);
+    assert_eq!(events, reversed_events);
+    assert_eq!(deals, reversed_deals);
+
+    // the deals of each trade by id, then the round, then a snapshot per
+    // trade by id
+    let deal_ids: Vec<String> = deals.iter().map(|deal| deal.correlation_id.clone()).collect();
+    let trade_hexes = [trades[0].id.as_hex(), trades[1].id.as_hex()];
+    let expected = vec![format!("{}/0", trade_hexes[0]), format!("{}/0", trade_hexes[1])];
+    assert_eq!(deal_ids, expected);
+
+    let names: Vec<&str> = events.iter().map(|event| event.name.as_str()).collect();
+    assert_eq!(names, vec!["MatchingRound", "OrderBookSnapshot", "OrderBookSnapshot"]);
+    let snapshot_trades: Vec<Hash> = events
+        .iter()
+        .filter(|event| event.name == "OrderBookSnapshot")
+        .map(|event| serde_json::from_str::<OrderBook>(&event.data).unwrap().trade_id)
+        .collect();
+    assert_eq!(snapshot_trades, vec![trades[0].id.clone(), trades[1].id.clone()]);
+}
+
+#[test]
+fn test_invalidate_orders() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 12, 5);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 100);
+
+    // the asset service has no removal, so point the trade at an unknown asset
+    let broken = Trade {
+        counter_party: Hash::digest(Bytes::from_static(b"missing")),
+        ..trade.clone()
+    };
+    service.trades.insert(trade.id.clone(), broken);
+
+    service.matching_round(2);
+
+    assert!(service.get_open_orders(&trade.id, OrderKind::Buy).is_empty());
+    assert!(service.get_open_orders(&trade.id, OrderKind::Sell).is_empty());
+    let balance = get_balance(&service, &buyer, &base);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(balance.current, 1_000_000);
+
+    let buy_hash = Hash::digest(Bytes::from_static(b"buy"));
+    let sell_hash = Hash::digest(Bytes::from_static(b"sell"));
+    assert!(service.history_orders.contains(&buy_hash));
+    assert!(service.history_orders.contains(&sell_hash));
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let events = service
+        .get_hook_events(context, GetHookEventsPayload { height: 2 })
+        .succeed_data
+        .events;
+    let event = events
+        .iter()
+        .find(|event| event.name == "InvalidateOrders")
+        .unwrap();
+    let event: InvalidateOrdersEvent = serde_json::from_str(&event.data).unwrap();
+    assert_eq!(event.trade_id, trade.id);
+    assert_eq!(event.orders.len(), 2);
+    assert!(event.orders.contains(&buy_hash));
+}
+
+#[test]
+fn test_settle_failures() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+
+    // release what the sell locked behind the dex's back, so it can't settle
+    let lock_context = mock_admitted_context(b"dex_lock_token");
+    let res = service.asset.unlock(lock_context, ModifyBalancePayload {
+        asset_id:       counter.clone(),
+        user:           seller.clone(),
+        value:          10,
+        correlation_id: String::new(),
+    });
+    assert!(!res.is_error());
+
+    let sell_hash = Hash::digest(Bytes::from_static(b"sell"));
+    service.matching_round(2);
+    service.matching_round(3);
+    assert_eq!(service.get_open_order(&sell_hash).succeed_data.filled, 0);
+
+    service.matching_round(4);
+    assert!(service.get_open_orders(&trade.id, OrderKind::Sell).is_empty());
+    assert!(service.history_orders.contains(&sell_hash));
+    assert_eq!(get_balance(&service, &seller, &counter).current, 1_000_000);
+
+    // the buy wasn't to blame and keeps its place and its lock
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Buy).len(), 1);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 100);
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let events = service
+        .get_hook_events(context, GetHookEventsPayload { height: 4 })
+        .succeed_data
+        .events;
+    let event = events
+        .iter()
+        .find(|event| event.name == "InvalidateOrders")
+        .unwrap();
+    let event: InvalidateOrdersEvent = serde_json::from_str(&event.data).unwrap();
+    assert_eq!(event.reason, "SettlementFailed");
+    assert_eq!(event.orders, vec![sell_hash]);
+}
+
+#[test]
+fn test_disable_trade_cancels_orders() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 8, 20);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 1, buy_2).is_error());
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 4);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 2, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 15, 30);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 2, sell_2).is_error());
+    service.match_trade(&trade, 2);
+
+    let context = mock_context(CYCLES_LIMIT, admin);
+    let res = service.set_trade_enabled(context.clone(), SetTradeEnabledPayload {
+        trade_id:      trade.id.clone(),
+        enabled:       false,
+        cancel_orders: true,
+    });
+    assert!(!res.is_error());
+
+    assert!(service.get_open_orders(&trade.id, OrderKind::Buy).is_empty());
+    assert!(service.get_open_orders(&trade.id, OrderKind::Sell).is_empty());
+    // one cancel event per order, then the trade update
+    assert_eq!(context.get_events().len(), 4);
+
+    let buyer_base = get_balance(&service, &buyer, &base);
+    assert_eq!(buyer_base.locked, 0);
+    assert_eq!(buyer_base.current, 1_000_000 - 40);
+    let seller_counter = get_balance(&service, &seller, &counter);
+    assert_eq!(seller_counter.locked, 0);
+    assert_eq!(seller_counter.current, 1_000_000 - 4);
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    let res = place_order(&mut service, &buyer, b"buy_3", 3, buy);
+    assert_eq!(res.code, 216);
+}
+
+#[test]
+fn test_reference_price() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let payload = GetReferencePricePayload {
+        trade_id: trade.id.clone(),
+    };
+    let reference = service
+        .get_reference_price(context.clone(), payload.clone())
+        .succeed_data;
+    assert_eq!(reference, GetReferencePriceResponse {
+        price:  0,
+        source: PriceSource::Unavailable,
+    });
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 5);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 15, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell).is_error());
+    let reference = service
+        .get_reference_price(context.clone(), payload.clone())
+        .succeed_data;
+    assert_eq!(reference, GetReferencePriceResponse {
+        price:  12,
+        source: PriceSource::BookMid,
+    });
+
+    // once a deal empties the ask side the last deal price takes over
+    let buy = order_payload(&trade, OrderKind::Buy, 15, 5);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 2, buy).is_error());
+    service.match_trade(&trade, 2);
+    let reference = service.get_reference_price(context, payload).succeed_data;
+    assert_eq!(reference, GetReferencePriceResponse {
+        price:  15,
+        source: PriceSource::LastTrade,
+    });
+}
+
+#[test]
+fn test_batch_order() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let tx_hash = Hash::digest(Bytes::from_static(b"batch"));
+    let context = mock_tx_context(buyer.clone(), tx_hash.clone(), 1);
+    let res = service.batch_order(context, BatchOrderPayload {
+        orders: vec![
+            order_payload(&trade, OrderKind::Buy, 10, 100),
+            order_payload(&trade, OrderKind::Buy, 9, 100),
+            order_payload(&trade, OrderKind::Buy, 8, 100),
+        ],
+    });
+    assert!(!res.is_error());
+    assert_eq!(res.succeed_data.tx_hashes, vec![
+        derive_order_hash(&tx_hash, 0),
+        derive_order_hash(&tx_hash, 1),
+        derive_order_hash(&tx_hash, 2),
+    ]);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Buy).len(), 3);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 2700);
+
+    // every order of the transaction is looked up by its own derived key
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    for (index, price) in [10, 9, 8].iter().enumerate() {
+        let order = service
+            .get_order(context.clone(), GetOrderPayload {
+                tx_hash:    derive_order_hash(&tx_hash, index),
+                with_deals: false,
+            })
+            .succeed_data;
+        assert_eq!(order.price, *price);
+        assert_eq!(order.tx_hash, derive_order_hash(&tx_hash, index));
+    }
+    let res = service.get_order(context, GetOrderPayload {
+        tx_hash,
+        with_deals: false,
+    });
+    assert_eq!(res.code, 206);
+}
+
+#[test]
+fn test_batch_order_rollback() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    // the last order needs more base than the first two leave unlocked
+    let tx_hash = Hash::digest(Bytes::from_static(b"batch"));
+    let context = mock_tx_context(buyer.clone(), tx_hash, 1);
+    let res = service.batch_order(context, BatchOrderPayload {
+        orders: vec![
+            order_payload(&trade, OrderKind::Buy, 10, 100),
+            order_payload(&trade, OrderKind::Buy, 11, 100),
+            order_payload(&trade, OrderKind::Buy, 10, 99_900),
+        ],
+    });
+    assert_eq!(res.code, 214);
+
+    assert!(service.get_open_orders(&trade.id, OrderKind::Buy).is_empty());
+    let balance = get_balance(&service, &buyer, &base);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(balance.current, 1_000_000);
+}
+
+#[test]
+fn test_priority_queue() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 11, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 2, sell.clone()).is_error());
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_3", 3, sell).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 12, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_4", 1, sell).is_error());
+
+    let buy = order_payload(&trade, OrderKind::Buy, 9, 5);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 2);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 4, buy).is_error());
+    service.match_trade(&trade, 4);
+
+    // the cheapest ask goes first, and the earlier of two equal asks
+    let context = mock_context(CYCLES_LIMIT, seller);
+    let sells = service
+        .get_priority_queue(context.clone(), GetPriorityQueuePayload {
+            trade_id: trade.id.clone(),
+            kind:     OrderKind::Sell,
+        })
+        .succeed_data
+        .orders;
+    let hashes: Vec<Hash> = sells.iter().map(|order| order.tx_hash.clone()).collect();
+    assert_eq!(hashes, vec![
+        Hash::digest(Bytes::from_static(b"sell_3")),
+        Hash::digest(Bytes::from_static(b"sell_2")),
+        Hash::digest(Bytes::from_static(b"sell_1")),
+        Hash::digest(Bytes::from_static(b"sell_4")),
+    ]);
+    assert_eq!(sells[0].remaining, 3);
+    assert_eq!(sells[0].amount, 5);
+
+    let buys = service
+        .get_priority_queue(context, GetPriorityQueuePayload {
+            trade_id: trade.id.clone(),
+            kind:     OrderKind::Buy,
+        })
+        .succeed_data
+        .orders;
+    assert_eq!(buys.len(), 1);
+    assert_eq!(buys[0].tx_hash, Hash::digest(Bytes::from_static(b"buy_1")));
+}
+
+#[test]
+fn test_duplicate_order() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy.clone()).is_error());
+    let res = place_order(&mut service, &buyer, b"buy", 1, buy.clone());
+    assert_eq!(res.code, 223);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 100);
+
+    // a filled order in history still holds its hash
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell", 2, sell).is_error());
+    service.match_trade(&trade, 2);
+    let res = place_order(&mut service, &buyer, b"buy", 3, buy);
+    assert_eq!(res.code, 223);
+}
+
+#[test]
+fn test_force_match() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+
+    let res = service.force_match(mock_context(CYCLES_LIMIT, buyer.clone()));
+    assert!(res.is_error());
+    // two open orders cost more than the limit allows
+    let res = service.force_match(mock_context(40_000, admin.clone()));
+    assert!(res.is_error());
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Buy).len(), 1);
+
+    let res = service.force_match(mock_context(CYCLES_LIMIT, admin));
+    assert!(!res.is_error());
+
+    assert!(service.get_open_orders(&trade.id, OrderKind::Buy).is_empty());
+    assert!(service.get_open_orders(&trade.id, OrderKind::Sell).is_empty());
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 10);
+    assert_eq!(get_balance(&service, &seller, &base).current, 100);
+}
+
+#[test]
+fn test_net_settlement() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let treasury = Address::from_hex("0x888cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let run = |net_settlement: bool| {
+        let mut service = new_dex_service();
+        service.init_genesis(GenesisPayload {
+            fee_rate: 100,
+            maker_rebate: 40,
+            fee_account: treasury.clone(),
+            net_settlement,
+            ..mock_genesis()
+        });
+
+        let base = create_asset(&mut service, &buyer, "BASE");
+        let counter = create_asset(&mut service, &seller, "CNT");
+        let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+        // both users trade on both sides, so their legs partly offset
+        let res = service.add_value(ModifyAssetPayload {
+            asset_id: counter.clone(),
+            user:     buyer.clone(),
+            value:    1000,
+        });
+        assert!(!res.is_error());
+        let res = service.add_value(ModifyAssetPayload {
+            asset_id: base.clone(),
+            user:     seller.clone(),
+            value:    10_000,
+        });
+        assert!(!res.is_error());
+        let buy = order_payload(&trade, OrderKind::Buy, 12, 100);
+        assert!(!place_order(&mut service, &buyer, b"buyer_buy", 1, buy).is_error());
+        let sell = order_payload(&trade, OrderKind::Sell, 9, 100);
+        assert!(!place_order(&mut service, &seller, b"seller_sell", 1, sell).is_error());
+        let buy = order_payload(&trade, OrderKind::Buy, 10, 100);
+        assert!(!place_order(&mut service, &seller, b"seller_buy", 2, buy).is_error());
+        let sell = order_payload(&trade, OrderKind::Sell, 10, 100);
+        assert!(!place_order(&mut service, &buyer, b"buyer_sell", 2, sell).is_error());
+
+        service.matching_round(2);
+        let context = mock_context(CYCLES_LIMIT, buyer.clone());
+        assert_eq!(service.get_stats(context.clone()).succeed_data.total_deals, 2);
+
+        // netted or not, every deal keeps its correlation id and no balance
+        // change is copied into the hook event log
+        let deal_ids: Vec<String> = service
+            .get_user_fills(context.clone(), GetUserFillsPayload {
+                user:  buyer.clone(),
+                limit: 10,
+            })
+            .succeed_data
+            .deals
+            .into_iter()
+            .map(|deal| deal.correlation_id)
+            .collect();
+        let trade_hex = trade.id.as_hex();
+        assert_eq!(deal_ids, vec![format!("{}/1", trade_hex), format!("{}/0", trade_hex)]);
+        let events = service
+            .get_hook_events(context, GetHookEventsPayload { height: 2 })
+            .succeed_data
+            .events;
+        assert!(events.iter().all(|event| event.name == "MatchingRound"));
+
+        let mut balances = Vec::new();
+        for user in [&buyer, &seller, &treasury].iter() {
+            balances.push(get_balance(&service, user, &base));
+            balances.push(get_balance(&service, user, &counter));
+        }
+        balances
+    };
+
+    assert_eq!(run(true), run(false));
+}
+
+#[test]
+fn test_net_settlement_failures() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    // a deal that can't settle fails alike whether or not the round is netted
+    let run = |net_settlement: bool| {
+        let mut service = new_dex_service();
+        service.init_genesis(GenesisPayload {
+            net_settlement,
+            ..mock_genesis()
+        });
+
+        let base = create_asset(&mut service, &buyer, "BASE");
+        let counter = create_asset(&mut service, &seller, "CNT");
+        let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+        let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+        assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+        let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+        assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+
+        // release what the sell locked behind the dex's back, so it can't settle
+        let lock_context = mock_admitted_context(b"dex_lock_token");
+        let res = service.asset.unlock(lock_context, ModifyBalancePayload {
+            asset_id:       counter.clone(),
+            user:           seller.clone(),
+            value:          10,
+            correlation_id: String::new(),
+        });
+        assert!(!res.is_error());
+
+        let sell_hash = Hash::digest(Bytes::from_static(b"sell"));
+        service.matching_round(2);
+        assert_eq!(service.get_open_order(&sell_hash).succeed_data.filled, 0);
+        let context = mock_context(CYCLES_LIMIT, buyer.clone());
+        assert_eq!(service.get_stats(context).succeed_data.total_deals, 0);
+
+        service.matching_round(3);
+        service.matching_round(4);
+        assert!(service.get_open_orders(&trade.id, OrderKind::Sell).is_empty());
+
+        let mut balances = Vec::new();
+        for user in [&buyer, &seller].iter() {
+            balances.push(get_balance(&service, user, &base));
+            balances.push(get_balance(&service, user, &counter));
+        }
+        balances
+    };
+
+    let balances = run(true);
+    assert_eq!(balances, run(false));
+    assert_eq!(balances[0].locked, 100);
+    assert_eq!(balances[3].current, 1_000_000);
+}
+
+#[test]
+fn test_net_settlement_overflow() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    // a credit the buyer's balance can't hold fails the deal in either mode,
+    // rather than failing the netted deltas at the end of the round
+    let run = |net_settlement: bool| {
+        let mut service = new_dex_service();
+        service.init_genesis(GenesisPayload {
+            net_settlement,
+            ..mock_genesis()
+        });
+
+        let base = create_asset(&mut service, &buyer, "BASE");
+        let counter = create_asset(&mut service, &seller, "CNT");
+        let trade = add_trade(&mut service, trade_payload(&base, &counter));
+        let res = service.add_value(ModifyAssetPayload {
+            asset_id: counter.clone(),
+            user:     buyer.clone(),
+            value:    std::u64::MAX - 5,
+        });
+        assert!(!res.is_error());
+
+        let buy = order_payload(&trade, OrderKind::Buy, 10, 10);
+        assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+        let sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+        assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+
+        service.matching_round(2);
+        let sell_hash = Hash::digest(Bytes::from_static(b"sell"));
+        assert_eq!(service.get_open_order(&sell_hash).succeed_data.filled, 0);
+        let context = mock_context(CYCLES_LIMIT, buyer.clone());
+        assert_eq!(service.get_stats(context.clone()).succeed_data.total_deals, 0);
+        let events = service
+            .get_hook_events(context, GetHookEventsPayload { height: 2 })
+            .succeed_data
+            .events;
+        assert!(events.iter().all(|event| event.name != "NetDeltaFailed"));
+
+        let mut balances = Vec::new();
+        for user in [&buyer, &seller].iter() {
+            balances.push(get_balance(&service, user, &base));
+            balances.push(get_balance(&service, user, &counter));
+        }
+        balances
+    };
+
+    let balances = run(true);
+    assert_eq!(balances, run(false));
+    assert_eq!(balances[1].current, std::u64::MAX - 5);
+    assert_eq!(balances[3].locked, 10);
+}
+
+#[test]
+fn test_asset_group() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let issuer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let native = create_asset(&mut service, &issuer, "BTC");
+    let wrapped = create_asset(&mut service, &issuer, "WBTC");
+    let other = create_asset(&mut service, &issuer, "CNT");
+
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let res = service.set_asset_group(context, SetAssetGroupPayload {
+        asset_id: native.clone(),
+        group:    "BTC".to_owned(),
+    });
+    assert_eq!(res.code, 209);
+
+    let context = mock_context(CYCLES_LIMIT, admin);
+    for asset_id in [&native, &wrapped].iter() {
+        let res = service.set_asset_group(context.clone(), SetAssetGroupPayload {
+            asset_id: (*asset_id).clone(),
+            group:    "BTC".to_owned(),
+        });
+        assert!(!res.is_error());
+    }
+
+    let res = service.add_trade(context.clone(), trade_payload(&wrapped, &native));
+    assert_eq!(res.code, 202);
+    let res = service.add_trade(context.clone(), trade_payload(&native, &other));
+    assert!(!res.is_error());
+
+    let res = service.set_asset_group(context.clone(), SetAssetGroupPayload {
+        asset_id: wrapped.clone(),
+        group:    "".to_owned(),
+    });
+    assert!(!res.is_error());
+    let res = service.add_trade(context, trade_payload(&wrapped, &native));
+    assert!(!res.is_error());
+}
+
+#[test]
+fn test_balance_change_correlation() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 100, 10);
+    assert!(!place_order(&mut service, &seller, b"sell1", 1, sell).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 100, 10);
+    assert!(!place_order(&mut service, &seller, b"sell2", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 100, 20);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+
+    service.match_trade(&trade, 2);
+
+    // each deal record keeps the id its balance changes were tagged with
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let deals = service
+        .get_deals_by_range(context, GetDealsByRangePayload {
+            trade_id:    trade.id.clone(),
+            from_height: 2,
+            to_height:   2,
+            limit:       10,
+        })
+        .succeed_data
+        .deals;
+    let deal_ids: Vec<String> = deals.iter().map(|deal| deal.correlation_id.clone()).collect();
+    let trade_hex = trade.id.as_hex();
+    assert_eq!(deal_ids, vec![format!("{}/1", trade_hex), format!("{}/0", trade_hex)]);
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        circuit_breaker: 1000,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+    service.last_prices.insert(trade.id.clone(), 100);
+
+    // 150 is 50% away from the last price, far beyond the 10% limit
+    let sell = order_payload(&trade, OrderKind::Sell, 150, 10);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 150, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+
+    service.match_trade(&trade, 1);
+
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Buy).len(), 1);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Sell).len(), 1);
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 0);
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let events = service
+        .get_hook_events(context, GetHookEventsPayload { height: 1 })
+        .succeed_data
+        .events;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].name, "CircuitBreaker");
+}
+
+#[test]
+fn test_circuit_breaker_market_order() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        circuit_breaker: 1000,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+    service.last_prices.insert(trade.id.clone(), 100);
+
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 105, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 150, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2).is_error());
+
+    // the sweep stops at the level 50% away from the last price
+    let context = mock_tx_context(buyer.clone(), Hash::digest(Bytes::from_static(b"market")), 2);
+    let res = service.market_order(context, MarketOrderPayload {
+        trade_id:      trade.id.clone(),
+        kind:          OrderKind::Buy,
+        amount:        10,
+        max_avg_price: None,
+        min_avg_price: None,
+        quote_amount:  None,
+    });
+    assert!(!res.is_error());
+    assert_eq!(res.succeed_data.filled, 5);
+
+    let sells = service.get_open_orders(&trade.id, OrderKind::Sell);
+    assert_eq!(sells.len(), 1);
+    assert_eq!(sells[0].price, 150);
+    assert_eq!(service.last_prices.get(&trade.id), Some(105));
+    assert_eq!(get_balance(&service, &buyer, &base).current, 1_000_000 - 525);
+}
+
+#[test]
+fn test_min_notional() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, AddTradePayload {
+        min_notional: 1000,
+        ..trade_payload(&base, &counter)
+    });
+
+    let below = order_payload(&trade, OrderKind::Buy, 10, 99);
+    let res = place_order(&mut service, &buyer, b"below", 1, below);
+    assert_eq!(res.code, 208);
+
+    let above = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"above", 1, above).is_error());
+}
+
+#[test]
+fn test_locked_breakdown() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 20, 50);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 1, buy_2).is_error());
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let breakdown = service
+        .get_locked_breakdown(context, GetLockedBreakdownPayload {
+            user:     buyer.clone(),
+            asset_id: base.clone(),
+        })
+        .succeed_data;
+
+    assert_eq!(breakdown.locks.len(), 2);
+    assert_eq!(breakdown.total, 2000);
+    assert_eq!(breakdown.total, get_balance(&service, &buyer, &base).locked);
+}
+
+#[test]
+fn test_audit_locks() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 20, 50);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 1, buy_2).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 15, 60);
+    assert!(!place_order(&mut service, &seller, b"sell", 2, sell).is_error());
+    service.match_trade(&trade, 2);
+
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let audit = service
+        .audit_locks(context.clone(), AuditLocksPayload {
+            asset_id: base.clone(),
+        })
+        .succeed_data;
+    assert_eq!(audit, AuditLocksResponse {
+        asset_id: base.clone(),
+        expected: 1000,
+        locked:   1000,
+        balanced: true,
+    });
+    let audit = service
+        .audit_locks(context.clone(), AuditLocksPayload {
+            asset_id: counter.clone(),
+        })
+        .succeed_data;
+    assert_eq!(audit.expected, 10);
+    assert!(audit.balanced);
+
+    // an order dropped from the book without a refund leaves its lock behind
+    let order = service.get_open_orders(&trade.id, OrderKind::Buy).remove(0);
+    service.remove_order(&order, 1);
+    let audit = service
+        .audit_locks(context, AuditLocksPayload { asset_id: base })
+        .succeed_data;
+    assert_eq!(audit.expected, 0);
+    assert_eq!(audit.locked, 1000);
+    assert!(!audit.balanced);
+}
+
 #[test]
-fn test_json() {
-    let o = OrderPayload{
-        kind: OrderKind::Sell,
-        price: 2,
-        amount: 100,
-        expiry: 99999,
+fn test_tvl() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter_1 = create_asset(&mut service, &seller, "CNT1");
+    let counter_2 = create_asset(&mut service, &seller, "CNT2");
+    let trade_1 = add_trade(&mut service, trade_payload(&base, &counter_1));
+    let trade_2 = add_trade(&mut service, trade_payload(&base, &counter_2));
+
+    let buy_1 = order_payload(&trade_1, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 1, buy_1).is_error());
+    let buy_2 = order_payload(&trade_2, OrderKind::Buy, 20, 5);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 1, buy_2).is_error());
+    let sell_1 = order_payload(&trade_1, OrderKind::Sell, 50, 30);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade_2, OrderKind::Sell, 30, 7);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2).is_error());
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let tvl = service.get_tvl(context).succeed_data;
+    let locked = |asset_id: &Hash| {
+        tvl.assets
+            .iter()
+            .find(|tvl| &tvl.asset_id == asset_id)
+            .map(|tvl| tvl.locked)
     };
-    println!("buy, {:?}", serde_json::to_string(&o).unwrap());
+    assert_eq!(tvl.assets.len(), 3);
+    assert_eq!(locked(&base), Some(200));
+    assert_eq!(locked(&counter_1), Some(30));
+    assert_eq!(locked(&counter_2), Some(7));
+}
 
-    println!("hash, {:?}", &Hash::from_empty());
+#[test]
+fn test_max_notional() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        max_notional: 1000,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let at_cap = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"at_cap", 1, at_cap).is_error());
+
+    let above_cap = order_payload(&trade, OrderKind::Sell, 10, 101);
+    let res = place_order(&mut service, &seller, b"above_cap", 1, above_cap);
+    assert_eq!(res.code, 221);
+
+    // the cap is in base asset units, after the price scale
+    let scaled_base = create_asset(&mut service, &buyer, "SBASE");
+    let scaled_counter = create_asset(&mut service, &seller, "SCNT");
+    let scaled = add_trade(&mut service, AddTradePayload {
+        price_scale: 1_000_000,
+        ..trade_payload(&scaled_base, &scaled_counter)
+    });
+    let at_cap = order_payload(&scaled, OrderKind::Sell, 500_000, 2000);
+    assert!(!place_order(&mut service, &seller, b"scaled_at_cap", 1, at_cap).is_error());
+    let above_cap = order_payload(&scaled, OrderKind::Sell, 500_000, 2002);
+    let res = place_order(&mut service, &seller, b"scaled_above_cap", 1, above_cap);
+    assert_eq!(res.code, 221);
 }
 
 #[test]
-fn test_add_trade() {
-    let cycles_limit = 1024 * 1024 * 1024; // 1073741824
-    let caller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
-    let context = mock_context(cycles_limit, caller);
+fn test_good_till_cancel() {
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let mut gtd = order_payload(&trade, OrderKind::Sell, 10, 100);
+    gtd.expiry = Some(10);
+    assert!(!place_order(&mut service, &seller, b"gtd", 1, gtd).is_error());
+
+    // a gtc order ignores both its expiry and the validity window
+    let mut gtc = order_payload(&trade, OrderKind::Sell, 10, 100);
+    gtc.expiry = Some(0);
+    gtc.time_in_force = TimeInForce::GoodTillCancel;
+    assert!(!place_order(&mut service, &seller, b"gtc", 1, gtc).is_error());
+
+    service.remove_expiry_orders(11);
+    let sells = service.get_open_orders(&trade.id, OrderKind::Sell);
+    assert_eq!(sells.len(), 1);
+    assert!(sells[0].tx_hash == Hash::digest(Bytes::from_static(b"gtc")));
+
+    service.remove_expiry_orders(1_000_000);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Sell).len(), 1);
+}
+
+#[test]
+fn test_expire_partial_orders() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let mut buy = order_payload(&trade, OrderKind::Buy, 10, 100);
+    buy.expiry = Some(5);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 30);
+    assert!(!place_order(&mut service, &seller, b"sell", 2, sell).is_error());
+    service.match_trade(&trade, 2);
+
+    let mut other_sell = order_payload(&trade, OrderKind::Sell, 12, 50);
+    other_sell.expiry = Some(5);
+    assert!(!place_order(&mut service, &seller, b"other_sell", 3, other_sell).is_error());
+    let other_buy = order_payload(&trade, OrderKind::Buy, 12, 20);
+    assert!(!place_order(&mut service, &buyer, b"other_buy", 4, other_buy).is_error());
+    service.match_trade(&trade, 4);
+
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 700);
+    assert_eq!(get_balance(&service, &seller, &counter).locked, 30);
+
+    // the buy refunds the notional of its 70 unfilled, the sell its 30
+    service.remove_expiry_orders(6);
+    let buyer_base = get_balance(&service, &buyer, &base);
+    assert_eq!(buyer_base.locked, 0);
+    assert_eq!(buyer_base.current, 1_000_000 - 300 - 240);
+    let seller_counter = get_balance(&service, &seller, &counter);
+    assert_eq!(seller_counter.locked, 0);
+    assert_eq!(seller_counter.current, 1_000_000 - 30 - 20);
+}
+
+#[test]
+fn test_expiry_grace() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        expiry_grace: 2,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let mut buy = order_payload(&trade, OrderKind::Buy, 10, 100);
+    buy.expiry = Some(5);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+
+    // two blocks past its expiry the buy is still within grace and matches
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 30);
+    assert!(!place_order(&mut service, &seller, b"sell", 7, sell).is_error());
+    service.matching_round(7);
+
+    let buy_hash = Hash::digest(Bytes::from_static(b"buy"));
+    assert_eq!(service.get_open_order(&buy_hash).succeed_data.filled, 30);
+    assert_eq!(get_balance(&service, &seller, &base).current, 300);
+
+    service.matching_round(8);
+    assert!(service.get_open_orders(&trade.id, OrderKind::Buy).is_empty());
+    assert!(service.history_orders.contains(&buy_hash));
+    let balance = get_balance(&service, &buyer, &base);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(balance.current, 1_000_000 - 300);
+}
+
+#[test]
+fn test_expiry_refunds() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buys: [(&'static [u8], u64, u64); 3] =
+        [(b"buy_1", 10, 10), (b"buy_2", 9, 20), (b"buy_3", 8, 30)];
+    for (seed, price, amount) in buys.iter() {
+        let mut buy = order_payload(&trade, OrderKind::Buy, *price, *amount);
+        buy.expiry = Some(5);
+        assert!(!place_order(&mut service, &buyer, *seed, 1, buy).is_error());
+    }
+    let mut sell = order_payload(&trade, OrderKind::Sell, 20, 5);
+    sell.expiry = Some(5);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+
+    let mut orders = service.get_open_orders(&trade.id, OrderKind::Buy);
+    orders.extend(service.get_open_orders(&trade.id, OrderKind::Sell));
+    let refunds = service.expiry_refunds(&orders);
+    assert_eq!(refunds.len(), 2);
+    let buyer_refund = refunds.iter().find(|refund| refund.user == buyer).unwrap();
+    assert!(buyer_refund.asset_id == base);
+    assert_eq!(buyer_refund.value, 100 + 180 + 240);
+    let seller_refund = refunds.iter().find(|refund| refund.user == seller).unwrap();
+    assert!(seller_refund.asset_id == counter);
+    assert_eq!(seller_refund.value, 5);
+
+    assert_eq!(service.remove_expiry_orders(6), 4);
+    let buyer_base = get_balance(&service, &buyer, &base);
+    assert_eq!(buyer_base.locked, 0);
+    assert_eq!(buyer_base.current, 1_000_000);
+    let seller_counter = get_balance(&service, &seller, &counter);
+    assert_eq!(seller_counter.locked, 0);
+    assert_eq!(seller_counter.current, 1_000_000);
+}
+
+#[test]
+fn test_trade_validity() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let other_counter = create_asset(&mut service, &seller, "OCNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+    let long_trade = add_trade(&mut service, trade_payload(&base, &other_counter));
+
+    let context = mock_context(CYCLES_LIMIT, admin);
+    let res = service.update_trade_params(context, UpdateTradeParamsPayload {
+        trade_id:     long_trade.id.clone(),
+        tick_size:    1,
+        lot_size:     1,
+        min_notional: 0,
+        allow_buy:    true,
+        allow_sell:   true,
+        validity:     500,
+    });
+    assert!(!res.is_error());
+
+    // the genesis validity of 100 still holds for the other trade
+    let mut sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    sell.expiry = Some(102);
+    let res = place_order(&mut service, &seller, b"sell_1", 1, sell.clone());
+    assert_eq!(res.code, 205);
+    sell.trade_id = long_trade.id.clone();
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell.clone()).is_error());
+    sell.expiry = Some(502);
+    let res = place_order(&mut service, &seller, b"sell_3", 1, sell);
+    assert_eq!(res.code, 205);
+}
+
+#[test]
+fn test_default_expiry() {
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let other = create_asset(&mut service, &seller, "OTHER");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+    let mut short_trade = add_trade(&mut service, trade_payload(&base, &other));
+    short_trade.validity = 20;
+    service.trades.insert(short_trade.id.clone(), short_trade.clone());
+    let expiry = |service: &TestDexService, seed: &'static [u8]| {
+        let order = service.get_open_order(&Hash::digest(Bytes::from_static(seed)));
+        order.succeed_data.expiry
+    };
+
+    // left out, the expiry is the end of the trade's validity window
+    let mut sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    sell.expiry = None;
+    assert!(!place_order(&mut service, &seller, b"sell_1", 7, sell.clone()).is_error());
+    assert_eq!(expiry(&service, b"sell_1"), 107);
+    sell.trade_id = short_trade.id.clone();
+    assert!(!place_order(&mut service, &seller, b"sell_2", 7, sell).is_error());
+    assert_eq!(expiry(&service, b"sell_2"), 27);
+
+    // an explicit one is kept, and still held to the window
+    let mut sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    sell.expiry = Some(50);
+    assert!(!place_order(&mut service, &seller, b"sell_3", 7, sell.clone()).is_error());
+    assert_eq!(expiry(&service, b"sell_3"), 50);
+    sell.expiry = Some(108);
+    let res = place_order(&mut service, &seller, b"sell_4", 7, sell);
+    assert_eq!(res.code, 205);
+}
+
+#[test]
+fn test_max_expiry() {
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        order_validity: 1_000,
+        max_expiry: 50,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    // well within the validity window, but past the ceiling
+    let mut sell = order_payload(&trade, OrderKind::Sell, 10, 10);
+    sell.expiry = Some(51);
+    let res = place_order(&mut service, &seller, b"sell_1", 1, sell.clone());
+    assert_eq!(res.code, 205);
+    sell.expiry = Some(50);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell.clone()).is_error());
+
+    // the ceiling doesn't move with the height
+    sell.expiry = Some(60);
+    let res = place_order(&mut service, &seller, b"sell_3", 40, sell.clone());
+    assert_eq!(res.code, 205);
+    // and cuts the window an order without an expiry gets short
+    sell.expiry = None;
+    assert!(!place_order(&mut service, &seller, b"sell_5", 40, sell.clone()).is_error());
+    let order = service.get_open_order(&Hash::digest(Bytes::from_static(b"sell_5")));
+    assert_eq!(order.succeed_data.expiry, 50);
+
+    // past the ceiling an order would be expired before it's placed
+    let res = place_order(&mut service, &seller, b"sell_6", 50, sell.clone());
+    assert_eq!(res.code, 205);
+    sell.expiry = Some(40);
+    let res = place_order(&mut service, &seller, b"sell_7", 40, sell.clone());
+    assert_eq!(res.code, 205);
+
+    // a good-till-cancel order would outlive the ceiling
+    sell.time_in_force = TimeInForce::GoodTillCancel;
+    let res = place_order(&mut service, &seller, b"sell_4", 40, sell.clone());
+    assert_eq!(res.code, 205);
+
+    // once the chain is past the ceiling only the admin can reopen the dex
+    sell.time_in_force = TimeInForce::GoodTillDate;
+    sell.expiry = None;
+    let res = place_order(&mut service, &seller, b"sell_8", 60, sell.clone());
+    assert_eq!(res.code, 205);
+    let context = mock_context(CYCLES_LIMIT, seller.clone());
+    let res = service.set_max_expiry(context, SetMaxExpiryPayload { max_expiry: 100 });
+    assert_eq!(res.code, 209);
+    let context = mock_context(CYCLES_LIMIT, mock_genesis().admin);
+    let res = service.set_max_expiry(context.clone(), SetMaxExpiryPayload { max_expiry: 100 });
+    assert!(!res.is_error());
+    assert!(!place_order(&mut service, &seller, b"sell_8", 60, sell.clone()).is_error());
+    let order = service.get_open_order(&Hash::digest(Bytes::from_static(b"sell_8")));
+    assert_eq!(order.succeed_data.expiry, 100);
+
+    // and 0 lifts the ceiling altogether
+    let res = service.set_max_expiry(context, SetMaxExpiryPayload { max_expiry: 0 });
+    assert!(!res.is_error());
+    sell.time_in_force = TimeInForce::GoodTillCancel;
+    assert!(!place_order(&mut service, &seller, b"sell_9", 60, sell).is_error());
+}
+
+#[test]
+fn test_reconcile_locks() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &buyer, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 100);
+    assert!(!place_order(&mut service, &buyer, b"buy", 1, buy).is_error());
+
+    // lock 500 more than the open buy accounts for
+    service.lock_asset(ModifyAssetPayload {
+        asset_id: base.clone(),
+        user:     buyer.clone(),
+        value:    500,
+    });
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 1500);
+
+    let payload = ReconcileLocksPayload {
+        user:     buyer.clone(),
+        asset_id: base.clone(),
+    };
+    let res = service.reconcile_locks(mock_context(CYCLES_LIMIT, buyer.clone()), payload.clone());
+    assert_eq!(res.code, 209);
+
+    let res = service.reconcile_locks(mock_context(CYCLES_LIMIT, admin), payload);
+    assert_eq!(res.succeed_data.expected, 1000);
+    assert_eq!(res.succeed_data.unlocked, 500);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 1000);
+}
+
+#[test]
+fn test_open_interest() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
 
     let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let orders = vec![
+        (&buyer, b"buy_1" as &'static [u8], OrderKind::Buy, 10, 100),
+        (&buyer, b"buy_2", OrderKind::Buy, 8, 50),
+        (&seller, b"sell_1", OrderKind::Sell, 12, 30),
+        (&seller, b"sell_2", OrderKind::Sell, 15, 20),
+    ];
+    for (user, seed, kind, price, amount) in orders {
+        let payload = order_payload(&trade, kind, price, amount);
+        assert!(!place_order(&mut service, user, seed, 1, payload).is_error());
+    }
 
-    let supply = 1024 * 1024;
-    // test create_asset
-    let trade = service
-        .add_trade(context.clone(), Trade {
-            base_asset: Hash::from_empty(),
-            counter_party: Hash::from_empty(),
+    let context = mock_context(CYCLES_LIMIT, buyer.clone());
+    let interest = service
+        .get_open_interest(context, GetOpenInterestPayload {
+            trade_id: trade.id.clone(),
         })
-        .unwrap();
+        .succeed_data;
 
-    println!("add trade:{:?}", trade);
+    assert_eq!(interest.buy_amount, 150);
+    assert_eq!(interest.buy_notional, 10 * 100 + 8 * 50);
+    assert_eq!(interest.sell_amount, 50);
+    assert_eq!(interest.sell_notional, 12 * 30 + 15 * 20);
+}
 
-    let trades = service
-        .get_trades(context.clone())
-        .unwrap();
+#[test]
+fn test_price_scale() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
 
-    println!("get trades:{:?}", trades);
-    // assert_eq!(trade, new_asset);
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, AddTradePayload {
+        price_scale: 1_000_000,
+        ..trade_payload(&base, &counter)
+    });
+
+    // 0.5 base per counter
+    let sell = order_payload(&trade, OrderKind::Sell, 500_000, 101);
+    assert!(!place_order(&mut service, &seller, b"sell", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 500_000, 101);
+    assert!(!place_order(&mut service, &buyer, b"buy", 2, buy).is_error());
+    // 101 * 0.5 rounds down to 50
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 50);
+
+    service.match_trade(&trade, 2);
+
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 101);
+    assert_eq!(get_balance(&service, &buyer, &base).locked, 0);
+    assert_eq!(get_balance(&service, &buyer, &base).current, 1_000_000 - 50);
+    assert_eq!(get_balance(&service, &seller, &base).current, 50);
+}
+
+#[test]
+fn test_rate_limit() {
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        max_orders_per_block: 2,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 10, 1);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell.clone()).is_error());
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell.clone()).is_error());
+
+    let res = place_order(&mut service, &seller, b"sell_3", 1, sell.clone());
+    assert_eq!(res.code, 210);
+
+    assert!(!place_order(&mut service, &seller, b"sell_3", 2, sell).is_error());
+}
+
+#[test]
+fn test_order_book_snapshot() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        order_book_snapshot: true,
+        snapshot_depth:      2,
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell_1 = order_payload(&trade, OrderKind::Sell, 10, 5);
+    assert!(!place_order(&mut service, &seller, b"sell_1", 1, sell_1).is_error());
+    let sell_2 = order_payload(&trade, OrderKind::Sell, 11, 2);
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell_2.clone()).is_error());
+    assert!(!place_order(&mut service, &seller, b"sell_3", 1, sell_2).is_error());
+    let sell_4 = order_payload(&trade, OrderKind::Sell, 12, 1);
+    assert!(!place_order(&mut service, &seller, b"sell_4", 1, sell_4).is_error());
+    let buy_1 = order_payload(&trade, OrderKind::Buy, 10, 3);
+    assert!(!place_order(&mut service, &buyer, b"buy_1", 2, buy_1).is_error());
+    let buy_2 = order_payload(&trade, OrderKind::Buy, 8, 4);
+    assert!(!place_order(&mut service, &buyer, b"buy_2", 2, buy_2).is_error());
+
+    let trades = vec![trade.clone()];
+    service.match_trade(&trade, 2);
+    service.snapshot_order_books(&trades, 2);
+
+    let context = mock_context(CYCLES_LIMIT, buyer);
+    let events = service
+        .get_hook_events(context, GetHookEventsPayload { height: 2 })
+        .succeed_data
+        .events;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].name, "OrderBookSnapshot");
+
+    let snapshot: OrderBook = serde_json::from_str(&events[0].data).unwrap();
+    assert_eq!(snapshot.buys, vec![PriceLevel {
+        price:  8,
+        amount: 4,
+    }]);
+    assert_eq!(snapshot.sells, vec![
+        PriceLevel {
+            price:  10,
+            amount: 2,
+        },
+        PriceLevel {
+            price:  11,
+            amount: 4,
+        },
+    ]);
+}
+
+#[test]
+fn test_hidden_order() {
+    let buyer = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    let base = create_asset(&mut service, &buyer, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let hidden = OrderPayload {
+        hidden: true,
+        ..order_payload(&trade, OrderKind::Sell, 10, 5)
+    };
+    assert!(!place_order(&mut service, &seller, b"hidden", 1, hidden).is_error());
+    let visible = order_payload(&trade, OrderKind::Sell, 10, 3);
+    assert!(!place_order(&mut service, &seller, b"visible", 2, visible).is_error());
+
+    // only the visible order shows in the book
+    let book = service.order_book(&trade.id, 0);
+    assert_eq!(book.sells, vec![PriceLevel {
+        price:  10,
+        amount: 3,
+    }]);
+
+    // the visible order fills first although the hidden one rests longer
+    let buy = order_payload(&trade, OrderKind::Buy, 10, 4);
+    assert!(!place_order(&mut service, &buyer, b"buy", 3, buy).is_error());
+    service.match_trade(&trade, 3);
+
+    let sells = service.get_open_orders(&trade.id, OrderKind::Sell);
+    assert_eq!(sells.len(), 1);
+    assert!(sells[0].hidden);
+    assert_eq!(sells[0].remaining(), 4);
+    assert_eq!(get_balance(&service, &buyer, &counter).current, 4);
+    assert!(service.order_book(&trade.id, 0).sells.is_empty());
+}
+
+#[test]
+fn test_get_user_trades() {
+    let user = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(mock_genesis());
+
+    let first = create_asset(&mut service, &user, "FST");
+    let second = create_asset(&mut service, &user, "SND");
+    let third = create_asset(&mut service, &user, "TRD");
+    let trade_1 = add_trade(&mut service, trade_payload(&first, &second));
+    let trade_2 = add_trade(&mut service, trade_payload(&first, &third));
+    add_trade(&mut service, trade_payload(&second, &third));
+
+    let buy = order_payload(&trade_1, OrderKind::Buy, 10, 10);
+    assert!(!place_order(&mut service, &user, b"buy", 1, buy).is_error());
+    let sell = order_payload(&trade_2, OrderKind::Sell, 10, 10);
+    assert!(!place_order(&mut service, &user, b"sell", 1, sell).is_error());
+
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let mut trade_ids = service
+        .get_user_trades(context, GetUserTradesPayload { user })
+        .succeed_data
+        .trade_ids;
+    trade_ids.sort_by_key(|id| id.as_hex());
+
+    let mut expected = vec![trade_1.id, trade_2.id];
+    expected.sort_by_key(|id| id.as_hex());
+    assert_eq!(trade_ids, expected);
+}
+
+#[test]
+fn test_update_trade_params() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let seller = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &seller, "BASE");
+    let counter = create_asset(&mut service, &seller, "CNT");
+    let trade = add_trade(&mut service, AddTradePayload {
+        tick_size: 5,
+        ..trade_payload(&base, &counter)
+    });
+
+    let sell = order_payload(&trade, OrderKind::Sell, 12, 10);
+    let res = place_order(&mut service, &seller, b"sell_1", 1, sell.clone());
+    assert_eq!(res.code, 211);
+
+    let update = UpdateTradeParamsPayload {
+        trade_id:     trade.id.clone(),
+        tick_size:    1,
+        lot_size:     1,
+        min_notional: 0,
+        allow_buy:    true,
+        allow_sell:   true,
+        validity:     0,
+    };
+    let context = mock_context(CYCLES_LIMIT, seller.clone());
+    let res = service.update_trade_params(context, update.clone());
+    assert_eq!(res.code, 209);
+    let context = mock_context(CYCLES_LIMIT, admin);
+    let res = service.update_trade_params(context, update);
+    assert!(!res.is_error());
+
+    assert!(!place_order(&mut service, &seller, b"sell_2", 1, sell).is_error());
+}
+
+#[test]
+fn test_offgrid_orders() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let user = Address::from_hex("0x666cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        admin: admin.clone(),
+        ..mock_genesis()
+    });
+
+    let base = create_asset(&mut service, &user, "BASE");
+    let counter = create_asset(&mut service, &user, "CNT");
+    let trade = add_trade(&mut service, trade_payload(&base, &counter));
+
+    let sell = order_payload(&trade, OrderKind::Sell, 12, 10);
+    assert!(!place_order(&mut service, &user, b"sell_1", 1, sell).is_error());
+    let sell = order_payload(&trade, OrderKind::Sell, 15, 10);
+    assert!(!place_order(&mut service, &user, b"sell_2", 1, sell).is_error());
+    let buy = order_payload(&trade, OrderKind::Buy, 8, 10);
+    assert!(!place_order(&mut service, &user, b"buy", 1, buy).is_error());
+
+    let payload = OffgridOrdersPayload {
+        trade_id: trade.id.clone(),
+    };
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let offgrid = |service: &TestDexService| {
+        let res = service.find_offgrid_orders(context.clone(), payload.clone());
+        let mut tx_hashes: Vec<Hash> = res
+            .succeed_data
+            .orders
+            .into_iter()
+            .map(|order| order.tx_hash)
+            .collect();
+        tx_hashes.sort_by_key(|tx_hash| tx_hash.as_hex());
+        tx_hashes
+    };
+    assert!(offgrid(&service).is_empty());
+
+    let admin_context = mock_context(CYCLES_LIMIT, admin);
+    let res = service.update_trade_params(admin_context.clone(), UpdateTradeParamsPayload {
+        trade_id:     trade.id.clone(),
+        tick_size:    5,
+        lot_size:     1,
+        min_notional: 0,
+        allow_buy:    true,
+        allow_sell:   true,
+        validity:     0,
+    });
+    assert!(!res.is_error());
+
+    let mut expected = vec![
+        Hash::digest(Bytes::from_static(b"sell_1")),
+        Hash::digest(Bytes::from_static(b"buy")),
+    ];
+    expected.sort_by_key(|tx_hash| tx_hash.as_hex());
+    assert_eq!(offgrid(&service), expected);
+
+    let res = service.cancel_offgrid_orders(context.clone(), payload.clone());
+    assert_eq!(res.code, 209);
+    let res = service.cancel_offgrid_orders(admin_context, payload.clone());
+    assert!(!res.is_error());
+    assert!(offgrid(&service).is_empty());
+    assert_eq!(get_balance(&service, &user, &counter).locked, 10);
+    assert_eq!(get_balance(&service, &user, &base).locked, 0);
+    assert_eq!(service.get_open_orders(&trade.id, OrderKind::Sell).len(), 1);
+}
+
+#[test]
+fn test_get_config() {
+    let admin = Address::from_hex("0x999cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let treasury = Address::from_hex("0x888cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+
+    let genesis = GenesisPayload {
+        order_validity:       50,
+        pro_rata:             true,
+        fee_rate:             30,
+        referral_rate:        2000,
+        maker_rebate:         10,
+        fee_account:          treasury,
+        circuit_breaker:      500,
+        admin,
+        max_orders_per_block: 8,
+        order_book_snapshot:  true,
+        snapshot_depth:       5,
+        native_asset:         Hash::digest(Bytes::from_static(b"native")),
+        max_notional:         1_000_000,
+        net_settlement:       true,
+        rounding:             Rounding::Nearest,
+        expiry_grace:         3,
+        max_expiry:           1_000_000,
+        trades:               Vec::new(),
+        orders:               Vec::new(),
+    };
+    let mut service = new_dex_service();
+    service.init_genesis(genesis.clone());
+
+    let context = mock_context(CYCLES_LIMIT, genesis.admin.clone());
+    let config = service.get_config(context).succeed_data;
+    assert_eq!(config, GetConfigResponse {
+        order_validity:       genesis.order_validity,
+        pro_rata:             genesis.pro_rata,
+        fee_rate:             genesis.fee_rate,
+        referral_rate:        genesis.referral_rate,
+        maker_rebate:         genesis.maker_rebate,
+        fee_account:          genesis.fee_account,
+        circuit_breaker:      genesis.circuit_breaker,
+        admin:                genesis.admin,
+        max_orders_per_block: genesis.max_orders_per_block,
+        order_book_snapshot:  genesis.order_book_snapshot,
+        snapshot_depth:       genesis.snapshot_depth,
+        native_asset:         genesis.native_asset,
+        max_notional:         genesis.max_notional,
+        net_settlement:       genesis.net_settlement,
+        rounding:             genesis.rounding,
+        expiry_grace:         genesis.expiry_grace,
+        max_expiry:           genesis.max_expiry,
+    });
+}
+
+#[test]
+fn test_native_asset() {
+    let caller = Address::from_hex("0x755cdba6ae4f479f7164792b318b2a06c759833b").unwrap();
+    let native_asset = Hash::digest(Bytes::from_static(b"native"));
+
+    let mut service = new_dex_service();
+    service.init_genesis(GenesisPayload {
+        native_asset: native_asset.clone(),
+        ..mock_genesis()
+    });
+
+    let context = mock_context(CYCLES_LIMIT, caller);
+    let res = service.get_native_asset(context);
+    assert_eq!(res.succeed_data.asset_id, native_asset);
+}
+
+#[test]
+fn test_notional_decimals() {
+    let mut trade = Trade {
+        price_scale:      1,
+        base_decimals:    2,
+        counter_decimals: 8,
+        ..Default::default()
+    };
+    // 1.5 counter party at a price of 3 is 4.5 base asset
+    assert_eq!(trade.notional(150_000_000, 3), 450);
+    assert_eq!(trade.notional(1, 3), 0);
+
+    trade.base_decimals = 8;
+    trade.counter_decimals = 2;
+    assert_eq!(trade.notional(150, 3), 450_000_000);
+    assert_eq!(trade.notional(std::u64::MAX, 1), std::u64::MAX);
+}
+
+fn mock_genesis() -> GenesisPayload {
+    GenesisPayload {
+        order_validity:  100,
+        pro_rata:        false,
+        fee_rate:        0,
+        referral_rate:   0,
+        maker_rebate:    0,
+        fee_account:     Address::from_hash(Hash::from_empty()).unwrap(),
+        circuit_breaker: 0,
+        admin:           Address::from_hash(Hash::from_empty()).unwrap(),
+
+        max_orders_per_block: 0,
+        order_book_snapshot:  false,
+        snapshot_depth:       0,
+        native_asset:         Hash::from_empty(),
+        max_notional:         0,
+        net_settlement:       false,
+        rounding:             Rounding::Floor,
+        expiry_grace:         0,
+        max_expiry:           0,
+        trades:               Vec::new(),
+        orders:               Vec::new(),
+    }
+}
+
+fn create_asset(service: &mut TestDexService, issuer: &Address, symbol: &str) -> Hash {
+    let context = mock_context(CYCLES_LIMIT, issuer.clone());
+    let asset = service.asset.create_asset(context, CreateAssetPayload {
+        name:             symbol.to_owned(),
+        symbol:           symbol.to_owned(),
+        supply:           1_000_000,
+        decimals:         0,
+        max_supply:       0,
+        transfer_fee_bps: 0,
+    });
+
+    asset.succeed_data.id
+}
+
+fn get_balance(service: &TestDexService, user: &Address, asset_id: &Hash) -> Balance {
+    let context = mock_context(CYCLES_LIMIT, user.clone());
+    let res = service.asset.get_balance(context, GetBalancePayload {
+        asset_id: asset_id.clone(),
+        user:     user.clone(),
+    });
+
+    res.succeed_data.balance
+}
+
+fn trade_payload(base_asset: &Hash, counter_party: &Hash) -> AddTradePayload {
+    AddTradePayload {
+        base_asset: base_asset.clone(),
+        counter_party: counter_party.clone(),
+        ..Default::default()
+    }
+}
+
+fn add_trade(service: &mut TestDexService, payload: AddTradePayload) -> Trade {
+    let context = mock_context(CYCLES_LIMIT, Address::from_hash(Hash::from_empty()).unwrap());
+    service.add_trade(context.clone(), payload.clone());
+
+    service
+        .get_trades(context)
+        .succeed_data
+        .trades
+        .into_iter()
+        .find(|trade| {
+            trade.base_asset == payload.base_asset && trade.counter_party == payload.counter_party
+        })
+        .expect("trade should be added")
+}
+
+fn order_payload(trade: &Trade, kind: OrderKind, price: u64, amount: u64) -> OrderPayload {
+    OrderPayload {
+        trade_id: trade.id.clone(),
+        kind,
+        price,
+        amount,
+        expiry: Some(100),
+        referrer: None,
+        time_in_force: TimeInForce::GoodTillDate,
+        use_locked: false,
+        hidden: false,
+        client_order_id: None,
+        reduce_only: false,
+    }
+}
+
+fn place_order(
+    service: &mut TestDexService,
+    user: &Address,
+    seed: &'static [u8],
+    height: u64,
+    payload: OrderPayload,
+) -> ServiceResponse<()> {
+    let context = mock_tx_context(user.clone(), Hash::digest(Bytes::from_static(seed)), height);
+    service.order(context, payload)
 }
 
-fn new_dex_service() -> DexService<
-    DefalutServiceSDK<
-        GeneralServiceState<MemoryDB>,
-        DefaultChainQuerier<MockStorage>,
-        NoopDispatcher,
-    >,
-> {
-    let chain_db = DefaultChainQuerier::new(Arc::new(MockStorage {}));
+fn new_dex_service() -> TestDexService {
+    let chain_db = Rc::new(DefaultChainQuerier::new(Arc::new(MockStorage {})));
     let trie = MPTTrie::new(Arc::new(MemoryDB::new(false)));
-    let state = GeneralServiceState::new(trie);
+    let state = Rc::new(RefCell::new(GeneralServiceState::new(trie)));
 
-    let sdk = DefalutServiceSDK::new(
-        Rc::new(RefCell::new(state)),
-        Rc::new(chain_db),
-        NoopDispatcher {},
-    );
+    let asset_sdk = DefalutServiceSDK::new(Rc::clone(&state), Rc::clone(&chain_db), NoopDispatcher {});
+    let dex_sdk = DefalutServiceSDK::new(state, chain_db, NoopDispatcher {});
+
+    DexService::new(dex_sdk, AssetService::new(asset_sdk))
+}
+
+fn mock_tx_context(caller: Address, tx_hash: Hash, height: u64) -> ServiceContext {
+    let params = ServiceContextParams {
+        tx_hash: Some(tx_hash),
+        nonce: None,
+        cycles_limit: CYCLES_LIMIT,
+        cycles_price: 1,
+        cycles_used: Rc::new(RefCell::new(0)),
+        caller,
+        height,
+        timestamp: 0,
+        service_name: "service_name".to_owned(),
+        service_method: "service_method".to_owned(),
+        service_payload: "service_payload".to_owned(),
+        extra: None,
+        events: Rc::new(RefCell::new(vec![])),
+    };
 
-    DexService::new(sdk).unwrap()
+    ServiceContext::new(params)
 }
 
 fn mock_context(cycles_limit: u64, caller: Address) -> ServiceContext {
@@ -85,7 +4100,7 @@ fn mock_context(cycles_limit: u64, caller: Address) -> ServiceContext {
         cycles_price: 1,
         cycles_used: Rc::new(RefCell::new(0)),
         caller,
-        epoch_id: 1,
+        height: 1,
         timestamp: 0,
         service_name: "service_name".to_owned(),
         service_method: "service_method".to_owned(),
@@ -97,6 +4112,26 @@ fn mock_context(cycles_limit: u64, caller: Address) -> ServiceContext {
     ServiceContext::new(params)
 }
 
+fn mock_admitted_context(token: &'static [u8]) -> ServiceContext {
+    let params = ServiceContextParams {
+        tx_hash: None,
+        nonce: None,
+        cycles_limit: CYCLES_LIMIT,
+        cycles_price: 1,
+        cycles_used: Rc::new(RefCell::new(0)),
+        caller: Address::from_hash(Hash::from_empty()).unwrap(),
+        height: 1,
+        timestamp: 0,
+        service_name: "service_name".to_owned(),
+        service_method: "service_method".to_owned(),
+        service_payload: "service_payload".to_owned(),
+        extra: Some(Bytes::from_static(token)),
+        events: Rc::new(RefCell::new(vec![])),
+    };
+
+    ServiceContext::new(params)
+}
+
 struct MockStorage;
 
 #[async_trait]
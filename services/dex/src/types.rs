@@ -7,131 +7,1267 @@ use protocol::fixed_codec::{FixedCodec, FixedCodecError};
 use protocol::types::{Address, Hash};
 use protocol::ProtocolResult;
 
+use asset::types::scale_amount;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GenesisPayload {
+    pub order_validity: u64,
+    #[serde(default)]
+    pub pro_rata: bool,
+    // taker fee in basis points, paid to fee_account
+    #[serde(default)]
+    pub fee_rate: u64,
+    // share of the taker fee, in basis points, paid to the taker's referrer
+    #[serde(default)]
+    pub referral_rate: u64,
+    // rebate paid to the maker out of the taker fee, in basis points of what
+    // the taker receives. must not exceed fee_rate
+    #[serde(default)]
+    pub maker_rebate: u64,
+    #[serde(default)]
+    pub fee_account: Address,
+    // max deviation of a deal price from the trade's last price, in basis
+    // points. 0 disables the circuit breaker
+    #[serde(default)]
+    pub circuit_breaker: u64,
+    #[serde(default)]
+    pub admin: Address,
+    // max orders a user may place in one block, 0 means unlimited
+    #[serde(default)]
+    pub max_orders_per_block: u64,
+    // emit an OrderBookSnapshot hook event per active trade after matching,
+    // holding at most snapshot_depth levels per side
+    #[serde(default)]
+    pub order_book_snapshot: bool,
+    #[serde(default)]
+    pub snapshot_depth: u64,
+    // the chain's native asset, used by default where no asset is specified
+    #[serde(default)]
+    pub native_asset: Hash,
+    // max amount * price of a single order, 0 means unlimited
+    #[serde(default)]
+    pub max_notional: u64,
+    // apply the asset changes of a block's matches once per user and asset
+    // instead of once per deal
+    #[serde(default)]
+    pub net_settlement: bool,
+    // how notional and fee divisions round, taken over by every trade added
+    #[serde(default)]
+    pub rounding: Rounding,
+    // blocks an order stays matchable past its expiry before it is swept
+    #[serde(default)]
+    pub expiry_grace: u64,
+    // highest expiry height any good-till-date order may have, whatever the
+    // validity window allows. 0 means no ceiling
+    #[serde(default)]
+    pub max_expiry: u64,
+    // trades and open orders to start from, as the pages of export_state
+    // list them. the asset service state must already hold the orders' locks
+    #[serde(default)]
+    pub trades: Vec<Trade>,
+    #[serde(default)]
+    pub orders: Vec<Order>,
+}
+
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+pub fn apply_bps(value: u64, bps: u64) -> u64 {
+    (value as u128 * bps as u128 / BPS_DENOMINATOR as u128) as u64
+}
+
+// the direction a division that doesn't come out even is rounded in.
+// Floor favours whoever pays: a buyer pays less base asset for a deal and a
+// taker less fee. Ceil favours whoever receives, i.e. the seller and the fee
+// account, though a buyer never pays more than its order locked. Nearest
+// rounds half up and favours neither side over many deals
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub enum Rounding {
+    Floor,
+    Ceil,
+    Nearest,
+}
+
+impl Default for Rounding {
+    fn default() -> Self {
+        Rounding::Floor
+    }
+}
+
+impl Rounding {
+    pub fn div(&self, numerator: u128, denominator: u128) -> u128 {
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        let round_up = match self {
+            Rounding::Floor => false,
+            Rounding::Ceil => remainder != 0,
+            Rounding::Nearest => remainder >= denominator - remainder,
+        };
+        if round_up {
+            quotient + 1
+        } else {
+            quotient
+        }
+    }
+
+    pub fn apply_bps(&self, value: u64, bps: u64) -> u64 {
+        let applied = self.div(value as u128 * bps as u128, BPS_DENOMINATOR as u128);
+        std::cmp::min(applied, std::u64::MAX as u128) as u64
+    }
+}
+
+impl From<&Rounding> for u64 {
+    fn from(rounding: &Rounding) -> u64 {
+        match rounding {
+            Rounding::Floor => 0,
+            Rounding::Ceil => 1,
+            Rounding::Nearest => 2,
+        }
+    }
+}
+
+impl From<u64> for Rounding {
+    fn from(tag: u64) -> Rounding {
+        match tag {
+            1 => Rounding::Ceil,
+            2 => Rounding::Nearest,
+            _ => Rounding::Floor,
+        }
+    }
+}
+
+// a trade without a last price, or a limit of 0, never trips the breaker
+pub fn exceeds_deviation(last_price: u64, price: u64, max_bps: u64) -> bool {
+    if last_price == 0 || max_bps == 0 {
+        return false;
+    }
+
+    let diff = if price > last_price {
+        price - last_price
+    } else {
+        last_price - price
+    };
+    diff as u128 * BPS_DENOMINATOR as u128 > last_price as u128 * max_bps as u128
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct Trade {
+    pub id: Hash,
+    pub base_asset: Hash,
+    pub counter_party: Hash,
+    pub price_rule: PriceRule,
+    // orders whose notional is below it are rejected, 0 disables the check
+    pub min_notional: u64,
+    // prices are fixed-point values with price_scale as one unit
+    pub price_scale: u64,
+    // order prices must be multiples of tick_size and amounts of lot_size
+    pub tick_size: u64,
+    pub lot_size: u64,
+    pub base_decimals: u8,
+    pub counter_decimals: u8,
+    // new orders of a disallowed side are rejected, resting ones still settle
+    pub allow_buy: bool,
+    pub allow_sell: bool,
+    // how far ahead an order may expire, 0 falls back to the genesis validity
+    pub validity: u64,
+    // bounds on the taker fee, in the asset the taker receives. a max_fee
+    // of 0 leaves the fee uncapped
+    pub min_fee: u64,
+    pub max_fee: u64,
+    pub rounding: Rounding,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct AddTradePayload {
+    pub base_asset: Hash,
+    pub counter_party: Hash,
+    #[serde(default)]
+    pub price_rule: PriceRule,
+    #[serde(default)]
+    pub min_notional: u64,
+    // 0 is taken as 1, i.e. integer prices
+    #[serde(default)]
+    pub price_scale: u64,
+    // 0 is taken as 1, i.e. no restriction
+    #[serde(default)]
+    pub tick_size: u64,
+    #[serde(default)]
+    pub lot_size: u64,
+    #[serde(default)]
+    pub min_fee: u64,
+    #[serde(default)]
+    pub max_fee: u64,
+}
+
+// tick_size and lot_size of 0 are taken as 1, as in AddTradePayload
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct UpdateTradeParamsPayload {
+    pub trade_id: Hash,
+    pub tick_size: u64,
+    pub lot_size: u64,
+    pub min_notional: u64,
+    pub allow_buy: bool,
+    pub allow_sell: bool,
+    pub validity: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct OffgridOrdersPayload {
+    pub trade_id: Hash,
+}
+
+// open orders resting at a price off the trade's current tick grid, left by
+// a change of tick_size
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct FindOffgridOrdersResponse {
+    pub orders: Vec<Order>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SetTradeEnabledPayload {
+    pub trade_id: Hash,
+    pub enabled: bool,
+    // when disabling, also cancel every open order of the trade
+    #[serde(default)]
+    pub cancel_orders: bool,
+}
+
+// assets sharing a group, e.g. a token and its wrapped form, can't be traded
+// against each other. an empty group removes the asset from its group
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SetAssetGroupPayload {
+    pub asset_id: Hash,
+    pub group: String,
+}
+
+// taker fee in basis points, at most MAX_FEE_RATE and at least maker_rebate
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SetFeeRatePayload {
+    pub fee_rate: u64,
+}
+
+// the last height an order may expire at, 0 lifts the ceiling
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SetMaxExpiryPayload {
+    pub max_expiry: u64,
+}
+
+// the id of a trade doesn't depend on which asset is the base asset
+pub fn canonical_trade_id(base_asset: &Hash, counter_party: &Hash) -> Hash {
+    if base_asset < counter_party {
+        Hash::digest(Bytes::from(base_asset.as_hex() + &counter_party.as_hex()))
+    } else {
+        Hash::digest(Bytes::from(counter_party.as_hex() + &base_asset.as_hex()))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ComputeTradeIdPayload {
+    pub base_asset: Hash,
+    pub counter_party: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ComputeTradeIdResponse {
+    pub trade_id: Hash,
+}
+
+impl Trade {
+    // amount * price / price_scale of base asset, rounded as the trade's
+    // rounding says, with amount rescaled from counter party decimals to base
+    // asset decimals, which always rounds down.
+    // saturates at u64::MAX, which no balance can ever cover
+    pub fn notional(&self, amount: u64, price: u64) -> u64 {
+        // rescale before dividing when that grows the value, and after it
+        // otherwise, so the result is only rounded down once
+        let scaled = if self.base_decimals >= self.counter_decimals {
+            scale_amount(amount, self.counter_decimals, self.base_decimals)
+                .map(|amount| self.unscaled_notional(amount, price))
+        } else {
+            scale_amount(
+                self.unscaled_notional(amount, price),
+                self.counter_decimals,
+                self.base_decimals,
+            )
+        };
+        scaled.unwrap_or(std::u64::MAX)
+    }
+
+    // the largest amount whose notional at price doesn't exceed `notional`
+    pub fn amount_for_notional(&self, notional: u64, price: u64) -> u64 {
+        let (mut low, mut high) = (0u64, std::u64::MAX);
+        while low < high {
+            let mid = low + (high - low) / 2 + 1;
+            if self.notional(mid, price) <= notional {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low
+    }
+
+    fn unscaled_notional(&self, amount: u64, price: u64) -> u64 {
+        let notional = self
+            .rounding
+            .div(amount as u128 * price as u128, self.price_scale as u128);
+        std::cmp::min(notional, std::u64::MAX as u128) as u64
+    }
+
+    // the fee never exceeds what it is taken out of
+    pub fn clamp_fee(&self, fee: u64, receive: u64) -> u64 {
+        let mut fee = std::cmp::max(fee, self.min_fee);
+        if self.max_fee != 0 {
+            fee = std::cmp::min(fee, self.max_fee);
+        }
+        std::cmp::min(fee, receive)
+    }
+
+    pub fn allows(&self, kind: &OrderKind) -> bool {
+        match kind {
+            OrderKind::Buy => self.allow_buy,
+            OrderKind::Sell => self.allow_sell,
+        }
+    }
+
+    pub fn locked_asset(&self, kind: &OrderKind) -> &Hash {
+        match kind {
+            OrderKind::Buy => &self.base_asset,
+            OrderKind::Sell => &self.counter_party,
+        }
+    }
+
+    // whether an order of this trade can rest on `other` as it is, i.e. the
+    // assets are the same and the notional it has locked is computed the same
+    pub fn is_compatible(&self, other: &Trade) -> bool {
+        self.base_asset == other.base_asset
+            && self.counter_party == other.counter_party
+            && self.price_scale == other.price_scale
+            && self.base_decimals == other.base_decimals
+            && self.counter_decimals == other.counter_decimals
+            && self.rounding == other.rounding
+    }
+}
+
+// decide which price a crossing buy and sell deal at
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub enum PriceRule {
+    Midpoint,
+    MakerPrice,
+    TakerPrice,
+}
+
+impl Default for PriceRule {
+    fn default() -> Self {
+        PriceRule::Midpoint
+    }
+}
+
+impl PriceRule {
+    pub fn deal_price(&self, buy: &Order, sell: &Order) -> u64 {
+        let (maker, taker) = match taker_kind(buy, sell) {
+            OrderKind::Buy => (sell, buy),
+            OrderKind::Sell => (buy, sell),
+        };
+
+        match self {
+            PriceRule::Midpoint => (buy.price + sell.price) / 2,
+            PriceRule::MakerPrice => maker.price,
+            PriceRule::TakerPrice => taker.price,
+        }
+    }
+}
+
+// the maker is the resting order, i.e. the one placed at the lower height;
+// if both arrived at the same height the sell is treated as the maker
+pub fn taker_kind(buy: &Order, sell: &Order) -> OrderKind {
+    if buy.height < sell.height {
+        OrderKind::Sell
+    } else {
+        OrderKind::Buy
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetTradePayload {
+    pub trade_id: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetTradesResponse {
+    pub trades: Vec<Trade>,
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct Order {
+    pub trade_id: Hash,
+    pub tx_hash: Hash,
+    pub kind: OrderKind,
+    pub price: u64,
+    pub amount: u64,
+    pub height: u64,
+    pub user: Address,
+    pub expiry: u64,
+    pub status: OrderStatus,
+    // amount filled so far, the status turns Full once it reaches amount
+    pub filled: u64,
+    pub deals: Vec<Deal>,
+    pub referrer: Option<Address>,
+    // taker fees paid so far, in the asset the order receives
+    pub fees: u64,
+    // a hidden order rests and matches but is left out of the order book
+    pub hidden: bool,
+}
+
+impl Order {
+    pub fn remaining(&self) -> u64 {
+        self.amount - self.filled
+    }
+
+    // priority between two orders at the same price
+    fn time_priority(&self, other: &Order) -> Ordering {
+        other
+            .hidden
+            .cmp(&self.hidden)
+            .then(other.height.cmp(&self.height))
+    }
+
+    // what the order still holds locked: a buy locks the notional of its
+    // remaining amount in base asset, a sell locks its remaining counter party
+    pub fn locked(&self, trade: &Trade) -> u64 {
+        match self.kind {
+            OrderKind::Buy => trade.notional(self.remaining(), self.price),
+            OrderKind::Sell => self.remaining(),
+        }
+    }
+
+    pub fn fill(&mut self, deal: Deal) {
+        self.filled += deal.amount;
+        self.status = if self.filled == self.amount {
+            OrderStatus::Full
+        } else {
+            OrderStatus::Partial
+        };
+        self.deals.push(deal);
+    }
+}
+
+// split `total` across orders in proportion to their `weights`.
+// if the weights don't add up to `total` every order is filled completely,
+// otherwise the remainder left by rounding down is handed out one unit at a
+// time in priority order, i.e. the order of `weights`
+pub fn pro_rata_allocate(total: u64, weights: &[u64]) -> Vec<u64> {
+    let sum: u128 = weights.iter().map(|w| *w as u128).sum();
+    if sum <= total as u128 {
+        return weights.to_vec();
+    }
+
+    let mut shares: Vec<u64> = weights
+        .iter()
+        .map(|w| (total as u128 * *w as u128 / sum) as u64)
+        .collect();
+
+    let mut remainder = total - shares.iter().sum::<u64>();
+    for (share, weight) in shares.iter_mut().zip(weights.iter()) {
+        if remainder == 0 {
+            break;
+        }
+        if *share < *weight {
+            *share += 1;
+            remainder -= 1;
+        }
+    }
+
+    shares
+}
+
+// tx hashes of the open orders of one trade, per side
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq, Default)]
+pub struct OrderIndex {
+    pub buys: Vec<Hash>,
+    pub sells: Vec<Hash>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub enum OrderKind {
+    Buy,
+    Sell,
+}
+
+impl Default for OrderKind {
+    fn default() -> Self {
+        OrderKind::Buy
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub enum OrderStatus {
+    Fresh,
+    Partial,
+    Full,
+}
+
+impl Default for OrderStatus {
+    fn default() -> Self {
+        OrderStatus::Fresh
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct Deal {
+    pub price: u64,
+    pub amount: u64,
+    pub height: u64,
+}
+
+// an entry of the deal log, which keeps every deal of every trade in the
+// order they were settled
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq, Default)]
+pub struct DealRecord {
+    pub trade_id: Hash,
+    pub price: u64,
+    pub amount: u64,
+    pub height: u64,
+    pub maker: Address,
+    pub taker: Address,
+    // "<trade id>/<deal index in the trade>", which the balance changes that
+    // settled the deal carry too
+    pub correlation_id: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum DealStatus {
+    Dealing,
+    Dealt,
+}
+
+impl Default for DealStatus {
+    fn default() -> Self {
+        Self::Dealing
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct OrderPayload {
+    pub trade_id: Hash,
+    pub kind: OrderKind,
+    pub price: u64,
+    pub amount: u64,
+    // left out, a good-till-date order is valid for the whole validity window
+    #[serde(default)]
+    pub expiry: Option<u64>,
+    pub referrer: Option<Address>,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    // fund the order from locked funds no open order accounts for, e.g. what
+    // an expired order left behind, instead of from the current balance
+    #[serde(default)]
+    pub use_locked: bool,
+    // keep the order out of the order book and its snapshots. it still
+    // matches, after the visible orders at its price
+    #[serde(default)]
+    pub hidden: bool,
+    // the trader's own id for the order, unique among their open orders
+    #[serde(default)]
+    pub client_order_id: Option<String>,
+    // only ever reduce what the user holds: a sell of at most their current
+    // balance of the counter party, and no buys
+    #[serde(default)]
+    pub reduce_only: bool,
+}
+
+// a good-till-date order expires at its expiry height, a good-till-cancel
+// order is stored with the GOOD_TILL_CANCEL sentinel expiry and never expires
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub enum TimeInForce {
+    GoodTillDate,
+    GoodTillCancel,
+}
+
+impl Default for TimeInForce {
+    fn default() -> Self {
+        TimeInForce::GoodTillDate
+    }
+}
+
+pub const GOOD_TILL_CANCEL: u64 = std::u64::MAX;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetOrderPayload {
+    pub tx_hash: Hash,
+    // deals are left out unless asked for, see get_order_deals for paging
+    #[serde(default)]
+    pub with_deals: bool,
+}
+
+// a market order fills immediately against the book at the resting orders'
+// prices and never rests itself. a buy stops before its average price would
+// go above max_avg_price, a sell before it would go below min_avg_price
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MarketOrderPayload {
+    pub trade_id: Hash,
+    pub kind: OrderKind,
+    pub amount: u64,
+    #[serde(default)]
+    pub max_avg_price: Option<u64>,
+    #[serde(default)]
+    pub min_avg_price: Option<u64>,
+    // for a buy, spend up to quote_amount of base asset instead of buying a
+    // fixed amount, in which case amount is ignored
+    #[serde(default)]
+    pub quote_amount: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct MarketOrderResponse {
+    pub filled: u64,
+    // rounded down, 0 if nothing was filled
+    pub avg_price: u64,
+}
+
+// the average price limits work as in MarketOrderPayload
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ConvertToMarketPayload {
+    pub tx_hash: Hash,
+    #[serde(default)]
+    pub max_avg_price: Option<u64>,
+    #[serde(default)]
+    pub min_avg_price: Option<u64>,
+}
+
+// how much of `wanted` can be filled at `price` while keeping the average
+// price of an order that has `filled` for `quote` (the sum of amount * price)
+// within `limit`, i.e. at most `limit` for a buy and at least for a sell
+pub fn fill_within_average(
+    kind: &OrderKind,
+    filled: u64,
+    quote: u128,
+    price: u64,
+    limit: u64,
+    wanted: u64,
+) -> u64 {
+    let (price, limit) = (price as u128, limit as u128);
+    let headroom = match kind {
+        OrderKind::Buy if price > limit => (limit * filled as u128 - quote) / (price - limit),
+        OrderKind::Sell if price < limit => (quote - limit * filled as u128) / (limit - price),
+        _ => return wanted,
+    };
+    std::cmp::min(headroom, wanted as u128) as u64
+}
+
+// the amounts must add up to the order's remaining amount
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SplitOrderPayload {
+    pub tx_hash: Hash,
+    pub amounts: Vec<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SplitOrderResponse {
+    pub tx_hashes: Vec<Hash>,
+}
+
+// the key of the index-th order derived from the order keyed by tx_hash
+pub fn derive_order_hash(tx_hash: &Hash, index: usize) -> Hash {
+    Hash::digest(Bytes::from(tx_hash.as_hex() + &index.to_string()))
+}
+
+// the key of the order user placed under client_order_id
+pub fn client_order_key(user: &Address, client_order_id: &str) -> Hash {
+    Hash::digest(Bytes::from(user.as_hex() + client_order_id))
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BatchOrderPayload {
+    pub orders: Vec<OrderPayload>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct BatchOrderResponse {
+    pub tx_hashes: Vec<Hash>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CancelOrderPayload {
+    pub tx_hash: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CancelByClientIdPayload {
+    pub client_order_id: String,
+}
+
+// move an open order to a new price and total amount. the amount can't drop
+// to what is already filled
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AmendOrderPayload {
+    pub tx_hash: Hash,
+    pub price: u64,
+    pub amount: u64,
+}
+
+// grow an open order at its price, keeping its place in the queue
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct IncreaseOrderPayload {
+    pub tx_hash: Hash,
+    pub additional_amount: u64,
+}
+
+// move an open order onto a duplicate of its trade
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MoveOrderPayload {
+    pub tx_hash: Hash,
+    pub new_trade_id: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ComputeDealPricePayload {
+    pub buy_tx_hash: Hash,
+    pub sell_tx_hash: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ComputeDealPriceResponse {
+    pub trade_id: Hash,
+    pub deal_price: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetHistoryPayload {
+    #[serde(default)]
+    pub user: Option<Address>,
+    pub start: u64,
+    pub limit: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetHistoryResponse {
+    pub orders: Vec<GetOrderResponse>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ExportStatePayload {
+    pub start: u64,
+    pub limit: u64,
+}
+
+// a page of the trades sorted by id followed by the open orders sorted by
+// trade, then tx_hash. start and limit count both, so the trades and orders
+// of every page concatenated are those of a genesis that replays the state
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ExportStateResponse {
+    pub trades: Vec<Trade>,
+    pub orders: Vec<Order>,
+    pub total: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PruneHistoryPayload {
+    pub before_height: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct PruneHistoryResponse {
+    pub pruned: u64,
+    pub pruned_hook_events: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetOrderDealsPayload {
+    pub tx_hash: Hash,
+    pub start: u64,
+    pub limit: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetOrderDealsResponse {
+    pub deals: Vec<Deal>,
+    pub total: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetOrderResponse {
+    pub trade_id: Hash,
+    pub tx_hash: Hash,
+    pub kind: OrderKind,
+    pub price: u64,
+    pub amount: u64,
+    pub height: u64,
+    pub user: Address,
+    pub expiry: u64,
+    pub order_status: OrderStatus,
+    pub filled: u64,
+    pub deal_status: DealStatus,
+    pub deals: Vec<Deal>,
+    // filled share of amount, in basis points rounded down
+    pub fill_ratio_bps: u64,
+}
+
+impl GetOrderResponse {
+    pub fn from_order(order: &Order, status: DealStatus) -> Self {
+        Self {
+            trade_id: order.trade_id.clone(),
+            tx_hash: order.tx_hash.clone(),
+            kind: order.kind.clone(),
+            price: order.price,
+            amount: order.amount,
+            height: order.height,
+            user: order.user.clone(),
+            expiry: order.expiry,
+            order_status: order.status.clone(),
+            filled: order.filled,
+            deal_status: status,
+            deals: order.deals.clone(),
+            fill_ratio_bps: fill_ratio_bps(order),
+        }
+    }
+}
+
+fn fill_ratio_bps(order: &Order) -> u64 {
+    match order.status {
+        OrderStatus::Fresh => 0,
+        OrderStatus::Partial => {
+            (order.filled as u128 * BPS_DENOMINATOR as u128 / order.amount as u128) as u64
+        }
+        OrderStatus::Full => BPS_DENOMINATOR,
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetOrderReceiptPayload {
+    pub tx_hash: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetOrderReceiptResponse {
+    pub tx_hash: Hash,
+    pub status: OrderStatus,
+    pub filled: u64,
+    // rounded down, 0 if nothing was filled
+    pub avg_price: u64,
+    pub fees: u64,
+    pub deal_heights: Vec<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetDealsByRangePayload {
+    pub trade_id: Hash,
+    pub from_height: u64,
+    pub to_height: u64,
+    pub limit: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetDealsResponse {
+    pub deals: Vec<DealRecord>,
+}
+
+// the window is the last blocks_per_day blocks up to the current height
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Get24hStatsPayload {
+    pub trade_id: Hash,
+    pub blocks_per_day: u64,
+}
+
+// prices of the first, highest, lowest and last deal in the window, and the
+// amount and number of deals. all 0 when the window has no deals
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Get24hStatsResponse {
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+    pub volume: u64,
+    pub deals: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetUserFillsPayload {
+    pub user: Address,
+    pub limit: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq, Default)]
+pub struct HookEvent {
+    pub height: u64,
+    pub name: String,
+    pub data: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetHookEventsPayload {
+    pub height: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetHookEventsResponse {
+    pub events: Vec<HookEvent>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CircuitBreakerEvent {
+    pub trade_id: Hash,
+    pub last_price: u64,
+    pub deal_price: u64,
+}
+
+// open orders dropped from a trade that can no longer settle
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct InvalidateOrdersEvent {
+    pub trade_id: Hash,
+    pub reason: String,
+    pub orders: Vec<Hash>,
+}
+
+// a user's netted balance change that failed to apply at the end of a round
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetDeltaFailedEvent {
+    pub asset_id: Hash,
+    pub user: Address,
+    pub correlation_ids: Vec<String>,
+    pub error: String,
+}
+
+// a summary of one block's matching: the open orders of active trades that
+// were considered, how many of them got filled, how many orders expired, and
+// the deals made on each trade that had any
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MatchingRoundEvent {
+    pub height: u64,
+    pub considered: u64,
+    pub matched: u64,
+    pub expired: u64,
+    pub trade_fills: Vec<TradeDealCount>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetLockedBreakdownPayload {
+    pub user: Address,
+    pub asset_id: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetLockedBreakdownResponse {
+    pub asset_id: Hash,
+    pub locks: Vec<OrderLock>,
+    pub total: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AuditLocksPayload {
+    pub asset_id: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditLocksResponse {
+    pub asset_id: Hash,
+    pub expected: u64,
+    pub locked: u64,
+    pub balanced: bool,
+}
+
+// what open orders and swaps hold locked, per asset
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GetTvlResponse {
+    pub assets: Vec<AssetTvl>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AssetTvl {
+    pub asset_id: Hash,
+    pub locked: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct OrderLock {
+    pub tx_hash: Hash,
+    pub locked: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ReconcileLocksPayload {
+    pub user: Address,
+    pub asset_id: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ReconcileLocksResponse {
+    pub user: Address,
+    pub asset_id: Hash,
+    pub expected: u64,
+    pub unlocked: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetOpenInterestPayload {
+    pub trade_id: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GetOpenInterestResponse {
+    pub trade_id: Hash,
+    pub buy_amount: u64,
+    pub sell_amount: u64,
+    pub buy_notional: u64,
+    pub sell_notional: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetOrderBookPayload {
+    pub trade_id: Hash,
+    // max levels per side, 0 returns every level
+    pub depth: u64,
+}
+
+// open orders aggregated by price, best price first on both sides
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrderBook {
+    pub trade_id: Hash,
+    pub buys: Vec<PriceLevel>,
+    pub sells: Vec<PriceLevel>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub price: u64,
+    pub amount: u64,
+}
+
+// dex-wide settings, see GenesisPayload. price rules, tick and lot sizes
+// and min notionals are set per trade
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GetConfigResponse {
+    pub order_validity: u64,
+    pub pro_rata: bool,
+    pub fee_rate: u64,
+    pub referral_rate: u64,
+    pub maker_rebate: u64,
+    pub fee_account: Address,
+    pub circuit_breaker: u64,
+    pub admin: Address,
+    pub max_orders_per_block: u64,
+    pub order_book_snapshot: bool,
+    pub snapshot_depth: u64,
+    pub native_asset: Hash,
+    pub max_notional: u64,
+    pub net_settlement: bool,
+    pub rounding: Rounding,
+    pub expiry_grace: u64,
+    pub max_expiry: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetNativeAssetResponse {
+    pub asset_id: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetStatsResponse {
+    pub total_deals: u64,
+    pub trade_deals: Vec<TradeDealCount>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TradeDealCount {
+    pub trade_id: Hash,
+    pub deals: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct IsCrossedPayload {
+    pub trade_id: Hash,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct IsCrossedResponse {
+    pub crossed: bool,
+}
+
+// a book matching left crossed, e.g. halted by the circuit breaker or with
+// deals that failed to settle
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CrossedBook {
+    pub trade_id: Hash,
+    pub best_bid: u64,
+    pub best_ask: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct DetectCrossedBooksResponse {
+    pub books: Vec<CrossedBook>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct IsPriceImprovingResponse {
+    pub improving: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetLevelCountsPayload {
+    pub trade_id: Hash,
+}
+
+// distinct prices among the visible open orders of each side
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GetLevelCountsResponse {
+    pub bids: u64,
+    pub asks: u64,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
-pub struct GenesisPayload {
-    pub order_validity: u64,
+pub struct GetBookVersionPayload {
+    pub trade_id: Hash,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
-pub struct Trade {
-    pub id: Hash,
-    pub base_asset: Hash,
-    pub counter_party: Hash,
+// the height the trade's book last changed at, by an order placed, filled,
+// cancelled or expired. 0 if it never has
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetBookVersionResponse {
+    pub trade_id: Hash,
+    pub height: u64,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
-pub struct AddTradePayload {
-    pub base_asset: Hash,
-    pub counter_party: Hash,
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct EstimateMatchCostPayload {
+    pub trade_id: Hash,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default)]
-pub struct GetTradesResponse {
-    pub trades: Vec<Trade>,
+pub struct EstimateMatchCostResponse {
+    // orders that would take part in at least one deal
+    pub crossing_orders: u64,
+    pub settles: u64,
+    pub cycles: u64,
 }
 
-#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Default)]
-pub struct Order {
+// quote an order of `kind` for `quantity` against the opposite book
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetFillPricePayload {
     pub trade_id: Hash,
-    pub tx_hash: Hash,
     pub kind: OrderKind,
-    pub price: u64,
-    pub amount: u64,
-    pub height: u64,
-    pub user: Address,
-    pub expiry: u64,
-    pub status: OrderStatus,
-    pub deals: Vec<Deal>,
+    pub quantity: u64,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
-pub enum OrderKind {
-    Buy,
-    Sell,
+// what the visible book would fill and at what average price. sufficient is
+// false when it holds less than the quantity, filled is then all it holds
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GetFillPriceResponse {
+    pub filled: u64,
+    pub avg_price: u64,
+    pub sufficient: bool,
 }
 
-impl Default for OrderKind {
-    fn default() -> Self {
-        OrderKind::Buy
-    }
+// how far an order's average fill against the visible book, at the resting
+// prices, lands from the reference price, in basis points of it and counted
+// against the order only. sufficient is false when the book within the
+// order's price can't fill all of it, the estimate then covers what it can
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EstimateSlippageResponse {
+    pub avg_price: u64,
+    pub reference_price: u64,
+    pub slippage_bps: u64,
+    pub sufficient: bool,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
-pub enum OrderStatus {
-    Fresh,
-    Partial(u64),
-    Full,
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GetPriorityQueuePayload {
+    pub trade_id: Hash,
+    pub kind: OrderKind,
 }
 
-impl Default for OrderStatus {
-    fn default() -> Self {
-        OrderStatus::Fresh
-    }
+// one side of a trade's book, highest priority first
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GetPriorityQueueResponse {
+    pub orders: Vec<QueuedOrder>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
-pub struct Deal {
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueuedOrder {
+    pub tx_hash: Hash,
     pub price: u64,
     pub amount: u64,
+    pub remaining: u64,
+    pub height: u64,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
-pub enum DealStatus {
-    Dealing,
-    Dealt,
+pub struct GetReferencePricePayload {
+    pub trade_id: Hash,
 }
 
-impl Default for DealStatus {
+// where a reference price came from: the mid of a two-sided book, else the
+// last deal price, else there is none and the price is 0
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub enum PriceSource {
+    BookMid,
+    LastTrade,
+    Unavailable,
+}
+
+impl Default for PriceSource {
     fn default() -> Self {
-        Self::Dealing
+        PriceSource::Unavailable
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
-pub struct OrderPayload {
-    pub trade_id: Hash,
-    pub kind: OrderKind,
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GetReferencePriceResponse {
     pub price: u64,
-    pub amount: u64,
-    pub expiry: u64,
+    pub source: PriceSource,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
-pub struct GetOrderPayload {
-    pub tx_hash: Hash,
+pub struct GetUserTradesPayload {
+    pub user: Address,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default)]
-pub struct GetOrderResponse {
-    pub trade_id: Hash,
-    pub tx_hash: Hash,
-    pub kind: OrderKind,
-    pub price: u64,
-    pub amount: u64,
+pub struct GetUserTradesResponse {
+    pub trade_ids: Vec<Hash>,
+}
+
+// orders a user has placed at a height, reset once the height moves on
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrderCounter {
     pub height: u64,
-    pub user: Address,
-    pub expiry: u64,
-    pub order_status: OrderStatus,
-    pub deal_status: DealStatus,
-    pub deals: Vec<Deal>,
+    pub count: u64,
 }
 
-impl GetOrderResponse {
-    pub fn from_order(order: &Order, status: DealStatus) -> Self {
-        Self {
-            trade_id: order.trade_id.clone(),
-            tx_hash: order.tx_hash.clone(),
-            kind: order.kind.clone(),
-            price: order.price,
-            amount: order.amount,
-            height: order.height,
-            user: order.user.clone(),
-            expiry: order.expiry,
-            order_status: order.status.clone(),
-            deal_status: status,
-            deals: order.deals.clone(),
-        }
-    }
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CreateSwapPayload {
+    pub counterparty: Address,
+    pub offer_asset: Hash,
+    pub offer_amount: u64,
+    pub want_asset: Hash,
+    pub want_amount: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SwapPayload {
+    pub swap_id: Hash,
+}
+
+// an OTC escrow between two parties. the maker's offer stays locked until
+// the counterparty accepts it or the maker cancels it
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Swap {
+    pub id: Hash,
+    pub maker: Address,
+    pub counterparty: Address,
+    pub offer_asset: Hash,
+    pub offer_amount: u64,
+    pub want_asset: Hash,
+    pub want_amount: u64,
+}
+
+// what a block's matches add up to for one user's balance of an asset
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetDelta {
+    pub asset_id: Hash,
+    pub user: Address,
+    pub unlocked: u64,
+    pub net: i128,
+    // the deals netted into it
+    pub correlation_ids: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -141,29 +1277,86 @@ pub struct ModifyAssetPayload {
     pub value: u64,
 }
 
+// pays `to` out of what `from` has locked
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SettleAssetPayload {
+    pub asset_id: Hash,
+    pub from: Address,
+    pub to: Address,
+    pub value: u64,
+}
+
 impl rlp::Encodable for Trade {
     fn rlp_append(&self, s: &mut rlp::RlpStream) {
-        s.begin_list(3)
+        s.begin_list(16)
             .append(&self.id)
             .append(&self.base_asset)
             .append(&self.counter_party);
+        match self.price_rule {
+            PriceRule::Midpoint => s.append(&0u64),
+            PriceRule::MakerPrice => s.append(&1u64),
+            PriceRule::TakerPrice => s.append(&2u64),
+        };
+        s.append(&self.min_notional)
+            .append(&self.price_scale)
+            .append(&self.tick_size)
+            .append(&self.lot_size)
+            .append(&self.base_decimals)
+            .append(&self.counter_decimals)
+            .append(&self.allow_buy)
+            .append(&self.allow_sell)
+            .append(&self.validity)
+            .append(&self.min_fee)
+            .append(&self.max_fee)
+            .append(&u64::from(&self.rounding));
     }
 }
 
 impl rlp::Decodable for Trade {
     fn decode(r: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
-        if !r.is_list() && r.size() != 3 {
+        if !r.is_list() && r.size() != 16 {
             return Err(rlp::DecoderError::RlpIncorrectListLen);
         }
 
         let id = rlp::decode(r.at(0)?.as_raw())?;
         let base_asset = rlp::decode(r.at(1)?.as_raw())?;
         let counter_party = rlp::decode(r.at(2)?.as_raw())?;
+        let price_rule = match r.at(3)?.as_val::<u64>()? {
+            0 => PriceRule::Midpoint,
+            1 => PriceRule::MakerPrice,
+            2 => PriceRule::TakerPrice,
+            _ => unreachable!(),
+        };
+        let min_notional = r.at(4)?.as_val::<u64>()?;
+        let price_scale = r.at(5)?.as_val::<u64>()?;
+        let tick_size = r.at(6)?.as_val::<u64>()?;
+        let lot_size = r.at(7)?.as_val::<u64>()?;
+        let base_decimals = r.at(8)?.as_val::<u8>()?;
+        let counter_decimals = r.at(9)?.as_val::<u8>()?;
+        let allow_buy = r.at(10)?.as_val::<bool>()?;
+        let allow_sell = r.at(11)?.as_val::<bool>()?;
+        let validity = r.at(12)?.as_val::<u64>()?;
+        let min_fee = r.at(13)?.as_val::<u64>()?;
+        let max_fee = r.at(14)?.as_val::<u64>()?;
+        let rounding = Rounding::from(r.at(15)?.as_val::<u64>()?);
 
         Ok(Trade {
             id,
             base_asset,
             counter_party,
+            price_rule,
+            min_notional,
+            price_scale,
+            tick_size,
+            lot_size,
+            base_decimals,
+            counter_decimals,
+            allow_buy,
+            allow_sell,
+            validity,
+            min_fee,
+            max_fee,
+            rounding,
         })
     }
 }
@@ -180,7 +1373,7 @@ impl FixedCodec for Trade {
 
 impl rlp::Encodable for Order {
     fn rlp_append(&self, s: &mut rlp::RlpStream) {
-        s.begin_list(11)
+        s.begin_list(14)
             .append(&self.trade_id)
             .append(&self.tx_hash);
         match self.kind {
@@ -195,18 +1388,26 @@ impl rlp::Encodable for Order {
             .append(&self.expiry);
 
         match self.status {
-            OrderStatus::Fresh => s.append(&0u64).append(&0u64),
-            OrderStatus::Partial(v) => s.append(&1u64).append(&v),
-            OrderStatus::Full => s.append(&2u64).append(&0u64),
+            OrderStatus::Fresh => s.append(&0u64),
+            OrderStatus::Partial => s.append(&1u64),
+            OrderStatus::Full => s.append(&2u64),
         };
+        s.append(&self.filled);
 
         s.append_list(&self.deals);
+
+        match &self.referrer {
+            Some(referrer) => s.begin_list(1).append(referrer),
+            None => s.begin_list(0),
+        };
+
+        s.append(&self.fees).append(&self.hidden);
     }
 }
 
 impl rlp::Decodable for Order {
     fn decode(r: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
-        if !r.is_list() && r.size() != 11 {
+        if !r.is_list() && r.size() != 14 {
             return Err(rlp::DecoderError::RlpIncorrectListLen);
         }
 
@@ -225,13 +1426,23 @@ impl rlp::Decodable for Order {
         let expiry = r.at(7)?.as_val::<u64>()?;
         let status = match r.at(8)?.as_val::<u64>()? {
             0 => OrderStatus::Fresh,
-            1 => OrderStatus::Partial(r.at(9)?.as_val::<u64>()?),
+            1 => OrderStatus::Partial,
             2 => OrderStatus::Full,
             _ => unreachable!(),
         };
+        let filled = r.at(9)?.as_val::<u64>()?;
 
         let deals: Vec<Deal> = rlp::decode_list(r.at(10)?.as_raw());
 
+        let referrer_rlp = r.at(11)?;
+        let referrer = if referrer_rlp.item_count()? == 0 {
+            None
+        } else {
+            Some(rlp::decode(referrer_rlp.at(0)?.as_raw())?)
+        };
+        let fees = r.at(12)?.as_val::<u64>()?;
+        let hidden = r.at(13)?.as_val::<bool>()?;
+
         Ok(Order {
             trade_id,
             tx_hash,
@@ -242,7 +1453,11 @@ impl rlp::Decodable for Order {
             user,
             expiry,
             status,
+            filled,
             deals,
+            referrer,
+            fees,
+            hidden,
         })
     }
 }
@@ -259,20 +1474,28 @@ impl FixedCodec for Order {
 
 impl rlp::Encodable for Deal {
     fn rlp_append(&self, s: &mut rlp::RlpStream) {
-        s.begin_list(2).append(&self.price).append(&self.amount);
+        s.begin_list(3)
+            .append(&self.price)
+            .append(&self.amount)
+            .append(&self.height);
     }
 }
 
 impl rlp::Decodable for Deal {
     fn decode(r: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
-        if !r.is_list() && r.size() != 2 {
+        if !r.is_list() && r.size() != 3 {
             return Err(rlp::DecoderError::RlpIncorrectListLen);
         }
 
         let price = r.at(0)?.as_val::<u64>()?;
         let amount = r.at(1)?.as_val::<u64>()?;
+        let height = r.at(2)?.as_val::<u64>()?;
 
-        Ok(Deal { price, amount })
+        Ok(Deal {
+            price,
+            amount,
+            height,
+        })
     }
 }
 
@@ -286,6 +1509,199 @@ impl FixedCodec for Deal {
     }
 }
 
+impl rlp::Encodable for DealRecord {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        s.begin_list(7)
+            .append(&self.trade_id)
+            .append(&self.price)
+            .append(&self.amount)
+            .append(&self.height)
+            .append(&self.maker)
+            .append(&self.taker)
+            .append(&self.correlation_id);
+    }
+}
+
+impl rlp::Decodable for DealRecord {
+    fn decode(r: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        if !r.is_list() && r.size() != 7 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+
+        let trade_id = rlp::decode(r.at(0)?.as_raw())?;
+        let price = r.at(1)?.as_val::<u64>()?;
+        let amount = r.at(2)?.as_val::<u64>()?;
+        let height = r.at(3)?.as_val::<u64>()?;
+        let maker = rlp::decode(r.at(4)?.as_raw())?;
+        let taker = rlp::decode(r.at(5)?.as_raw())?;
+        let correlation_id = r.at(6)?.as_val::<String>()?;
+
+        Ok(DealRecord {
+            trade_id,
+            price,
+            amount,
+            height,
+            maker,
+            taker,
+            correlation_id,
+        })
+    }
+}
+
+impl FixedCodec for DealRecord {
+    fn encode_fixed(&self) -> ProtocolResult<Bytes> {
+        Ok(Bytes::from(rlp::encode(self)))
+    }
+
+    fn decode_fixed(bytes: Bytes) -> ProtocolResult<Self> {
+        Ok(rlp::decode(bytes.as_ref()).map_err(FixedCodecError::from)?)
+    }
+}
+
+impl rlp::Encodable for OrderIndex {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        s.begin_list(2);
+        s.append_list(&self.buys);
+        s.append_list(&self.sells);
+    }
+}
+
+impl rlp::Decodable for OrderIndex {
+    fn decode(r: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        if !r.is_list() && r.size() != 2 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+
+        let buys: Vec<Hash> = rlp::decode_list(r.at(0)?.as_raw());
+        let sells: Vec<Hash> = rlp::decode_list(r.at(1)?.as_raw());
+
+        Ok(OrderIndex { buys, sells })
+    }
+}
+
+impl FixedCodec for OrderIndex {
+    fn encode_fixed(&self) -> ProtocolResult<Bytes> {
+        Ok(Bytes::from(rlp::encode(self)))
+    }
+
+    fn decode_fixed(bytes: Bytes) -> ProtocolResult<Self> {
+        Ok(rlp::decode(bytes.as_ref()).map_err(FixedCodecError::from)?)
+    }
+}
+
+impl rlp::Encodable for HookEvent {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        s.begin_list(3)
+            .append(&self.height)
+            .append(&self.name)
+            .append(&self.data);
+    }
+}
+
+impl rlp::Decodable for HookEvent {
+    fn decode(r: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        if !r.is_list() && r.size() != 3 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+
+        let height = r.at(0)?.as_val::<u64>()?;
+        let name = r.at(1)?.as_val::<String>()?;
+        let data = r.at(2)?.as_val::<String>()?;
+
+        Ok(HookEvent { height, name, data })
+    }
+}
+
+impl FixedCodec for HookEvent {
+    fn encode_fixed(&self) -> ProtocolResult<Bytes> {
+        Ok(Bytes::from(rlp::encode(self)))
+    }
+
+    fn decode_fixed(bytes: Bytes) -> ProtocolResult<Self> {
+        Ok(rlp::decode(bytes.as_ref()).map_err(FixedCodecError::from)?)
+    }
+}
+
+impl rlp::Encodable for OrderCounter {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        s.begin_list(2).append(&self.height).append(&self.count);
+    }
+}
+
+impl rlp::Decodable for OrderCounter {
+    fn decode(r: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        if !r.is_list() && r.size() != 2 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+
+        let height = r.at(0)?.as_val::<u64>()?;
+        let count = r.at(1)?.as_val::<u64>()?;
+
+        Ok(OrderCounter { height, count })
+    }
+}
+
+impl FixedCodec for OrderCounter {
+    fn encode_fixed(&self) -> ProtocolResult<Bytes> {
+        Ok(Bytes::from(rlp::encode(self)))
+    }
+
+    fn decode_fixed(bytes: Bytes) -> ProtocolResult<Self> {
+        Ok(rlp::decode(bytes.as_ref()).map_err(FixedCodecError::from)?)
+    }
+}
+
+impl rlp::Encodable for Swap {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        s.begin_list(7)
+            .append(&self.id)
+            .append(&self.maker)
+            .append(&self.counterparty)
+            .append(&self.offer_asset)
+            .append(&self.offer_amount)
+            .append(&self.want_asset)
+            .append(&self.want_amount);
+    }
+}
+
+impl rlp::Decodable for Swap {
+    fn decode(r: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        if !r.is_list() && r.size() != 7 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+
+        let id = rlp::decode(r.at(0)?.as_raw())?;
+        let maker = rlp::decode(r.at(1)?.as_raw())?;
+        let counterparty = rlp::decode(r.at(2)?.as_raw())?;
+        let offer_asset = rlp::decode(r.at(3)?.as_raw())?;
+        let offer_amount = r.at(4)?.as_val::<u64>()?;
+        let want_asset = rlp::decode(r.at(5)?.as_raw())?;
+        let want_amount = r.at(6)?.as_val::<u64>()?;
+
+        Ok(Swap {
+            id,
+            maker,
+            counterparty,
+            offer_asset,
+            offer_amount,
+            want_asset,
+            want_amount,
+        })
+    }
+}
+
+impl FixedCodec for Swap {
+    fn encode_fixed(&self) -> ProtocolResult<Bytes> {
+        Ok(Bytes::from(rlp::encode(self)))
+    }
+
+    fn decode_fixed(bytes: Bytes) -> ProtocolResult<Self> {
+        Ok(rlp::decode(bytes.as_ref()).map_err(FixedCodecError::from)?)
+    }
+}
+
+// at the same price visible orders come before hidden ones, then the earlier
+// order comes first
 impl PartialOrd for Order {
     fn partial_cmp(&self, other: &Order) -> Option<Ordering> {
         match (self.kind.clone(), other.kind.clone()) {
@@ -295,7 +1711,7 @@ impl PartialOrd for Order {
                 } else if self.price < other.price {
                     Some(Ordering::Greater)
                 } else {
-                    Some(self.height.cmp(&other.height))
+                    Some(self.time_priority(other))
                 }
             }
             (OrderKind::Buy, OrderKind::Buy) => {
@@ -304,7 +1720,7 @@ impl PartialOrd for Order {
                 } else if self.price < other.price {
                     Some(Ordering::Less)
                 } else {
-                    Some(self.height.cmp(&other.height))
+                    Some(self.time_priority(other))
                 }
             }
             _ => None,